@@ -0,0 +1,376 @@
+use alloy_primitives::{Address, B256, Bytes, U256};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+/// Everything that can go wrong talking to a node.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request didn't reach the node or the response couldn't be read.
+    Http(reqwest::Error),
+    /// The node answered with an error body. `code` says why, on the errors a client can act on
+    /// (e.g. `fee_too_low`, `nonce_too_low` for a rejected transaction).
+    Rpc {
+        status: u16,
+        message: String,
+        code: Option<String>,
+    },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "request failed: {}", e),
+            ClientError::Rpc {
+                status, message, ..
+            } => {
+                write!(f, "node error ({}): {}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+/// Account state as reported by the node.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Account {
+    pub address: Address,
+    pub nonce: u64,
+    pub balance: U256,
+}
+
+/// A block with the hashes of its transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Block {
+    pub number: u64,
+    pub hash: B256,
+    pub parent_hash: B256,
+    pub timestamp: u64,
+    pub coinbase: Address,
+    pub chain_id: u64,
+    pub transactions: Vec<B256>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Log {
+    pub address: Address,
+    pub topics: Vec<B256>,
+    pub data: Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Receipt {
+    pub tx_hash: B256,
+    pub block_hash: B256,
+    pub block_number: u64,
+    pub gas_used: u64,
+    pub effective_gas_price: U256,
+    pub logs: Vec<Log>,
+}
+
+#[derive(Deserialize)]
+struct SendTransactionResponse {
+    tx_hash: B256,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    code: Option<String>,
+}
+
+/// Typed async client for the pETHit RPC.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl Client {
+    /// `url` is the node base address, e.g. `http://127.0.0.1:3000`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Submits an RLP encoded signed transaction. Returns its hash.
+    pub async fn send_raw_tx(&self, raw_tx: &[u8]) -> Result<B256, ClientError> {
+        let body = serde_json::json!({ "raw_tx": format!("0x{}", hex::encode(raw_tx)) });
+        let response: SendTransactionResponse = self.post("/send_tx", &body).await?;
+        Ok(response.tx_hash)
+    }
+
+    pub async fn get_account(&self, address: Address) -> Result<Account, ClientError> {
+        let body = serde_json::json!({ "address": address.to_checksum(None) });
+        self.post("/get_account", &body).await
+    }
+
+    pub async fn get_balance(&self, address: Address) -> Result<U256, ClientError> {
+        Ok(self.get_account(address).await?.balance)
+    }
+
+    pub async fn get_nonce(&self, address: Address) -> Result<u64, ClientError> {
+        Ok(self.get_account(address).await?.nonce)
+    }
+
+    /// `None` if the node doesn't know the block.
+    pub async fn get_block_by_hash(&self, hash: B256) -> Result<Option<Block>, ClientError> {
+        let body = serde_json::json!({ "hash": hash.to_string() });
+        not_found_as_none(self.post("/get_block?full=false", &body).await)
+    }
+
+    /// The tip of the node's chain.
+    pub async fn head(&self) -> Result<Block, ClientError> {
+        let response = self
+            .http
+            .get(format!("{}/head?full=false", self.url))
+            .send()
+            .await?;
+        parse(response).await
+    }
+
+    /// `None` if the transaction hasn't been mined.
+    pub async fn get_receipt(&self, tx_hash: B256) -> Result<Option<Receipt>, ClientError> {
+        let body = serde_json::json!({ "hash": tx_hash.to_string() });
+        not_found_as_none(self.post("/get_receipt", &body).await)
+    }
+
+    /// Credits `to` with `amount` wei through the faucet. Only nodes in dev mode serve it.
+    /// Returns the account after the credit.
+    pub async fn faucet(
+        &self,
+        to: Address,
+        amount: U256,
+        api_key: Option<&str>,
+    ) -> Result<Account, ClientError> {
+        let body = serde_json::json!({
+            "to": to.to_checksum(None),
+            "amount": amount.to_string(),
+            "api_key": api_key,
+        });
+        self.post("/faucet", &body).await
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> Result<T, ClientError> {
+        let response = self
+            .http
+            .post(format!("{}{}", self.url, path))
+            .json(body)
+            .send()
+            .await?;
+        parse(response).await
+    }
+}
+
+// Turns error bodies into ClientError::Rpc, parses the rest as T
+async fn parse<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, ClientError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response.json().await?);
+    }
+
+    let text = response.text().await?;
+    let (message, code) = serde_json::from_str::<ErrorResponse>(&text)
+        .map(|body| (body.error, body.code))
+        .unwrap_or((text, None));
+    Err(ClientError::Rpc {
+        status: status.as_u16(),
+        message,
+        code,
+    })
+}
+
+fn not_found_as_none<T>(result: Result<T, ClientError>) -> Result<Option<T>, ClientError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(ClientError::Rpc { status, .. }) if status == StatusCode::NOT_FOUND.as_u16() => {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        http::StatusCode as MockStatus,
+        routing::{get, post},
+    };
+    use serde_json::{Value, json};
+
+    const ADDRESS: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    const HASH: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+    const PARENT: &str = "0x2222222222222222222222222222222222222222222222222222222222222222";
+
+    // Serves `app` on a random local port and returns a client for it
+    async fn mock_node(app: Router) -> Client {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Client::new(url)
+    }
+
+    fn block_json() -> Value {
+        json!({
+            "number": 7,
+            "hash": HASH,
+            "parent_hash": PARENT,
+            "timestamp": 1700000000,
+            "coinbase": ADDRESS,
+            "chain_id": 1,
+            "transactions": [HASH]
+        })
+    }
+
+    fn account_app() -> Router {
+        Router::new().route(
+            "/get_account",
+            post(|Json(body): Json<Value>| async move {
+                Json(json!({ "address": body["address"], "nonce": 3, "balance": "1000" }))
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send_raw_tx() {
+        let app = Router::new().route(
+            "/send_tx",
+            post(|Json(body): Json<Value>| async move {
+                assert_eq!(body["raw_tx"], "0xc0ffee");
+                Json(json!({ "tx_hash": HASH }))
+            }),
+        );
+        let client = mock_node(app).await;
+
+        let hash = client.send_raw_tx(&[0xc0, 0xff, 0xee]).await.unwrap();
+
+        assert_eq!(hash, HASH.parse::<B256>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_send_raw_tx_error() {
+        let app = Router::new().route(
+            "/send_tx",
+            post(|| async {
+                (
+                    MockStatus::BAD_REQUEST,
+                    Json(json!({ "error": "Insufficient funds", "code": "insufficient_funds" })),
+                )
+            }),
+        );
+        let client = mock_node(app).await;
+
+        match client.send_raw_tx(&[0xc0]).await {
+            Err(ClientError::Rpc {
+                status,
+                message,
+                code,
+            }) => {
+                assert_eq!(status, 400);
+                assert_eq!(message, "Insufficient funds");
+                assert_eq!(code.as_deref(), Some("insufficient_funds"));
+            }
+            other => panic!("expected an RPC error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_balance() {
+        let client = mock_node(account_app()).await;
+
+        let balance = client.get_balance(ADDRESS.parse().unwrap()).await.unwrap();
+
+        assert_eq!(balance, U256::from(1000));
+    }
+
+    #[tokio::test]
+    async fn test_get_nonce() {
+        let client = mock_node(account_app()).await;
+
+        let nonce = client.get_nonce(ADDRESS.parse().unwrap()).await.unwrap();
+
+        assert_eq!(nonce, 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_block_by_hash() {
+        let app = Router::new().route(
+            "/get_block",
+            post(|Json(body): Json<Value>| async move {
+                if body["hash"] == HASH {
+                    (MockStatus::OK, Json(block_json()))
+                } else {
+                    (MockStatus::NOT_FOUND, Json(json!({ "error": "not found" })))
+                }
+            }),
+        );
+        let client = mock_node(app).await;
+
+        let block = client
+            .get_block_by_hash(HASH.parse().unwrap())
+            .await
+            .unwrap();
+        let block = block.unwrap();
+        assert_eq!(block.number, 7);
+        assert_eq!(block.parent_hash, PARENT.parse::<B256>().unwrap());
+        assert_eq!(block.transactions, vec![HASH.parse::<B256>().unwrap()]);
+
+        let missing = client.get_block_by_hash(B256::ZERO).await.unwrap();
+        assert_eq!(missing, None);
+    }
+
+    #[tokio::test]
+    async fn test_head() {
+        let app = Router::new().route("/head", get(|| async { Json(block_json()) }));
+        let client = mock_node(app).await;
+
+        let head = client.head().await.unwrap();
+
+        assert_eq!(head.number, 7);
+        assert_eq!(head.hash, HASH.parse::<B256>().unwrap());
+        assert_eq!(head.chain_id, 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_receipt() {
+        let app = Router::new().route(
+            "/get_receipt",
+            post(|| async {
+                Json(json!({
+                    "tx_hash": HASH,
+                    "block_hash": PARENT,
+                    "block_number": 2,
+                    "gas_used": 21000,
+                    "effective_gas_price": "1",
+                    "logs": [{ "address": ADDRESS, "topics": [HASH], "data": "0x01" }]
+                }))
+            }),
+        );
+        let client = mock_node(app).await;
+
+        let receipt = client.get_receipt(HASH.parse().unwrap()).await.unwrap();
+        let receipt = receipt.unwrap();
+
+        assert_eq!(receipt.block_number, 2);
+        assert_eq!(receipt.gas_used, 21000);
+        assert_eq!(receipt.effective_gas_price, U256::from(1));
+        assert_eq!(receipt.logs[0].address, ADDRESS.parse::<Address>().unwrap());
+        assert_eq!(receipt.logs[0].data, Bytes::from(vec![1u8]));
+    }
+}
@@ -1,35 +1,240 @@
-use alloy_primitives::{B256, keccak256};
-use pethit_execution::{ExecutionEngine, SignedTransaction};
-use pethit_storage::SharedStorage;
-use pethit_txpool::SharedTxPool;
-use std::sync::{Arc, Mutex};
+use alloy_primitives::{Address, B256, Bloom, BloomInput, U256, b256};
+use alloy_rlp::{BufMut, Decodable, Encodable, Header, RlpDecodable, RlpEncodable};
+pub use pethit_execution::logs_bloom;
+use pethit_execution::{
+    BlockEnv, ExecError, ExecutionEngine, Hasher, Keccak, Log, Receipt, SignedTransaction,
+};
+use pethit_storage::{
+    Account, BlockWrites, SharedStorage, SimpleStorage, StorageBackend, StorageError,
+    merkle_branch, merkle_levels, verify_merkle_branch,
+};
+pub use pethit_txpool::{Clock, MockClock, SystemClock};
+use pethit_txpool::{PooledTx, SharedTxPool};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
 use std::{thread, time::Duration};
+use tokio::sync::broadcast;
 
-#[derive(Debug, Clone)]
+// How many recent blocks the average block time is computed over
+const STATS_WINDOW: usize = 100;
+
+/// Wei minted to the coinbase of every mined block (2 ETH).
+pub const BLOCK_REWARD: U256 = U256::from_limbs([2_000_000_000_000_000_000, 0, 0, 0]);
+
+/// Default cap on the RLP size of a mined block (1 MiB).
+pub const DEFAULT_MAX_BLOCK_BYTES: usize = 1 << 20;
+
+/// Time between two heartbeats of the miner by default.
+pub const DEFAULT_BLOCK_INTERVAL: Duration = Duration::from_secs(5);
+
+// How often an instant-seal miner waiting for transactions checks for a shutdown
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many new blocks a subscriber can fall behind by before it misses some, by default.
+pub const DEFAULT_BLOCK_CHANNEL_CAPACITY: usize = 64;
+
+/// How far ahead of the local clock a block timestamp can be by default.
+pub const DEFAULT_MAX_FUTURE_DRIFT: Duration = Duration::from_secs(15);
+
+/// How many pooled transactions the miner looks at per block by default.
+pub const DEFAULT_MAX_CANDIDATES_CONSIDERED: usize = 10_000;
+
+/// How many blocks a reorg can replace by default.
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 64;
+
+/// Chain id used when the genesis doesn't set one.
+pub const DEFAULT_CHAIN_ID: u64 = 1337;
+
+/// `tx_root` of a block without transactions: keccak256 of the empty RLP list (0xc0).
+pub const EMPTY_TX_ROOT: B256 =
+    b256!("0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347");
+
+/// Gas a block can use when the genesis doesn't set a limit.
+pub const DEFAULT_BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+// EIP-1559: blocks aim at half their gas limit, the base fee moves by at most 1/8 per block
+const ELASTICITY_MULTIPLIER: u64 = 2;
+const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+
+/// What a chain starts from. Nodes must share it to agree on the genesis hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenesisConfig {
+    pub chain_id: u64,
+    pub difficulty: u64,
+    // Every block inherits it
+    pub gas_limit: u64,
+    // Base fee of the genesis block, the following ones adjust from it
+    pub base_fee: U256,
+    // Unix time (seconds) recorded in the genesis block
+    pub timestamp: u64,
+    // Free-form bytes of the genesis block, e.g. a chain name or a commit
+    pub extra_data: Vec<u8>,
+    // Initial balances. A BTreeMap, so the config has a single canonical order
+    pub alloc: BTreeMap<Address, U256>,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        Self {
+            chain_id: DEFAULT_CHAIN_ID,
+            difficulty: 1,
+            gas_limit: DEFAULT_BLOCK_GAS_LIMIT,
+            base_fee: U256::ZERO,
+            timestamp: 0,
+            extra_data: Vec::new(),
+            alloc: BTreeMap::new(),
+        }
+    }
+}
+
+impl GenesisConfig {
+    /// Funds the initial accounts.
+    pub fn apply<B: StorageBackend>(&self, storage: &SharedStorage<B>) -> Result<(), StorageError> {
+        for (address, balance) in &self.alloc {
+            let account = Account {
+                nonce: 0,
+                balance: *balance,
+            };
+            storage.set_account(*address, account)?;
+        }
+        Ok(())
+    }
+
+    /// The genesis block. Its hash only depends on the config (the timestamp is not the sealing time).
+    pub fn genesis_block(&self) -> SealedBlock {
+        let mut state = SimpleStorage::new();
+        for (address, balance) in &self.alloc {
+            let account = Account {
+                nonce: 0,
+                balance: *balance,
+            };
+            // In memory, any write goes
+            let _ = state.set_account(*address, account);
+        }
+
+        Block {
+            id: 0,
+            transactions: Vec::new(),
+            parent_hash: B256::ZERO,
+            timestamp: self.timestamp,
+            coinbase: Address::ZERO,
+            chain_id: self.chain_id,
+            difficulty: self.difficulty,
+            state_root: state.state_root(),
+            tx_root: tx_root(&[]),
+            logs_bloom: Bloom::ZERO,
+            gas_limit: self.gas_limit,
+            gas_used: 0,
+            base_fee: self.base_fee,
+            extra_data: self.extra_data.clone(),
+        }
+        .seal()
+    }
+}
+
+#[derive(Debug, Clone, RlpEncodable, RlpDecodable)]
 pub struct Block {
     pub id: u64,
     pub transactions: Vec<SignedTransaction>,
     pub parent_hash: B256,
+    // Unix time (seconds) the block was sealed at. Set by the config for genesis.
+    pub timestamp: u64,
+    // Receives the block reward and the fees of the block transactions
+    pub coinbase: Address,
+    // Carried over from the genesis config
+    pub chain_id: u64,
+    pub difficulty: u64,
+    // Root of the state after the block transactions (and rewards) were applied
+    pub state_root: B256,
+    // Merkle root of the transaction hashes (see `tx_root`)
+    pub tx_root: B256,
+    // 2048-bit bloom of the addresses and topics of every log in the block
+    pub logs_bloom: Bloom,
+    // Carried over from the genesis config
+    pub gas_limit: u64,
+    // Gas used by the block transactions
+    pub gas_used: u64,
+    // Wei per unit of gas every transaction of the block burns (EIP-1559)
+    pub base_fee: U256,
+    // Set by the genesis config, empty in mined blocks
+    pub extra_data: Vec<u8>,
+}
+
+// Empty, so a default block with no transactions already carries the canonical empty root
+impl Default for Block {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            transactions: Vec::new(),
+            parent_hash: B256::ZERO,
+            timestamp: 0,
+            coinbase: Address::ZERO,
+            chain_id: 0,
+            difficulty: 0,
+            state_root: B256::ZERO,
+            tx_root: EMPTY_TX_ROOT,
+            logs_bloom: Bloom::ZERO,
+            gas_limit: 0,
+            gas_used: 0,
+            base_fee: U256::ZERO,
+            extra_data: Vec::new(),
+        }
+    }
 }
 
 impl Block {
+    /// `false` means no log of the block was emitted by `address`.
+    /// `true` only means it may have been (blooms have false positives).
+    pub fn may_contain_log(&self, address: Address) -> bool {
+        self.logs_bloom
+            .contains_input(BloomInput::Raw(address.as_slice()))
+    }
+
     pub fn hash(&self) -> B256 {
+        self.hash_with::<Keccak>()
+    }
+
+    /// Same as `hash`, with `H` instead of keccak256 for the header.
+    /// The transactions still go in by their own (keccak) hash.
+    pub fn hash_with<H: Hasher>(&self) -> B256 {
         let mut data = Vec::new();
         data.extend_from_slice(&self.id.to_be_bytes());
         data.extend_from_slice(self.parent_hash.as_slice());
+        data.extend_from_slice(&self.timestamp.to_be_bytes());
+        data.extend_from_slice(self.coinbase.as_slice());
+        data.extend_from_slice(&self.chain_id.to_be_bytes());
+        data.extend_from_slice(&self.difficulty.to_be_bytes());
+        data.extend_from_slice(self.state_root.as_slice());
+        data.extend_from_slice(self.tx_root.as_slice());
+        data.extend_from_slice(self.logs_bloom.as_slice());
+        data.extend_from_slice(&self.gas_limit.to_be_bytes());
+        data.extend_from_slice(&self.gas_used.to_be_bytes());
+        data.extend_from_slice(&self.base_fee.to_be_bytes::<32>());
+        // Length first, it's the only field that isn't fixed-size
+        data.extend_from_slice(&(self.extra_data.len() as u64).to_be_bytes());
+        data.extend_from_slice(&self.extra_data);
 
         for sig_tx in &self.transactions {
             // Add the transaction hash.
             data.extend_from_slice(sig_tx.hash().as_slice());
         }
 
-        keccak256(data)
+        H::hash(&data)
     }
 
     pub fn seal(self) -> SealedBlock {
-        let hashed_block = self.hash();
+        self.seal_with::<Keccak>()
+    }
+
+    pub fn seal_with<H: Hasher>(self) -> SealedBlock {
+        let hashed_block = self.hash_with::<H>();
 
         SealedBlock {
+            total_difficulty: U256::from(self.difficulty),
             block: self,
             k_hash: hashed_block,
         }
@@ -41,6 +246,51 @@ impl Block {
 pub struct SealedBlock {
     pub block: Block,
     pub k_hash: B256,
+    /// Difficulty summed from genesis through this block, the work fork choice weighs. Not
+    /// part of the hash or the encoding: the chain sets it when the block is added (until
+    /// then it counts only the block's own difficulty).
+    pub total_difficulty: U256,
+}
+
+// RLP list [block, k_hash]
+impl Encodable for SealedBlock {
+    fn encode(&self, out: &mut dyn BufMut) {
+        Header {
+            list: true,
+            payload_length: self.block.length() + self.k_hash.length(),
+        }
+        .encode(out);
+        self.block.encode(out);
+        self.k_hash.encode(out);
+    }
+
+    fn length(&self) -> usize {
+        let payload_length = self.block.length() + self.k_hash.length();
+        Header {
+            list: true,
+            payload_length,
+        }
+        .length()
+            + payload_length
+    }
+}
+
+// The carried hash is never trusted: it must match the one recomputed from the block
+impl Decodable for SealedBlock {
+    fn decode(buf: &mut &[u8]) -> alloy_rlp::Result<Self> {
+        let header = Header::decode(buf)?;
+        if !header.list {
+            return Err(alloy_rlp::Error::Custom("SealedBlock must be an RLP list"));
+        }
+        let block = Block::decode(buf)?;
+        let k_hash = B256::decode(buf)?;
+
+        let sealed = block.seal();
+        if sealed.k_hash != k_hash {
+            return Err(alloy_rlp::Error::Custom("SealedBlock hash mismatch"));
+        }
+        Ok(sealed)
+    }
 }
 
 impl std::ops::Deref for SealedBlock {
@@ -50,9 +300,471 @@ impl std::ops::Deref for SealedBlock {
     }
 }
 
+/// Reasons the chain refuses a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+    /// The block doesn't extend the current tip (e.g. another miner extended it first).
+    StaleParent,
+    /// The block can't be decoded or doesn't follow the chain rules (e.g. on import).
+    InvalidBlock(String),
+    /// A reorg would replace more blocks than `max_reorg_depth` allows.
+    ReorgTooDeep { depth: u64, max: u64 },
+    /// The block sits at a checkpoint height but isn't the trusted block.
+    CheckpointMismatch {
+        number: u64,
+        expected: B256,
+        got: B256,
+    },
+    /// An import brought a different block at a height the chain already has.
+    Conflict {
+        number: u64,
+        existing: B256,
+        got: B256,
+    },
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::StaleParent => write!(f, "block parent is not the current tip"),
+            ChainError::InvalidBlock(reason) => write!(f, "invalid block: {}", reason),
+            ChainError::ReorgTooDeep { depth, max } => {
+                write!(
+                    f,
+                    "reorg of {} blocks exceeds the maximum of {}",
+                    depth, max
+                )
+            }
+            ChainError::CheckpointMismatch {
+                number,
+                expected,
+                got,
+            } => write!(
+                f,
+                "block {} is {}, but the checkpoint expects {}",
+                number, got, expected
+            ),
+            ChainError::Conflict {
+                number,
+                existing,
+                got,
+            } => write!(
+                f,
+                "block {} is already {}, can't import {}",
+                number, existing, got
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+/// Where a mined transaction lives in the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxLocation {
+    pub block_hash: B256,
+    pub block_number: u64,
+    // Position of the transaction inside the block
+    pub index: usize,
+}
+
+/// Aggregates over the whole chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChainStats {
+    pub height: u64,
+    pub total_transactions: u64,
+    /// Average seconds between the recent blocks (0 until there are two mined blocks).
+    pub avg_block_time_secs: f64,
+}
+
+/// Progress of the local chain against the best tip reported by peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncStatus {
+    pub syncing: bool,
+    pub current_block: u64,
+    pub highest_block: u64,
+}
+
+/// A node this one is connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub address: String,
+    /// Latest tip the peer announced.
+    pub height: u64,
+    pub connected_for: Duration,
+}
+
+/// Sent to the subscribers of `SharedChain::subscribe_reorgs` after a reorg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgEvent {
+    /// Last block both branches share.
+    pub common_ancestor: B256,
+    /// Hashes of the blocks that left the chain, lowest first.
+    pub removed: Vec<B256>,
+    /// Hashes of the blocks that replaced them, lowest first.
+    pub added: Vec<B256>,
+}
+
+/// Which headers a chain accepts, on top of the checks every chain makes (linking to the
+/// parent, timestamp bounds, checkpoints). Set with `SharedChain::with_rules`.
+pub trait ConsensusRules: fmt::Debug + Send + Sync {
+    fn validate_header(&self, block: &SealedBlock, parent: &SealedBlock) -> Result<(), ChainError>;
+}
+
+/// Accepts any header. The default, for dev chains sealed on an interval or instantly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DevRules;
+
+impl ConsensusRules for DevRules {
+    fn validate_header(&self, _: &SealedBlock, _: &SealedBlock) -> Result<(), ChainError> {
+        Ok(())
+    }
+}
+
+/// Proof of work: the difficulty is the parent's, and the block hash read as a number is at
+/// most `U256::MAX / difficulty` (so `difficulty` hashes are tried on average).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PowRules;
+
+impl ConsensusRules for PowRules {
+    fn validate_header(&self, block: &SealedBlock, parent: &SealedBlock) -> Result<(), ChainError> {
+        if block.difficulty != parent.difficulty {
+            return Err(ChainError::InvalidBlock(
+                "difficulty differs from the parent".to_string(),
+            ));
+        }
+        let target = U256::MAX
+            .checked_div(U256::from(block.difficulty))
+            .ok_or_else(|| ChainError::InvalidBlock("difficulty is zero".to_string()))?;
+        if U256::from_be_bytes(block.k_hash.0) > target {
+            return Err(ChainError::InvalidBlock(
+                "hash doesn't meet the difficulty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Result of a block lookup on a chain that may have pruned old blocks.
+#[derive(Debug, Clone)]
+pub enum BlockLookup {
+    Found(Box<SealedBlock>),
+    /// The block is part of the chain, but only its hash is kept.
+    Pruned {
+        number: u64,
+        hash: B256,
+    },
+    NotFound,
+}
+
+/// This doesn't know about threads, just data.
+#[derive(Debug)]
+struct Chain {
+    // Genesis, then the blocks after `pruned_to`, in order
+    blocks: Vec<SealedBlock>,
+    // Key: signed tx hash, Value: where it was mined
+    tx_index: HashMap<B256, TxLocation>,
+    // Key: block hash, Value: receipts in the same order as the block transactions
+    receipts: HashMap<B256, Vec<Receipt>>,
+    // Highest tip announced by a peer (None until some peer reports)
+    best_peer_height: Option<u64>,
+    // Key: peer address, Value: announced tip and when the connection started
+    peers: BTreeMap<String, (u64, Instant)>,
+    // Blocks deeper than this from the tip are final
+    max_reorg_depth: u64,
+    // Keep at most this many blocks besides genesis (None keeps them all)
+    keep_blocks: Option<u64>,
+    // Key: hash of a discarded block, Value: its number
+    pruned: HashMap<B256, u64>,
+    // Highest discarded block number (0: nothing discarded)
+    pruned_to: u64,
+    // Transactions of discarded blocks, so the stats keep counting them
+    pruned_transactions: u64,
+    reorg_subscribers: Vec<Sender<ReorgEvent>>,
+    // Every block added to the chain. Bounded: a subscriber that falls behind gets `Lagged`
+    // instead of the channel growing or the chain waiting on it
+    new_blocks: broadcast::Sender<SealedBlock>,
+    // Key: block number, Value: the only hash accepted at that height
+    checkpoints: BTreeMap<u64, B256>,
+    // Blocks timestamped further than this past the local clock are refused
+    max_future_drift: Duration,
+    rules: Arc<dyn ConsensusRules>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Chain {
+    fn new(genesis: SealedBlock) -> Self {
+        Self {
+            blocks: vec![genesis],
+            tx_index: HashMap::new(),
+            receipts: HashMap::new(),
+            best_peer_height: None,
+            peers: BTreeMap::new(),
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            keep_blocks: None,
+            pruned: HashMap::new(),
+            pruned_to: 0,
+            pruned_transactions: 0,
+            reorg_subscribers: Vec::new(),
+            new_blocks: broadcast::channel(DEFAULT_BLOCK_CHANNEL_CAPACITY).0,
+            checkpoints: BTreeMap::new(),
+            max_future_drift: DEFAULT_MAX_FUTURE_DRIFT,
+            rules: Arc::new(DevRules),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Everything `add_block` checks before extending the tip with `block`
+    fn check(&self, block: &SealedBlock) -> Result<(), ChainError> {
+        let tip = self.blocks.last().unwrap();
+        if block.parent_hash != tip.k_hash || block.id != tip.id + 1 {
+            return Err(ChainError::StaleParent);
+        }
+        self.check_timestamp(block, tip.timestamp)?;
+        self.check_checkpoint(block)?;
+        self.rules.validate_header(block, tip)
+    }
+
+    // Not before the parent, and not too far past the local clock
+    fn check_timestamp(
+        &self,
+        block: &SealedBlock,
+        parent_timestamp: u64,
+    ) -> Result<(), ChainError> {
+        if block.timestamp < parent_timestamp {
+            return Err(ChainError::InvalidBlock(
+                "timestamp is before the parent's".to_string(),
+            ));
+        }
+        let now = self.clock.unix_now();
+        if block.timestamp > now.saturating_add(self.max_future_drift.as_secs()) {
+            return Err(ChainError::InvalidBlock(
+                "timestamp is too far in the future".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_checkpoint(&self, block: &SealedBlock) -> Result<(), ChainError> {
+        match self.checkpoints.get(&block.id) {
+            Some(expected) if *expected != block.k_hash => Err(ChainError::CheckpointMismatch {
+                number: block.id,
+                expected: *expected,
+                got: block.k_hash,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // Position of block `number` in `blocks`, if it's still held
+    fn index_of(&self, number: u64) -> Option<usize> {
+        let index = match number {
+            0 => 0,
+            n if n <= self.pruned_to => return None,
+            n => (n - self.pruned_to) as usize,
+        };
+        (index < self.blocks.len()).then_some(index)
+    }
+
+    // Discards the oldest blocks (never genesis) beyond `keep_blocks`
+    fn prune(&mut self) {
+        let Some(keep) = self.keep_blocks else {
+            return;
+        };
+        let excess = (self.blocks.len() - 1).saturating_sub(keep as usize);
+        for block in self.blocks.drain(1..1 + excess) {
+            for sig_tx in &block.transactions {
+                self.tx_index.remove(&sig_tx.hash());
+            }
+            self.receipts.remove(&block.k_hash);
+            self.pruned.insert(block.k_hash, block.id);
+            self.pruned_to = block.id;
+            self.pruned_transactions += block.transactions.len() as u64;
+        }
+    }
+
+    fn lookup(&self, number: u64) -> BlockLookup {
+        if let Some(index) = self.index_of(number) {
+            return BlockLookup::Found(Box::new(self.blocks[index].clone()));
+        }
+        self.pruned
+            .iter()
+            .find(|(_, pruned)| **pruned == number)
+            .map_or(BlockLookup::NotFound, |(hash, _)| BlockLookup::Pruned {
+                number,
+                hash: *hash,
+            })
+    }
+
+    // Held blocks numbered `from..=to`
+    fn range(&self, from: u64, to: u64) -> impl Iterator<Item = &SealedBlock> {
+        self.blocks
+            .iter()
+            .filter(move |block| (from..=to).contains(&block.id))
+    }
+
+    fn push(&mut self, mut block: SealedBlock, receipts: Vec<Receipt>) {
+        let parent = self.blocks.last().unwrap();
+        block.total_difficulty = parent.total_difficulty + U256::from(block.difficulty);
+        for (index, sig_tx) in block.transactions.iter().enumerate() {
+            let location = TxLocation {
+                block_hash: block.k_hash,
+                block_number: block.id,
+                index,
+            };
+            self.tx_index.insert(sig_tx.hash(), location);
+        }
+        self.receipts.insert(block.k_hash, receipts);
+        // Fails only when nobody is subscribed
+        let _ = self.new_blocks.send(block.clone());
+        self.blocks.push(block);
+        self.prune();
+    }
+
+    // Drops every block above `height`, with its index entries and receipts
+    fn truncate(&mut self, height: u64) {
+        let Some(index) = self.index_of(height) else {
+            return;
+        };
+        for block in self.blocks.drain(index + 1..) {
+            for sig_tx in &block.transactions {
+                self.tx_index.remove(&sig_tx.hash());
+            }
+            self.receipts.remove(&block.k_hash);
+        }
+    }
+
+    fn reorg(&mut self, branch: Vec<(SealedBlock, Vec<Receipt>)>) -> Result<(), ChainError> {
+        let Some((first, _)) = branch.first() else {
+            return Ok(());
+        };
+        let fork = self
+            .blocks
+            .iter()
+            .find(|block| block.k_hash == first.parent_hash)
+            .ok_or_else(|| ChainError::InvalidBlock("unknown fork point".to_string()))?;
+        let fork_height = fork.id;
+
+        let tip = self.blocks.last().unwrap().id;
+        let depth = tip - fork_height;
+        if depth > self.max_reorg_depth {
+            return Err(ChainError::ReorgTooDeep {
+                depth,
+                max: self.max_reorg_depth,
+            });
+        }
+        // Most work wins, then the longest (blocks without difficulty add no work)
+        let current = self.blocks.last().unwrap().total_difficulty;
+        let work = branch
+            .iter()
+            .fold(fork.total_difficulty, |work, (block, _)| {
+                work + U256::from(block.difficulty)
+            });
+        let height = fork_height + branch.len() as u64;
+        if (work, height) <= (current, tip) {
+            return Err(ChainError::InvalidBlock(
+                "branch doesn't have more work than the current chain".to_string(),
+            ));
+        }
+
+        // The branch must link up block by block
+        let mut parent = fork;
+        for (block, _) in &branch {
+            if block.parent_hash != parent.k_hash || block.id != parent.id + 1 {
+                return Err(ChainError::InvalidBlock(
+                    "branch blocks are not linked".to_string(),
+                ));
+            }
+            self.check_timestamp(block, parent.timestamp)?;
+            self.check_checkpoint(block)?;
+            self.rules.validate_header(block, parent)?;
+            parent = block;
+        }
+
+        let event = ReorgEvent {
+            common_ancestor: fork.k_hash,
+            removed: self
+                .range(fork_height + 1, tip)
+                .map(|block| block.k_hash)
+                .collect(),
+            added: branch.iter().map(|(block, _)| block.k_hash).collect(),
+        };
+        self.truncate(fork_height);
+        for (block, receipts) in branch {
+            self.push(block, receipts);
+        }
+        // Subscribers that went away are dropped
+        self.reorg_subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        Ok(())
+    }
+
+    fn get_transaction(&self, hash: B256) -> Option<(SignedTransaction, TxLocation)> {
+        let location = *self.tx_index.get(&hash)?;
+        let block = &self.blocks[self.index_of(location.block_number)?];
+        let sig_tx = block.transactions.get(location.index)?.clone();
+        Some((sig_tx, location))
+    }
+
+    fn inclusion_proof(&self, tx_hash: B256) -> Option<InclusionProof> {
+        let location = *self.tx_index.get(&tx_hash)?;
+        let block = &self.blocks[self.index_of(location.block_number)?];
+        let leaves = block.transactions.iter().map(|tx| tx.hash()).collect();
+
+        let levels = merkle_levels(leaves);
+        Some(InclusionProof {
+            tx_hash,
+            block_hash: block.k_hash,
+            index: location.index,
+            leaf_count: block.transactions.len(),
+            siblings: merkle_branch(&levels, location.index),
+        })
+    }
+
+    fn get_receipt(&self, hash: B256) -> Option<(Receipt, TxLocation)> {
+        let location = *self.tx_index.get(&hash)?;
+        let receipt = self
+            .receipts
+            .get(&location.block_hash)?
+            .get(location.index)?;
+        Some((receipt.clone(), location))
+    }
+
+    fn stats(&self) -> ChainStats {
+        let height = self.blocks.last().unwrap().id;
+
+        // Genesis is left out, its timestamp comes from the config
+        let mined = &self.blocks[1..];
+        let recent = &mined[mined.len().saturating_sub(STATS_WINDOW)..];
+        let avg_block_time_secs = match (recent.first(), recent.last()) {
+            (Some(first), Some(last)) if recent.len() > 1 => {
+                (last.timestamp - first.timestamp) as f64 / (recent.len() - 1) as f64
+            }
+            _ => 0.0,
+        };
+
+        ChainStats {
+            height,
+            total_transactions: self.tx_index.len() as u64 + self.pruned_transactions,
+            avg_block_time_secs,
+        }
+    }
+
+    fn get_logs(&self, from: u64, to: u64, address: Option<Address>) -> Vec<Log> {
+        // Pruned blocks have no receipts left, they are skipped
+        self.range(from, to)
+            .filter_map(|block| self.receipts.get(&block.k_hash))
+            .flatten()
+            .flat_map(|receipt| &receipt.logs)
+            .filter(|log| address.is_none_or(|address| log.address == address))
+            .cloned()
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SharedChain {
-    inner: Arc<Mutex<Vec<SealedBlock>>>,
+    inner: Arc<Mutex<Chain>>,
 }
 
 impl Default for SharedChain {
@@ -63,99 +775,654 @@ impl Default for SharedChain {
 
 impl SharedChain {
     pub fn new() -> Self {
-        // Initialize with genesis
-        let genesis = Block {
-            id: 0,
-            transactions: Vec::new(),
-            parent_hash: B256::ZERO,
-        }
-        .seal();
+        Self::from_genesis(&GenesisConfig::default())
+    }
+
+    // Initialize with the genesis of `config`
+    pub fn from_genesis(config: &GenesisConfig) -> Self {
         Self {
-            inner: Arc::new(Mutex::new(vec![genesis])),
+            inner: Arc::new(Mutex::new(Chain::new(config.genesis_block()))),
         }
     }
 
+    /// Keep only the last `keep` blocks (plus genesis) in memory.
+    /// Older blocks are discarded along with their transactions and receipts, only their hashes stay.
+    pub fn with_prune_blocks(self, keep: u64) -> Self {
+        let mut chain = lock(&self.inner);
+        chain.keep_blocks = Some(keep);
+        chain.prune();
+        drop(chain);
+        self
+    }
+
+    /// Only accept the given hash at each given height, whether the block comes from the miner,
+    /// an import, sync or a reorg.
+    pub fn with_checkpoints(self, checkpoints: BTreeMap<u64, B256>) -> Self {
+        lock(&self.inner).checkpoints = checkpoints;
+        self
+    }
+
+    /// Refuse blocks timestamped more than `drift` ahead of the local clock.
+    pub fn with_max_future_drift(self, drift: Duration) -> Self {
+        lock(&self.inner).max_future_drift = drift;
+        self
+    }
+
+    /// Reads the time from `clock` (the system's by default), for timestamps and peer uptimes.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        lock(&self.inner).clock = Arc::new(clock);
+        self
+    }
+
+    /// Accept only the headers `rules` accepts, from the miner, an import, sync or a reorg
+    /// (`DevRules` by default).
+    pub fn with_rules(self, rules: impl ConsensusRules + 'static) -> Self {
+        lock(&self.inner).rules = Arc::new(rules);
+        self
+    }
+
+    /// Refuse reorgs that replace more than `depth` blocks.
+    pub fn with_max_reorg_depth(self, depth: u64) -> Self {
+        lock(&self.inner).max_reorg_depth = depth;
+        self
+    }
+
+    // Replaces the blocks after the fork point with a heavier branch (blocks with their receipts).
+    // Only the chain is switched: the caller is in charge of rebuilding the state.
+    pub fn reorg(&self, branch: Vec<(SealedBlock, Vec<Receipt>)>) -> Result<(), ChainError> {
+        let mut chain = lock(&self.inner);
+        chain.reorg(branch)
+    }
+
+    /// How many blocks `subscribe_blocks` receivers can fall behind by. Set it before subscribing,
+    /// earlier receivers stop getting blocks.
+    pub fn with_block_channel_capacity(self, capacity: usize) -> Self {
+        lock(&self.inner).new_blocks = broadcast::channel(capacity).0;
+        self
+    }
+
+    /// A receiver of every block added from now on (mined, imported or from a reorg).
+    /// A receiver more than the channel capacity behind gets `RecvError::Lagged` with the
+    /// number of blocks it missed, then the oldest block still buffered.
+    pub fn subscribe_blocks(&self) -> broadcast::Receiver<SealedBlock> {
+        lock(&self.inner).new_blocks.subscribe()
+    }
+
+    /// A channel receiving a `ReorgEvent` for every reorg from now on (for RPC subscriptions, indexers).
+    pub fn subscribe_reorgs(&self) -> Receiver<ReorgEvent> {
+        let (sender, receiver) = mpsc::channel();
+        lock(&self.inner).reorg_subscribers.push(sender);
+        receiver
+    }
+
+    // Peers with a different genesis hash are on another chain (for the P2P handshake)
+    pub fn genesis_hash(&self) -> B256 {
+        let chain = lock(&self.inner);
+        chain.blocks[0].k_hash
+    }
+
+    /// Difficulty summed from genesis through the tip.
+    pub fn total_difficulty(&self) -> U256 {
+        lock(&self.inner).blocks.last().unwrap().total_difficulty
+    }
+
     // Helper to get the last block (for the Miner)
     pub fn last_block(&self) -> SealedBlock {
-        let chain = self.inner.lock().unwrap();
-        chain.last().cloned().unwrap()
+        let chain = lock(&self.inner);
+        chain.blocks.last().cloned().unwrap()
+    }
+
+    // Helper to add a block and the receipts of its transactions (for the Miner)
+    // Compare-and-set: the block must extend the tip as seen under the lock.
+    pub fn add_block(&self, block: SealedBlock, receipts: Vec<Receipt>) -> Result<(), ChainError> {
+        let mut chain = lock(&self.inner);
+        chain.check(&block)?;
+        chain.push(block, receipts);
+        Ok(())
+    }
+
+    /// Whether `add_block` would take `block` on top of the current tip, without adding it.
+    pub fn check_block(&self, block: &SealedBlock) -> Result<(), ChainError> {
+        lock(&self.inner).check(block)
+    }
+
+    // RLP of the blocks `from..=to`, back to back. Ranges past the tip are cut at the tip,
+    // pruned blocks are left out
+    pub fn export(&self, from: u64, to: u64) -> Vec<u8> {
+        let chain = lock(&self.inner);
+        let mut out = Vec::new();
+        for sealed in chain.range(from, to) {
+            sealed.block.encode(&mut out);
+        }
+        out
     }
 
-    // Helper to add a block (for the Miner)
-    pub fn add_block(&self, block: SealedBlock) {
-        let mut chain = self.inner.lock().unwrap();
-        chain.push(block);
+    /// Held blocks numbered `from..=to`, in ascending order. Pruned blocks are left out.
+    pub fn get_blocks(&self, from: u64, to: u64) -> Vec<SealedBlock> {
+        let chain = lock(&self.inner);
+        chain.range(from, to).cloned().collect()
     }
 
     // Helper to find by hash (for the RPC)
     pub fn get_block_by_hash(&self, hash: B256) -> Option<SealedBlock> {
-        let chain = self.inner.lock().unwrap();
+        let chain = lock(&self.inner);
         // Simple linear search is fine for now
-        chain.iter().find(|b| b.k_hash == hash).cloned()
+        chain.blocks.iter().find(|b| b.k_hash == hash).cloned()
+    }
+
+    // Like `get_block_by_hash`, telling pruned blocks apart from unknown ones
+    pub fn lookup_block_by_hash(&self, hash: B256) -> BlockLookup {
+        let chain = lock(&self.inner);
+        if let Some(&number) = chain.pruned.get(&hash) {
+            return BlockLookup::Pruned { number, hash };
+        }
+        match chain.blocks.iter().find(|b| b.k_hash == hash) {
+            Some(block) => BlockLookup::Found(Box::new(block.clone())),
+            None => BlockLookup::NotFound,
+        }
+    }
+
+    pub fn lookup_block_by_number(&self, number: u64) -> BlockLookup {
+        let chain = lock(&self.inner);
+        chain.lookup(number)
+    }
+
+    // Helper to find the receipt of a mined transaction (for the RPC)
+    pub fn get_receipt(&self, tx_hash: B256) -> Option<(Receipt, TxLocation)> {
+        let chain = lock(&self.inner);
+        chain.get_receipt(tx_hash)
+    }
+
+    // Logs emitted in blocks `from..=to`, optionally only the ones of `address` (for the RPC)
+    pub fn get_logs(&self, from: u64, to: u64, address: Option<Address>) -> Vec<Log> {
+        let chain = lock(&self.inner);
+        chain.get_logs(from, to, address)
+    }
+
+    // Aggregates for dashboards (for the RPC)
+    pub fn stats(&self) -> ChainStats {
+        let chain = lock(&self.inner);
+        chain.stats()
+    }
+
+    // Records the tip a peer announced (for the sync)
+    pub fn report_peer_height(&self, height: u64) {
+        let mut chain = lock(&self.inner);
+        chain.best_peer_height = chain.best_peer_height.max(Some(height));
+    }
+
+    /// Records a connected peer and the tip it announced. A known peer keeps its connection time.
+    pub fn add_peer(&self, address: &str, height: u64) {
+        let mut chain = lock(&self.inner);
+        let connected_at = chain
+            .peers
+            .get(address)
+            .map_or_else(|| chain.clock.now(), |(_, connected_at)| *connected_at);
+        chain
+            .peers
+            .insert(address.to_string(), (height, connected_at));
+        chain.best_peer_height = chain.best_peer_height.max(Some(height));
+    }
+
+    pub fn remove_peer(&self, address: &str) {
+        lock(&self.inner).peers.remove(address);
+    }
+
+    /// The connected peers, by address.
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let chain = lock(&self.inner);
+        let now = chain.clock.now();
+        chain
+            .peers
+            .iter()
+            .map(|(address, (height, connected_at))| PeerInfo {
+                address: address.clone(),
+                height: *height,
+                connected_for: now.saturating_duration_since(*connected_at),
+            })
+            .collect()
+    }
+
+    // We are syncing while some peer is ahead of our tip (for the RPC)
+    pub fn sync_status(&self) -> SyncStatus {
+        let chain = lock(&self.inner);
+        let current_block = chain.blocks.last().unwrap().id;
+        let highest_block = chain.best_peer_height.unwrap_or(0).max(current_block);
+
+        SyncStatus {
+            syncing: highest_block > current_block,
+            current_block,
+            highest_block,
+        }
+    }
+
+    // Helper to find a mined transaction by its hash (for the RPC)
+    pub fn get_transaction_by_hash(&self, hash: B256) -> Option<(SignedTransaction, TxLocation)> {
+        let chain = lock(&self.inner);
+        chain.get_transaction(hash)
+    }
+
+    /// Proof that a mined transaction is part of its block, checked with `verify_inclusion`
+    /// against the block's `tx_root` (for light clients).
+    pub fn inclusion_proof(&self, tx_hash: B256) -> Option<InclusionProof> {
+        let chain = lock(&self.inner);
+        chain.inclusion_proof(tx_hash)
+    }
+}
+
+/// Connects two nodes: checks they share a genesis, then each records the other as a peer
+/// along with its tip. Nodes on different chains are refused.
+pub fn handshake(
+    local: &SharedChain,
+    local_address: &str,
+    remote: &SharedChain,
+    remote_address: &str,
+) -> Result<(), String> {
+    let (local_genesis, remote_genesis) = (local.genesis_hash(), remote.genesis_hash());
+    if local_genesis != remote_genesis {
+        return Err(format!(
+            "Peer {} is on another chain: genesis {} instead of {}",
+            remote_address, remote_genesis, local_genesis
+        ));
+    }
+    local.add_peer(remote_address, remote.last_block().id);
+    remote.add_peer(local_address, local.last_block().id);
+    Ok(())
+}
+
+/// When the miner seals blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SealMode {
+    /// On every heartbeat (`block_interval`).
+    #[default]
+    Interval,
+    /// As soon as transactions arrive, with no sleeping. Nothing arrives, no blocks (dev mode).
+    Instant,
+}
+
+/// Why the miner produced no block. The block's transactions stay pooled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MineError {
+    /// The storage refused the block's writes (e.g. `StorageError::Full`).
+    Storage(StorageError),
+    /// The chain refused the block (e.g. its hash doesn't meet the `PowRules` difficulty).
+    Chain(ChainError),
+    /// The block can't be executed (e.g. the coinbase balance would pass U256::MAX).
+    Execution(ExecError),
+}
+
+impl fmt::Display for MineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MineError::Storage(e) => write!(f, "the storage refused the block: {}", e),
+            MineError::Chain(e) => write!(f, "the chain refused the block: {}", e),
+            MineError::Execution(e) => write!(f, "the block can't be executed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MineError {}
+
+impl From<StorageError> for MineError {
+    fn from(e: StorageError) -> Self {
+        MineError::Storage(e)
+    }
+}
+
+impl From<ChainError> for MineError {
+    fn from(e: ChainError) -> Self {
+        MineError::Chain(e)
+    }
+}
+
+/// Order in which the miner considers pooled transactions for a block: `Less` goes first.
+/// Whatever the order, a sender's transactions are always packed by increasing nonce.
+pub type TxPriority = fn(&PooledTx, &PooledTx) -> Ordering;
+
+/// First come, first packed.
+pub fn fifo(a: &PooledTx, b: &PooledTx) -> Ordering {
+    a.received_at.cmp(&b.received_at)
+}
+
+/// Highest gas price first, oldest first among equals.
+pub fn highest_fee(a: &PooledTx, b: &PooledTx) -> Ordering {
+    b.tx.transaction()
+        .gas_price
+        .cmp(&a.tx.transaction().gas_price)
+        .then_with(|| fifo(a, b))
+}
+
+// Sorts `pooled` by `priority`, then puts each sender's transactions back in nonce order
+// within the slots that sender got, so none of them lands before a lower nonce.
+// Only the first `max_candidates` by priority are kept, found without sorting the rest. A
+// sender's transactions after one that was cut are cut too, they couldn't execute.
+fn prioritize(
+    mut pooled: Vec<PooledTx>,
+    priority: TxPriority,
+    max_candidates: usize,
+) -> Vec<SignedTransaction> {
+    if pooled.len() > max_candidates {
+        pooled.select_nth_unstable_by(max_candidates, priority);
+        let mut first_cut: HashMap<Address, u64> = HashMap::new();
+        for cut in pooled.drain(max_candidates..) {
+            let nonce = cut.tx.transaction().nonce;
+            first_cut
+                .entry(cut.sender)
+                .and_modify(|first| *first = (*first).min(nonce))
+                .or_insert(nonce);
+        }
+        pooled.retain(|tx| {
+            first_cut
+                .get(&tx.sender)
+                .is_none_or(|&first| tx.tx.transaction().nonce < first)
+        });
+    }
+    pooled.sort_by(priority);
+    let mut slots: HashMap<Address, Vec<usize>> = HashMap::new();
+    for (slot, tx) in pooled.iter().enumerate() {
+        slots.entry(tx.sender).or_default().push(slot);
+    }
+    let mut ordered: Vec<Option<SignedTransaction>> = vec![None; pooled.len()];
+    for slots in slots.values() {
+        let mut txs: Vec<&SignedTransaction> = slots.iter().map(|&slot| &pooled[slot].tx).collect();
+        txs.sort_by_key(|tx| tx.transaction().nonce);
+        for (&slot, tx) in slots.iter().zip(txs) {
+            ordered[slot] = Some(tx.clone());
+        }
     }
+    ordered.into_iter().flatten().collect()
 }
 
-pub struct Miner {
+/// A block the miner added to the chain.
+#[derive(Debug, Clone)]
+pub struct MinedBlock {
+    pub block: SealedBlock,
+    /// Packed transactions the block left out because they failed, with why.
+    pub skipped: Vec<(SignedTransaction, ExecError)>,
+}
+
+pub struct Miner<B: StorageBackend = SimpleStorage> {
     txpool: SharedTxPool,
-    storage: SharedStorage,
+    storage: SharedStorage<B>,
     chain: SharedChain,
-    block_num: u64,
+    // Seal a block every heartbeat even if the pool is empty
+    mine_empty_blocks: bool,
+    // Where rewards and fees go
+    coinbase: Address,
+    // Cap on the RLP size of a block. Transactions that don't fit stay pooled
+    max_block_bytes: usize,
+    // Sleep between heartbeats
+    block_interval: Duration,
+    seal_mode: SealMode,
+    priority: TxPriority,
+    // Only the top transactions by priority are packed from, however large the pool is
+    max_candidates_considered: usize,
+    // Where block timestamps come from
+    clock: Arc<dyn Clock>,
+    // `start_mining` returns once it receives (None: runs until a block can't be mined)
+    shutdown: Option<Receiver<()>>,
+    // Called by `start_mining` with each block it mines
+    on_mined: Option<fn(&MinedBlock)>,
 }
 
-impl Miner {
+impl<B: StorageBackend> Miner<B> {
     /// The Miner is initialized with existing handles to the Pool and Storage.
-    pub fn new(txpool: SharedTxPool, storage: SharedStorage, chain: SharedChain) -> Self {
+    pub fn new(txpool: SharedTxPool, storage: SharedStorage<B>, chain: SharedChain) -> Self {
         Self {
             txpool,
             storage,
             chain,
-            block_num: 0,
+            mine_empty_blocks: true,
+            coinbase: Address::ZERO,
+            max_block_bytes: DEFAULT_MAX_BLOCK_BYTES,
+            block_interval: DEFAULT_BLOCK_INTERVAL,
+            seal_mode: SealMode::Interval,
+            priority: fifo,
+            max_candidates_considered: DEFAULT_MAX_CANDIDATES_CONSIDERED,
+            clock: Arc::new(SystemClock),
+            shutdown: None,
+            on_mined: None,
         }
     }
 
-    /// The "Heartbeat" loop.
-    /// 'mut self' because we update 'block_num' and 'blockchain'.
-    pub fn start_mining(mut self) {
-        println!("Miner initialized and starting heartbeat...");
+    /// Sets the time between two heartbeats of `start_mining`.
+    pub fn with_block_interval(mut self, block_interval: Duration) -> Self {
+        self.block_interval = block_interval;
+        self
+    }
 
-        loop {
-            self.mine_block();
-            thread::sleep(Duration::from_secs(5));
-        }
-    }
-
-    fn mine_block(&mut self) {
-        // Pull transactions from the shared pool
-        let all_txs = self.txpool.get_all_transactions();
-        // Successful transactions
-        let mut valid_txs = Vec::new();
-
-        // If there are txs, update the STATE
-        if !all_txs.is_empty() {
-            // .update() pattern is used to lock the DB once and run transactions through the Engine.
-            self.storage.update(|raw_db| {
-                for tx in all_txs {
-                    match ExecutionEngine::execute(raw_db, &tx) {
-                        Ok(_) => {
-                            valid_txs.push(tx);
-                        }
-                        Err(e) => {
-                            println!("Skipping invalid tx: {}", e);
-                        }
-                    };
+    /// Sets the cap on the RLP size of mined blocks.
+    pub fn with_max_block_bytes(mut self, max_block_bytes: usize) -> Self {
+        self.max_block_bytes = max_block_bytes;
+        self
+    }
+
+    /// Sets the address credited with the block reward and the collected fees.
+    pub fn with_coinbase(mut self, coinbase: Address) -> Self {
+        self.coinbase = coinbase;
+        self
+    }
+
+    /// When disabled, heartbeats with an empty pool don't seal a block.
+    /// Enabled by default to keep steady block times.
+    pub fn with_mine_empty_blocks(mut self, mine_empty_blocks: bool) -> Self {
+        self.mine_empty_blocks = mine_empty_blocks;
+        self
+    }
+
+    pub fn with_seal_mode(mut self, seal_mode: SealMode) -> Self {
+        self.seal_mode = seal_mode;
+        self
+    }
+
+    /// Timestamps blocks with `clock` (the system's by default).
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets the order transactions are packed in (`fifo` by default, see `highest_fee`).
+    pub fn with_priority(mut self, priority: TxPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets how many pooled transactions, best by priority, a block is packed from. Bounds the
+    /// time spent per block whatever the pool size; the rest waits for the next blocks.
+    pub fn with_max_candidates_considered(mut self, max_candidates_considered: usize) -> Self {
+        self.max_candidates_considered = max_candidates_considered;
+        self
+    }
+
+    /// Makes `start_mining` return once `shutdown` receives or its sender is dropped, between
+    /// two blocks: the block being mined, if any, is fully in the chain and the state first.
+    pub fn with_shutdown(mut self, shutdown: Receiver<()>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Hands each block `start_mining` mines to `on_mined`, e.g. to log the skipped
+    /// transactions (`try_mine_once` callers get them in its result).
+    pub fn with_on_mined(mut self, on_mined: fn(&MinedBlock)) -> Self {
+        self.on_mined = Some(on_mined);
+        self
+    }
+
+    /// The "Heartbeat" loop.
+    /// Several miners can share the same pool, storage and chain.
+    /// Returns (production stops) once a block can't be mined, e.g. because the storage is full,
+    /// or on shutdown (see `with_shutdown`).
+    pub fn start_mining(mut self) {
+        println!("Miner initialized and starting heartbeat...");
+
+        let result = match self.seal_mode {
+            SealMode::Interval => loop {
+                if let Err(e) = self.tick() {
+                    break Err(e);
                 }
-            });
+                if self.wait_for_shutdown(self.block_interval) {
+                    break Ok(());
+                }
+            },
+            SealMode::Instant => {
+                let mut seen = 0;
+                loop {
+                    // Everything that arrived while mining goes in the next block. Woken up
+                    // regularly to check for a shutdown
+                    seen = self
+                        .txpool
+                        .wait_for_transactions(seen, SHUTDOWN_POLL_INTERVAL);
+                    if self.wait_for_shutdown(Duration::ZERO) {
+                        break Ok(());
+                    }
+                    if self.txpool.is_empty() {
+                        continue;
+                    }
+                    if let Err(e) = self.mine_and_report() {
+                        break Err(e);
+                    }
+                }
+            }
+        };
+        match result {
+            Ok(()) => println!("Mining stopped"),
+            Err(e) => eprintln!("Mining stopped, {}", e),
         }
+    }
 
-        // Create the Block
-        self.block_num += 1;
-        let parent_block = self.chain.last_block();
-        let sealed_block = Block {
-            id: self.block_num,
-            transactions: valid_txs,
-            parent_hash: parent_block.k_hash,
+    // Sleeps for `timeout`, cut short by a shutdown. Returns whether there was one
+    fn wait_for_shutdown(&self, timeout: Duration) -> bool {
+        match &self.shutdown {
+            Some(shutdown) => !matches!(
+                shutdown.recv_timeout(timeout),
+                Err(mpsc::RecvTimeoutError::Timeout)
+            ),
+            None => {
+                thread::sleep(timeout);
+                false
+            }
         }
-        .seal();
+    }
+
+    // A single heartbeat: mine unless there is nothing to mine and empty blocks are off.
+    fn tick(&mut self) -> Result<(), MineError> {
+        if !self.mine_empty_blocks && self.txpool.is_empty() {
+            return Ok(());
+        }
+        self.mine_and_report()
+    }
+
+    // Mines a block and passes it to `on_mined`
+    fn mine_and_report(&mut self) -> Result<(), MineError> {
+        let mined = self.try_mine_once()?;
+        if let Some(on_mined) = self.on_mined {
+            on_mined(&mined);
+        }
+        Ok(())
+    }
+
+    /// Like `try_mine_once`, for storage that can't fail (e.g. in memory) and chains that take
+    /// any block (`DevRules`). Panics if the block is refused.
+    pub fn mine_once(&mut self) -> SealedBlock {
+        match self.try_mine_once() {
+            Ok(mined) => mined.block,
+            Err(e) => panic!("cannot mine the block: {}", e),
+        }
+    }
+
+    /// Mines a single block on top of the tip right away (no sleep) and returns it, with the
+    /// packed transactions that failed. Those that can never execute (bad signature or code, a
+    /// nonce already used) are dropped from the pool, the others stay pooled.
+    /// This is all the block production there is, `start_mining` just calls it on an interval.
+    ///
+    /// If the chain refuses the block (e.g. under `PowRules`, as the miner doesn't grind for the
+    /// difficulty) or the storage refuses its writes (e.g. `StorageError::Full`), no block is
+    /// added, the state is left as it was and the transactions stay pooled.
+    pub fn try_mine_once(&mut self) -> Result<MinedBlock, MineError> {
+        loop {
+            match self.mine_on_tip() {
+                // The tip moved while the block was built: its base fee and execution depend on
+                // the parent, so start over on the new tip
+                Err(MineError::Chain(ChainError::StaleParent)) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    // One attempt of `try_mine_once`, on the tip as of now
+    fn mine_on_tip(&mut self) -> Result<MinedBlock, MineError> {
+        let parent_block = self.chain.last_block();
+        // The base fee only depends on the parent, so it's known before packing
+        let base_fee = next_base_fee(&parent_block);
+        // Expired transactions are dropped rather than mined
+        self.txpool.prune_expired();
+        // Pull transactions from the shared pool, as many as fit in a block
+        let candidates = prioritize(
+            self.txpool.get_pooled_transactions(),
+            self.priority,
+            self.max_candidates_considered,
+        );
+        let packed_txs = self.pack(candidates, base_fee);
+
+        // .update_block() locks the DB once to run transactions through the Engine, without
+        // writing, then applies their writes (journaled, if the storage has a journal) and only
+        // then adds the block to the chain. The lock is held throughout, so miners sharing the
+        // storage can't interleave their state changes. If the writes or the chain fail, the
+        // writes are undone. A tip that moved since `parent_block` comes back as `StaleParent`.
+        let (sealed_block, skipped) = self.storage.update_block(
+            |raw_db| {
+                let env = BlockEnv {
+                    coinbase: self.coinbase,
+                    base_fee,
+                    reward: BLOCK_REWARD,
+                };
+                let result = match ExecutionEngine::simulate_block(raw_db, packed_txs, &env) {
+                    Ok(result) => result,
+                    Err(ExecError::Storage(e)) => return Err(MineError::Storage(e)),
+                    // Only fails otherwise if the coinbase balance would pass U256::MAX
+                    Err(e) => return Err(MineError::Execution(e)),
+                };
+
+                // Create the Block on top of the tip the base fee was computed from
+                let sealed_block = Block {
+                    id: parent_block.id + 1,
+                    transactions: result.transactions.clone(),
+                    parent_hash: parent_block.k_hash,
+                    // Never behind the parent, even if the clock goes backwards
+                    timestamp: self.clock.unix_now().max(parent_block.timestamp),
+                    coinbase: self.coinbase,
+                    chain_id: parent_block.chain_id,
+                    difficulty: parent_block.difficulty,
+                    state_root: result.state_root,
+                    tx_root: tx_root(&result.transactions),
+                    logs_bloom: result.logs_bloom,
+                    gas_limit: parent_block.gas_limit,
+                    gas_used: result.gas_used,
+                    base_fee,
+                    extra_data: Vec::new(),
+                }
+                .seal();
+
+                // Checked before anything is written, so a refused block costs no undo
+                self.chain
+                    .check_block(&sealed_block)
+                    .map_err(MineError::Chain)?;
+                let writes = BlockWrites {
+                    block: sealed_block.id,
+                    entries: result.writes,
+                };
+                Ok(((sealed_block, result.receipts, result.skipped), writes))
+            },
+            |(sealed_block, receipts, skipped)| {
+                self.chain.add_block(sealed_block.clone(), receipts)?;
+                Ok((sealed_block, skipped))
+            },
+        )?;
+
+        // Keep the history a reorg could need, no more
+        self.storage.commit_version(sealed_block.id);
+        self.storage
+            .prune_history(sealed_block.id.saturating_sub(DEFAULT_MAX_REORG_DEPTH));
 
         println!(
             "Mined Block #{} (Hash: {}) with {} txs",
@@ -164,8 +1431,1651 @@ impl Miner {
             sealed_block.transactions.len()
         );
 
-        // Save to history and clear the pool
-        self.chain.add_block(sealed_block.clone());
-        self.txpool.clear();
+        // Drop what this block included and what can never be, the rest (e.g. a nonce past a
+        // gap, or a sender that can't pay yet) waits for the next blocks
+        let done: Vec<B256> = sealed_block
+            .transactions
+            .iter()
+            .map(|tx| tx.hash())
+            .chain(
+                skipped
+                    .iter()
+                    .filter(|(_, e)| never_executes(e))
+                    .map(|(tx, _)| tx.hash()),
+            )
+            .collect();
+        self.txpool.remove(&done);
+        Ok(MinedBlock {
+            block: sealed_block,
+            skipped,
+        })
+    }
+
+    // Takes transactions in order until the next one would push the block over `max_block_bytes`
+    // or the gas limit. Transactions not covering the base fee stay pooled until it drops.
+    fn pack(&self, transactions: Vec<SignedTransaction>, base_fee: U256) -> Vec<SignedTransaction> {
+        let gas_limit = self.chain.last_block().gas_limit;
+        // Integer fields are encoded at their widest, so the estimate never falls short
+        let mut block = Block {
+            id: u64::MAX,
+            timestamp: u64::MAX,
+            chain_id: u64::MAX,
+            difficulty: u64::MAX,
+            gas_limit: u64::MAX,
+            gas_used: u64::MAX,
+            base_fee: U256::MAX,
+            ..Default::default()
+        };
+        let mut gas = 0;
+        for tx in transactions {
+            if tx.transaction().gas_price < base_fee {
+                continue;
+            }
+            gas += tx.transaction().gas();
+            block.transactions.push(tx);
+            if gas > gas_limit || block.length() > self.max_block_bytes {
+                block.transactions.pop();
+                break;
+            }
+        }
+        block.transactions
+    }
+}
+
+// Whether a transaction that failed with `e` fails in every later block too
+fn never_executes(e: &ExecError) -> bool {
+    match e {
+        ExecError::InvalidSignature(_) | ExecError::InvalidCode(_) => true,
+        ExecError::InvalidNonce { expected, got } => got < expected,
+        _ => false,
+    }
+}
+
+/// Base fee of the block after `parent` (EIP-1559): it rises when the parent used more than
+/// half its gas limit and falls when it used less, by at most 1/8.
+pub fn next_base_fee(parent: &Block) -> U256 {
+    let target = parent.gas_limit / ELASTICITY_MULTIPLIER;
+    if target == 0 || parent.gas_used == target {
+        return parent.base_fee;
+    }
+
+    let change = |gas_delta: u64| {
+        parent.base_fee.saturating_mul(U256::from(gas_delta))
+            / U256::from(target)
+            / U256::from(BASE_FEE_MAX_CHANGE_DENOMINATOR)
+    };
+    if parent.gas_used > target {
+        // Always moves up, even from a zero base fee
+        let delta = change(parent.gas_used - target).max(U256::from(1));
+        parent.base_fee.saturating_add(delta)
+    } else {
+        parent.base_fee - change(target - parent.gas_used)
+    }
+}
+
+/// Merkle path from a mined transaction to the `tx_root` of its block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProof {
+    pub tx_hash: B256,
+    pub block_hash: B256,
+    // Position of the transaction in the block, and how many the block has
+    pub index: usize,
+    pub leaf_count: usize,
+    // Bottom-up. Levels where the node has no sibling (odd one out) have no entry
+    pub siblings: Vec<B256>,
+}
+
+/// Binary keccak Merkle root over the transaction hashes, in block order.
+/// `EMPTY_TX_ROOT` for no transactions.
+pub fn tx_root(transactions: &[SignedTransaction]) -> B256 {
+    let leaves = transactions.iter().map(|tx| tx.hash()).collect();
+    merkle_levels(leaves)
+        .last()
+        .and_then(|root| root.first().copied())
+        .unwrap_or(EMPTY_TX_ROOT)
+}
+
+/// Whether `proof` links its transaction to `tx_root`.
+pub fn verify_inclusion(proof: &InclusionProof, tx_root: B256) -> bool {
+    verify_merkle_branch(
+        proof.tx_hash,
+        proof.index,
+        proof.leaf_count,
+        &proof.siblings,
+        tx_root,
+    )
+}
+
+/// What `import_block` did with a valid block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Imported,
+    /// The chain already had this very block, nothing changed.
+    AlreadyPresent,
+}
+
+/// Replays blocks written by `SharedChain::export` on top of the tip.
+/// Every block is re-executed and must reproduce its state root, otherwise it's
+/// rejected with the state untouched. Blocks already in the chain are skipped, a different
+/// block at a height the chain has is a `ChainError::Conflict`.
+/// Returns how many blocks were appended.
+pub fn import_blocks(
+    chain: &SharedChain,
+    storage: &SharedStorage,
+    mut data: &[u8],
+) -> Result<usize, ChainError> {
+    let mut imported = 0;
+    while !data.is_empty() {
+        let block =
+            Block::decode(&mut data).map_err(|e| ChainError::InvalidBlock(e.to_string()))?;
+        let number = block.id;
+        if import_block(chain, storage, block.seal())? == ImportOutcome::Imported {
+            storage.commit_version(number);
+            imported += 1;
+        }
+    }
+    Ok(imported)
+}
+
+/// Appends `block` on top of the tip after re-executing it (see `import_blocks`).
+/// Idempotent: a block the chain already has, pruned or not, is `AlreadyPresent`.
+pub fn import_block(
+    chain: &SharedChain,
+    storage: &SharedStorage,
+    block: SealedBlock,
+) -> Result<ImportOutcome, ChainError> {
+    if !matches!(
+        chain.lookup_block_by_hash(block.k_hash),
+        BlockLookup::NotFound
+    ) {
+        return Ok(ImportOutcome::AlreadyPresent);
+    }
+    let existing = match chain.lookup_block_by_number(block.id) {
+        BlockLookup::Found(existing) => Some(existing.k_hash),
+        BlockLookup::Pruned { hash, .. } => Some(hash),
+        BlockLookup::NotFound => None,
+    };
+    if let Some(existing) = existing {
+        return Err(ChainError::Conflict {
+            number: block.id,
+            existing,
+            got: block.k_hash,
+        });
+    }
+
+    storage.update(|raw_db| {
+        let parent = chain.last_block();
+        if block.parent_hash != parent.k_hash || block.id != parent.id + 1 {
+            return Err(ChainError::StaleParent);
+        }
+        if block.chain_id != parent.chain_id
+            || block.difficulty != parent.difficulty
+            || block.gas_limit != parent.gas_limit
+        {
+            return Err(ChainError::InvalidBlock(
+                "chain id, difficulty or gas limit differs from the parent".to_string(),
+            ));
+        }
+        if block.base_fee != next_base_fee(&parent) {
+            return Err(ChainError::InvalidBlock("wrong base fee".to_string()));
+        }
+
+        // Execute on a scratch copy (copy-on-write, so cheap), so a bad block leaves the state
+        // as it was
+        let mut scratch = raw_db.clone();
+        let env = BlockEnv {
+            coinbase: block.coinbase,
+            base_fee: block.base_fee,
+            reward: BLOCK_REWARD,
+        };
+        let result = ExecutionEngine::execute_block(&mut scratch, block.transactions.clone(), &env)
+            .map_err(|e| ChainError::InvalidBlock(e.to_string()))?;
+        if result.transactions.len() != block.transactions.len() {
+            return Err(ChainError::InvalidBlock(
+                "contains an invalid transaction".to_string(),
+            ));
+        }
+        if result.state_root != block.state_root {
+            return Err(ChainError::InvalidBlock("state root mismatch".to_string()));
+        }
+        if tx_root(&block.transactions) != block.tx_root {
+            return Err(ChainError::InvalidBlock(
+                "transactions root mismatch".to_string(),
+            ));
+        }
+        if result.logs_bloom != block.logs_bloom {
+            return Err(ChainError::InvalidBlock("logs bloom mismatch".to_string()));
+        }
+        if result.gas_used != block.gas_used || result.gas_used > block.gas_limit {
+            return Err(ChainError::InvalidBlock("wrong gas used".to_string()));
+        }
+
+        chain.add_block(block, result.receipts)?;
+        *raw_db = scratch;
+        Ok(ImportOutcome::Imported)
+    })
+}
+
+// `add_block` validates the whole block before touching the chain, so a poisoned lock still
+// guards a consistent chain and is taken over
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::keccak256;
+    use k256::ecdsa::SigningKey;
+    use pethit_execution::{TRANSFER_GAS, Transaction};
+
+    fn address_of(signing_key: &SigningKey) -> Address {
+        let public_key = signing_key.verifying_key().to_encoded_point(false);
+        let hash = keccak256(&public_key.as_bytes()[1..]);
+        Address::from_slice(&hash[12..])
+    }
+
+    // Helper to sign a transfer of 1 wei
+    fn signed_tx(signing_key: &SigningKey, nonce: u64) -> SignedTransaction {
+        let tx = Transaction {
+            to: Address::ZERO,
+            value: U256::from(1),
+            nonce,
+            gas_price: U256::from(1),
+            data: Vec::new(),
+        };
+        let (signature, recovery_id) = signing_key
+            .sign_prehash_recoverable(tx.hash().as_slice())
+            .unwrap();
+        SignedTransaction::new(tx, signature, recovery_id)
+    }
+
+    // Helper to give a key some funds
+    fn fund(storage: &SharedStorage, signing_key: &SigningKey) {
+        let account = Account {
+            nonce: 0,
+            balance: U256::from(1_000_000),
+        };
+        storage
+            .set_account(address_of(signing_key), account)
+            .unwrap();
+    }
+
+    fn test_miner() -> (Miner, SharedChain) {
+        let chain = SharedChain::new();
+        let miner = Miner::new(SharedTxPool::new(), SharedStorage::new(), chain.clone());
+        (miner, chain)
+    }
+
+    #[test]
+    fn test_empty_blocks_mined_by_default() {
+        let (mut miner, chain) = test_miner();
+
+        miner.tick().unwrap();
+
+        assert_eq!(chain.last_block().id, 1);
+    }
+
+    #[test]
+    fn test_skip_empty_blocks() {
+        let (miner, chain) = test_miner();
+        let mut miner = miner.with_mine_empty_blocks(false);
+
+        // Empty pool: the heartbeat doesn't seal anything
+        miner.tick().unwrap();
+        miner.tick().unwrap();
+
+        assert_eq!(chain.last_block().id, 0);
+    }
+
+    #[test]
+    fn test_add_block_rejects_stale_parent() {
+        let chain = SharedChain::new();
+        let genesis = chain.last_block();
+
+        let first = Block {
+            id: 1,
+            parent_hash: genesis.k_hash,
+            timestamp: 10,
+            ..Default::default()
+        }
+        .seal();
+        // A competing block on the same parent
+        let second = Block {
+            id: 1,
+            parent_hash: genesis.k_hash,
+            timestamp: 11,
+            ..Default::default()
+        }
+        .seal();
+
+        assert_eq!(chain.add_block(first, Vec::new()), Ok(()));
+        assert_eq!(
+            chain.add_block(second, Vec::new()),
+            Err(ChainError::StaleParent)
+        );
+        assert_eq!(chain.last_block().id, 1);
+    }
+
+    #[test]
+    fn test_concurrent_miners_keep_chain_linear() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+
+        // Two miners racing on the same pool, storage and chain
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone());
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        miner.tick().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Walk back from the tip: every height appears exactly once
+        let mut block = chain.last_block();
+        assert_eq!(block.id, 40);
+        while block.id > 0 {
+            let parent = chain.get_block_by_hash(block.parent_hash).unwrap();
+            assert_eq!(parent.id, block.id - 1);
+            block = parent;
+        }
+    }
+
+    #[test]
+    fn test_get_logs_by_range_and_address() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone());
+
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        let bob = SigningKey::from_bytes(&[2u8; 32].into()).unwrap();
+        fund(&storage, &alice);
+        fund(&storage, &bob);
+
+        // Blocks #1..=#3, each with one transfer from Alice and one from Bob
+        for nonce in 0..3 {
+            for key in [&alice, &bob] {
+                let tx = signed_tx(key, nonce);
+                txpool.add(tx.hash(), tx).unwrap();
+            }
+            miner.tick().unwrap();
+        }
+
+        let alice_logs = chain.get_logs(2, 3, Some(address_of(&alice)));
+        assert_eq!(alice_logs.len(), 2);
+        assert!(
+            alice_logs
+                .iter()
+                .all(|log| log.address == address_of(&alice))
+        );
+
+        // No filter, range past the tip
+        assert_eq!(chain.get_logs(1, 100, None).len(), 6);
+        // Reversed range
+        assert!(chain.get_logs(3, 1, None).is_empty());
+    }
+
+    #[test]
+    fn test_coinbase_collects_reward_and_fees() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let coinbase = Address::with_last_byte(0xcb);
+        let mut miner =
+            Miner::new(txpool.clone(), storage.clone(), chain.clone()).with_coinbase(coinbase);
+
+        // Three paying senders (the pool doesn't order nonces of the same sender)
+        let keys: Vec<_> = (1..=3u8)
+            .map(|byte| SigningKey::from_bytes(&[byte; 32].into()).unwrap())
+            .collect();
+        for key in &keys {
+            fund(&storage, key);
+            let tx = signed_tx(key, 0);
+            txpool.add(tx.hash(), tx).unwrap();
+        }
+        miner.tick().unwrap();
+
+        let block = chain.last_block();
+        assert_eq!(block.transactions.len(), 3);
+        assert_eq!(block.coinbase, coinbase);
+        let fees: U256 = block
+            .transactions
+            .iter()
+            .map(|tx| tx.transaction().fee())
+            .sum();
+        assert_eq!(storage.get_account(coinbase).balance, BLOCK_REWARD + fees);
+        // Each sender moved 1 wei and paid its fee
+        for key in &keys {
+            assert_eq!(
+                storage.get_account(address_of(key)).balance,
+                U256::from(1_000_000 - 1 - TRANSFER_GAS)
+            );
+        }
+    }
+
+    #[test]
+    fn test_failed_txs_come_back_with_the_block() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone());
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        let bob = SigningKey::from_bytes(&[2u8; 32].into()).unwrap();
+        fund(&storage, &alice);
+        let account = Account {
+            nonce: 1,
+            balance: U256::from(1_000_000),
+        };
+        storage.set_account(address_of(&bob), account).unwrap();
+        let valid = signed_tx(&alice, 0);
+        let replayed = signed_tx(&bob, 0);
+        txpool.add(valid.hash(), valid.clone()).unwrap();
+        txpool.add(replayed.hash(), replayed.clone()).unwrap();
+
+        let mined = miner.try_mine_once().unwrap();
+        assert_eq!(mined.block.transactions, vec![valid]);
+        assert_eq!(
+            mined.skipped,
+            vec![(
+                replayed,
+                ExecError::InvalidNonce {
+                    expected: 1,
+                    got: 0
+                }
+            )]
+        );
+        assert!(txpool.is_empty());
+    }
+
+    // Clock that extends the chain with a full block the first time the miner reads it, as
+    // if another node's block landed while the miner was building its own
+    #[derive(Debug)]
+    struct IntrudingClock {
+        chain: SharedChain,
+        intruded: Mutex<bool>,
+    }
+
+    impl Clock for IntrudingClock {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn unix_now(&self) -> u64 {
+            let mut intruded = self.intruded.lock().unwrap();
+            if !*intruded {
+                *intruded = true;
+                let parent = self.chain.last_block();
+                let block = Block {
+                    id: 1,
+                    parent_hash: parent.k_hash,
+                    gas_limit: parent.gas_limit,
+                    gas_used: parent.gas_limit,
+                    base_fee: next_base_fee(&parent),
+                    ..Default::default()
+                };
+                self.chain.add_block(block.seal(), Vec::new()).unwrap();
+            }
+            0
+        }
+    }
+
+    #[test]
+    fn test_stale_parent_remines_on_the_new_tip() {
+        let chain = SharedChain::new();
+        let clock = IntrudingClock {
+            chain: chain.clone(),
+            intruded: Mutex::new(false),
+        };
+        let mut miner =
+            Miner::new(SharedTxPool::new(), SharedStorage::new(), chain.clone()).with_clock(clock);
+
+        let genesis = chain.last_block();
+        let mined = miner.try_mine_once().unwrap();
+        let intruder = chain.get_block_by_hash(mined.block.parent_hash).unwrap();
+        assert_eq!((intruder.id, mined.block.id), (1, 2));
+        // Priced off the block it actually extends, not the genesis it started from
+        assert_eq!(mined.block.base_fee, next_base_fee(&intruder));
+        assert_ne!(mined.block.base_fee, next_base_fee(&genesis));
+    }
+
+    #[test]
+    fn test_queued_tx_stays_pooled() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone());
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        fund(&storage, &alice);
+        let first = signed_tx(&alice, 0);
+        let queued = signed_tx(&alice, 3);
+        txpool.add(first.hash(), first.clone()).unwrap();
+        txpool.add(queued.hash(), queued.clone()).unwrap();
+
+        let mined = miner.try_mine_once().unwrap();
+        assert_eq!(mined.block.transactions, vec![first]);
+        assert_eq!(
+            mined.skipped,
+            vec![(
+                queued.clone(),
+                ExecError::InvalidNonce {
+                    expected: 1,
+                    got: 3
+                }
+            )]
+        );
+        // Nonces 1 and 2 may still come
+        assert_eq!(txpool.get_all_transactions(), vec![queued]);
+    }
+
+    #[test]
+    fn test_coinbase_overflow_is_an_error() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let coinbase = Address::with_last_byte(0xcb);
+        let account = Account {
+            nonce: 0,
+            balance: U256::MAX,
+        };
+        storage.set_account(coinbase, account).unwrap();
+        let mut miner =
+            Miner::new(txpool.clone(), storage.clone(), chain.clone()).with_coinbase(coinbase);
+
+        assert_eq!(
+            miner.try_mine_once().unwrap_err(),
+            MineError::Execution(ExecError::BalanceOverflow)
+        );
+        assert_eq!(chain.last_block().id, 0);
+        assert_eq!(storage.get_account(coinbase).balance, U256::MAX);
+    }
+
+    #[test]
+    fn test_handshake_refuses_other_chains() {
+        let first = SharedChain::new();
+        let other = SharedChain::from_genesis(&GenesisConfig {
+            chain_id: 1,
+            ..GenesisConfig::default()
+        });
+
+        let err = handshake(&first, "127.0.0.1:1", &other, "127.0.0.1:2").unwrap_err();
+        assert!(err.starts_with("Peer 127.0.0.1:2 is on another chain"));
+        assert!(first.peers().is_empty());
+        assert!(other.peers().is_empty());
+    }
+
+    #[test]
+    fn test_empty_blocks_seal_identically() {
+        assert_eq!(tx_root(&[]), keccak256([alloy_rlp::EMPTY_LIST_CODE]));
+        assert_eq!(Block::default().tx_root, EMPTY_TX_ROOT);
+
+        // Built apart, the second one relying on Default for the root
+        let first = Block {
+            id: 1,
+            transactions: Vec::new(),
+            parent_hash: B256::repeat_byte(7),
+            timestamp: 1_700_000_000,
+            tx_root: tx_root(&[]),
+            ..Default::default()
+        }
+        .seal();
+        let second = Block {
+            id: 1,
+            parent_hash: B256::repeat_byte(7),
+            timestamp: 1_700_000_000,
+            ..Default::default()
+        }
+        .seal();
+        assert_eq!(first.k_hash, second.k_hash);
+    }
+
+    #[test]
+    fn test_genesis_extra_data_changes_the_hash() {
+        let config = GenesisConfig {
+            timestamp: 1_700_000_000,
+            extra_data: b"pethit-devnet".to_vec(),
+            ..GenesisConfig::default()
+        };
+        let genesis = config.genesis_block();
+        assert_eq!(genesis.timestamp, 1_700_000_000);
+        assert_eq!(genesis.extra_data, b"pethit-devnet");
+
+        let other = GenesisConfig {
+            extra_data: b"pethit-testnet".to_vec(),
+            ..config.clone()
+        };
+        assert_ne!(other.genesis_block().k_hash, genesis.k_hash);
+        let later = GenesisConfig {
+            timestamp: 1_700_000_001,
+            ..config.clone()
+        };
+        assert_ne!(later.genesis_block().k_hash, genesis.k_hash);
+    }
+
+    #[test]
+    fn test_genesis_hash_is_deterministic() {
+        let mut config = GenesisConfig::default();
+        config
+            .alloc
+            .insert(Address::with_last_byte(1), U256::from(1000));
+        config
+            .alloc
+            .insert(Address::with_last_byte(2), U256::from(2000));
+
+        // Two independently built chains
+        let first = SharedChain::from_genesis(&config);
+        let second = SharedChain::from_genesis(&config.clone());
+        assert_eq!(first.genesis_hash(), second.genesis_hash());
+
+        // Any change to the config is another chain
+        let other_chain_id = GenesisConfig {
+            chain_id: 1,
+            ..config.clone()
+        };
+        assert_ne!(
+            SharedChain::from_genesis(&other_chain_id).genesis_hash(),
+            first.genesis_hash()
+        );
+        config
+            .alloc
+            .insert(Address::with_last_byte(3), U256::from(1));
+        assert_ne!(
+            SharedChain::from_genesis(&config).genesis_hash(),
+            first.genesis_hash()
+        );
+    }
+
+    #[test]
+    fn test_export_then_import_reproduces_tip() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone())
+            .with_coinbase(Address::with_last_byte(0xcb));
+
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        fund(&storage, &alice);
+        for nonce in 0..3 {
+            let tx = signed_tx(&alice, nonce);
+            txpool.add(tx.hash(), tx).unwrap();
+            miner.tick().unwrap();
+        }
+
+        // Genesis included, range past the tip
+        let exported = chain.export(0, 100);
+
+        // A fresh node with the same genesis state
+        let fresh_storage = SharedStorage::new();
+        let fresh_chain = SharedChain::new();
+        fund(&fresh_storage, &alice);
+        let imported = import_blocks(&fresh_chain, &fresh_storage, &exported).unwrap();
+
+        assert_eq!(imported, 3);
+        assert_eq!(fresh_chain.last_block().k_hash, chain.last_block().k_hash);
+        assert_eq!(fresh_storage.state_root(), storage.state_root());
+    }
+
+    #[test]
+    fn test_reimport_is_a_no_op() {
+        let (mut miner, chain) = test_miner();
+        miner.mine_once();
+        miner.mine_once();
+        let exported = chain.export(1, 2);
+
+        let fresh_storage = SharedStorage::new();
+        let fresh_chain = SharedChain::new();
+        assert_eq!(
+            import_blocks(&fresh_chain, &fresh_storage, &exported).unwrap(),
+            2
+        );
+        let root = fresh_storage.state_root();
+
+        // The whole file again, then a single block
+        assert_eq!(
+            import_blocks(&fresh_chain, &fresh_storage, &exported).unwrap(),
+            0
+        );
+        let block = chain.get_blocks(1, 1).remove(0);
+        assert_eq!(
+            import_block(&fresh_chain, &fresh_storage, block),
+            Ok(ImportOutcome::AlreadyPresent)
+        );
+        assert_eq!(fresh_chain.last_block().id, 2);
+        assert_eq!(fresh_storage.state_root(), root);
+    }
+
+    #[test]
+    fn test_import_refuses_a_different_block_at_a_known_height() {
+        let (mut miner, chain) = test_miner();
+        let ours = miner.mine_once();
+
+        // Another miner's block 1, paying a different coinbase
+        let other_chain = SharedChain::new();
+        let mut other = Miner::new(
+            SharedTxPool::new(),
+            SharedStorage::new(),
+            other_chain.clone(),
+        )
+        .with_coinbase(Address::with_last_byte(0xcb));
+        let theirs = other.mine_once();
+        assert_ne!(ours.k_hash, theirs.k_hash);
+
+        let storage = miner.storage.clone();
+        let root = storage.state_root();
+        assert_eq!(
+            import_blocks(&chain, &storage, &other_chain.export(1, 1)),
+            Err(ChainError::Conflict {
+                number: 1,
+                existing: ours.k_hash,
+                got: theirs.k_hash,
+            })
+        );
+        assert_eq!(chain.last_block().k_hash, ours.k_hash);
+        assert_eq!(storage.state_root(), root);
+    }
+
+    #[test]
+    fn test_import_rejects_tampered_block() {
+        let (mut miner, chain) = test_miner();
+        miner.tick().unwrap();
+
+        let mut block = chain.last_block().block;
+        block.state_root = B256::repeat_byte(1);
+        let mut data = Vec::new();
+        block.encode(&mut data);
+
+        let fresh_storage = SharedStorage::new();
+        let fresh_chain = SharedChain::new();
+        let result = import_blocks(&fresh_chain, &fresh_storage, &data);
+
+        assert_eq!(
+            result,
+            Err(ChainError::InvalidBlock("state root mismatch".to_string()))
+        );
+        assert_eq!(fresh_chain.last_block().id, 0);
+        assert_eq!(
+            fresh_storage.state_root(),
+            SharedStorage::new().state_root()
+        );
+    }
+
+    // A block on top of the tip of `chain`, sealed at `timestamp`
+    fn block_at(chain: &SharedChain, timestamp: u64) -> SealedBlock {
+        let tip = chain.last_block();
+        Block {
+            id: tip.id + 1,
+            parent_hash: tip.k_hash,
+            timestamp,
+            ..Default::default()
+        }
+        .seal()
+    }
+
+    #[test]
+    fn test_block_timestamp_bounds() {
+        let clock = MockClock::new(1_000_000);
+        let chain = SharedChain::new()
+            .with_max_future_drift(Duration::from_secs(15))
+            .with_clock(clock.clone());
+        let now = clock.unix_now();
+
+        // In the past, but after the parent (genesis, at 0)
+        chain
+            .add_block(block_at(&chain, now - 100), Vec::new())
+            .unwrap();
+        // Within the drift
+        chain
+            .add_block(block_at(&chain, now + 5), Vec::new())
+            .unwrap();
+        assert_eq!(chain.last_block().id, 2);
+
+        let far_future = block_at(&chain, now + 60);
+        assert_eq!(
+            chain.add_block(far_future, Vec::new()),
+            Err(ChainError::InvalidBlock(
+                "timestamp is too far in the future".to_string()
+            ))
+        );
+        let before_parent = block_at(&chain, now);
+        assert_eq!(
+            chain.add_block(before_parent, Vec::new()),
+            Err(ChainError::InvalidBlock(
+                "timestamp is before the parent's".to_string()
+            ))
+        );
+        assert_eq!(chain.last_block().id, 2);
+
+        // The drift counts from the clock: 45 seconds later, +60 is within it
+        let later = block_at(&chain, now + 60);
+        clock.advance(Duration::from_secs(44));
+        assert!(chain.add_block(later.clone(), Vec::new()).is_err());
+        clock.advance(Duration::from_secs(1));
+        chain.add_block(later, Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn test_import_honors_checkpoints() {
+        let (mut miner, chain) = test_miner();
+        for _ in 0..3 {
+            miner.tick().unwrap();
+        }
+        let data = chain.export(1, 3);
+        let trusted = chain
+            .get_block_by_hash(chain.last_block().parent_hash)
+            .unwrap();
+        assert_eq!(trusted.id, 2);
+
+        let matching = SharedChain::new().with_checkpoints(BTreeMap::from([(2, trusted.k_hash)]));
+        assert_eq!(
+            import_blocks(&matching, &SharedStorage::new(), &data),
+            Ok(3)
+        );
+
+        let wrong = B256::repeat_byte(0xcc);
+        let conflicting = SharedChain::new().with_checkpoints(BTreeMap::from([(2, wrong)]));
+        let result = import_blocks(&conflicting, &SharedStorage::new(), &data);
+        assert_eq!(
+            result,
+            Err(ChainError::CheckpointMismatch {
+                number: 2,
+                expected: wrong,
+                got: trusted.k_hash,
+            })
+        );
+        // Stops right before the checkpoint
+        assert_eq!(conflicting.last_block().id, 1);
+    }
+
+    #[test]
+    fn test_block_byte_cap_spills_into_next_blocks() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        // Room for a single transfer per block
+        let max_block_bytes = 640;
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone())
+            .with_max_block_bytes(max_block_bytes);
+
+        for byte in 1..=3u8 {
+            let key = SigningKey::from_bytes(&[byte; 32].into()).unwrap();
+            fund(&storage, &key);
+            let tx = signed_tx(&key, 0);
+            txpool.add(tx.hash(), tx).unwrap();
+        }
+
+        for pending in (0..3).rev() {
+            miner.tick().unwrap();
+            let block = chain.last_block();
+            assert_eq!(block.transactions.len(), 1);
+            assert!(block.block.length() <= max_block_bytes);
+            assert_eq!(txpool.len(), pending);
+        }
+    }
+
+    // Helper to build `len` empty blocks on top of `parent`, tagged so they differ from other branches
+    fn branch(parent: &SealedBlock, len: u64, tag: u8) -> Vec<(SealedBlock, Vec<Receipt>)> {
+        let mut blocks = Vec::new();
+        let mut parent = parent.clone();
+        for _ in 0..len {
+            let block = Block {
+                id: parent.id + 1,
+                parent_hash: parent.k_hash,
+                coinbase: Address::with_last_byte(tag),
+                ..Default::default()
+            }
+            .seal();
+            parent = block.clone();
+            blocks.push((block, Vec::new()));
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_slow_block_subscriber_lags_instead_of_stalling() {
+        let chain = SharedChain::new().with_block_channel_capacity(2);
+        let mut miner = Miner::new(SharedTxPool::new(), SharedStorage::new(), chain.clone());
+        let mut subscriber = chain.subscribe_blocks();
+
+        // Nobody reads while five blocks are mined
+        for _ in 0..5 {
+            miner.tick().unwrap();
+        }
+        assert_eq!(chain.last_block().id, 5);
+
+        assert_eq!(
+            subscriber.try_recv().unwrap_err(),
+            broadcast::error::TryRecvError::Lagged(3)
+        );
+        // Then the most recent ones
+        assert_eq!(subscriber.try_recv().unwrap().id, 4);
+        assert_eq!(subscriber.try_recv().unwrap().id, 5);
+    }
+
+    #[test]
+    fn test_reorg_within_max_depth() {
+        let chain = SharedChain::new().with_max_reorg_depth(2);
+        let old_branch = branch(&chain.last_block(), 3, 1);
+        let fork = old_branch[0].0.clone();
+        for (block, receipts) in old_branch {
+            chain.add_block(block, receipts).unwrap();
+        }
+
+        // Fork off block #1: replaces #2 and #3
+        let new_branch = branch(&fork, 3, 2);
+        let new_tip = new_branch.last().unwrap().0.k_hash;
+
+        assert_eq!(chain.reorg(new_branch), Ok(()));
+        assert_eq!(chain.last_block().k_hash, new_tip);
+        assert_eq!(chain.last_block().id, 4);
+    }
+
+    #[test]
+    fn test_reorg_emits_event() {
+        let chain = SharedChain::new();
+        let old_branch = branch(&chain.last_block(), 3, 1);
+        let fork = old_branch[0].0.clone();
+        let removed: Vec<B256> = old_branch[1..].iter().map(|(b, _)| b.k_hash).collect();
+        for (block, receipts) in old_branch {
+            chain.add_block(block, receipts).unwrap();
+        }
+        let events = chain.subscribe_reorgs();
+        let new_branch = branch(&fork, 3, 2);
+        let added: Vec<B256> = new_branch.iter().map(|(b, _)| b.k_hash).collect();
+
+        chain.reorg(new_branch).unwrap();
+
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ReorgEvent {
+                common_ancestor: fork.k_hash,
+                removed,
+                added,
+            }
+        );
+        // Plain extensions of the tip are not reorgs
+        chain
+            .add_block(branch(&chain.last_block(), 1, 3).remove(0).0, Vec::new())
+            .unwrap();
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_heavier_branch_wins_over_longer_one() {
+        let chain = SharedChain::new();
+        let genesis = chain.last_block();
+        let with_difficulty = |blocks: Vec<(SealedBlock, Vec<Receipt>)>, difficulty: u64| {
+            let mut parent = genesis.clone();
+            let mut reworked = Vec::new();
+            for (block, receipts) in blocks {
+                let block = Block {
+                    parent_hash: parent.k_hash,
+                    difficulty,
+                    ..block.block
+                }
+                .seal();
+                parent = block.clone();
+                reworked.push((block, receipts));
+            }
+            reworked
+        };
+        for (block, receipts) in with_difficulty(branch(&genesis, 3, 1), 1) {
+            chain.add_block(block, receipts).unwrap();
+        }
+        assert_eq!(
+            chain.total_difficulty(),
+            genesis.total_difficulty + U256::from(3)
+        );
+
+        // Two blocks of difficulty 5 outweigh three of difficulty 1
+        let heavy = with_difficulty(branch(&genesis, 2, 2), 5);
+        let heavy_tip = heavy.last().unwrap().0.k_hash;
+        chain.reorg(heavy).unwrap();
+        assert_eq!(chain.last_block().k_hash, heavy_tip);
+        assert_eq!(chain.last_block().id, 2);
+        assert_eq!(
+            chain.total_difficulty(),
+            genesis.total_difficulty + U256::from(10)
+        );
+
+        // Longer but lighter: refused
+        let result = chain.reorg(with_difficulty(branch(&genesis, 4, 3), 2));
+        assert!(matches!(result, Err(ChainError::InvalidBlock(_))));
+        assert_eq!(chain.last_block().k_hash, heavy_tip);
+    }
+
+    #[test]
+    fn test_reorg_beyond_max_depth_is_refused() {
+        let chain = SharedChain::new().with_max_reorg_depth(2);
+        let genesis = chain.last_block();
+        for (block, receipts) in branch(&genesis, 3, 1) {
+            chain.add_block(block, receipts).unwrap();
+        }
+        let tip = chain.last_block().k_hash;
+
+        // Fork off genesis: would replace 3 blocks
+        let result = chain.reorg(branch(&genesis, 5, 2));
+
+        assert_eq!(result, Err(ChainError::ReorgTooDeep { depth: 3, max: 2 }));
+        assert_eq!(chain.last_block().k_hash, tip);
+    }
+
+    #[test]
+    fn test_mine_once_grows_chain() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone());
+
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        fund(&storage, &alice);
+
+        for id in 1..=5 {
+            let tx = signed_tx(&alice, id - 1);
+            txpool.add(tx.hash(), tx.clone()).unwrap();
+
+            let block = miner.mine_once();
+
+            assert_eq!(block.id, id);
+            assert_eq!(block.transactions, vec![tx]);
+            assert_eq!(chain.last_block().k_hash, block.k_hash);
+        }
+        assert_eq!(storage.get_account(address_of(&alice)).nonce, 5);
+    }
+
+    #[test]
+    fn test_mine_once_ignores_empty_block_setting() {
+        let (miner, chain) = test_miner();
+        let mut miner = miner.with_mine_empty_blocks(false);
+
+        // Mining on demand always seals, only the heartbeat skips empty blocks
+        let block = miner.mine_once();
+
+        assert_eq!(block.id, 1);
+        assert!(block.transactions.is_empty());
+        assert_eq!(chain.last_block().id, 1);
+    }
+
+    #[test]
+    fn test_logs_bloom() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone());
+
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        fund(&storage, &alice);
+        let tx = signed_tx(&alice, 0);
+        txpool.add(tx.hash(), tx).unwrap();
+        let block = miner.mine_once();
+
+        // Transfer logs are emitted by the sender
+        assert!(block.may_contain_log(address_of(&alice)));
+        assert!(!block.may_contain_log(Address::with_last_byte(0x42)));
+        // Nothing in an empty block
+        assert!(!miner.mine_once().may_contain_log(address_of(&alice)));
+    }
+
+    #[test]
+    fn test_pruned_blocks_keep_only_their_hash() {
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new().with_prune_blocks(10);
+        let mut miner = Miner::new(SharedTxPool::new(), storage, chain.clone());
+        let mined: Vec<_> = (0..20).map(|_| miner.mine_once()).collect();
+
+        let block_5 = &mined[4];
+        assert!(matches!(
+            chain.lookup_block_by_number(5),
+            BlockLookup::Pruned { number: 5, hash } if hash == block_5.k_hash
+        ));
+        assert!(matches!(
+            chain.lookup_block_by_hash(block_5.k_hash),
+            BlockLookup::Pruned { number: 5, .. }
+        ));
+        assert!(chain.get_block_by_hash(block_5.k_hash).is_none());
+
+        let block_15 = &mined[14];
+        assert!(matches!(
+            chain.lookup_block_by_number(15),
+            BlockLookup::Found(block) if block.k_hash == block_15.k_hash
+        ));
+        // Genesis is always kept, unknown blocks are not pruned ones
+        assert!(matches!(
+            chain.lookup_block_by_number(0),
+            BlockLookup::Found(_)
+        ));
+        assert!(matches!(
+            chain.lookup_block_by_number(21),
+            BlockLookup::NotFound
+        ));
+        assert!(matches!(
+            chain.lookup_block_by_hash(B256::ZERO),
+            BlockLookup::NotFound
+        ));
+        assert_eq!(chain.last_block().id, 20);
+    }
+
+    // Test hasher: the hash of a header is its block number (the first 8 bytes hashed)
+    struct NumberHasher;
+
+    impl Hasher for NumberHasher {
+        fn hash(data: &[u8]) -> B256 {
+            B256::left_padding_from(&data[..8])
+        }
+    }
+
+    #[test]
+    fn test_block_linking_with_mock_hasher() {
+        let genesis = Block::default().seal_with::<NumberHasher>();
+        assert_eq!(genesis.k_hash, B256::ZERO);
+        let chain = SharedChain {
+            inner: Arc::new(Mutex::new(Chain::new(genesis))),
+        };
+        let block = |id: u64, parent_hash: B256| {
+            Block {
+                id,
+                parent_hash,
+                ..Default::default()
+            }
+            .seal_with::<NumberHasher>()
+        };
+
+        chain.add_block(block(1, B256::ZERO), Vec::new()).unwrap();
+        assert_eq!(
+            chain.add_block(block(2, B256::with_last_byte(5)), Vec::new()),
+            Err(ChainError::StaleParent)
+        );
+        chain
+            .add_block(block(2, B256::with_last_byte(1)), Vec::new())
+            .unwrap();
+
+        assert_eq!(chain.last_block().k_hash, B256::with_last_byte(2));
+        assert!(chain.get_block_by_hash(B256::with_last_byte(1)).is_some());
+    }
+
+    #[test]
+    fn test_sealed_block_decode_checks_hash() {
+        let sealed = Block {
+            id: 3,
+            timestamp: 42,
+            ..Default::default()
+        }
+        .seal();
+        let encoded = alloy_rlp::encode(&sealed);
+        assert_eq!(encoded.len(), sealed.length());
+
+        let decoded = SealedBlock::decode(&mut encoded.as_slice()).unwrap();
+        assert_eq!(decoded.k_hash, sealed.k_hash);
+
+        let tampered = SealedBlock {
+            k_hash: B256::repeat_byte(0xab),
+            ..sealed
+        };
+        let encoded = alloy_rlp::encode(&tampered);
+        assert_eq!(
+            SealedBlock::decode(&mut encoded.as_slice()).unwrap_err(),
+            alloy_rlp::Error::Custom("SealedBlock hash mismatch")
+        );
+    }
+
+    #[test]
+    fn test_base_fee_follows_block_fullness() {
+        // Room for two transfers, the target is one
+        let config = GenesisConfig {
+            gas_limit: 2 * TRANSFER_GAS,
+            base_fee: U256::from(1000),
+            ..Default::default()
+        };
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::from_genesis(&config);
+        let coinbase = Address::with_last_byte(0xcb);
+        let mut miner =
+            Miner::new(txpool.clone(), storage.clone(), chain.clone()).with_coinbase(coinbase);
+
+        let gas_price = 1200;
+        for byte in 1..=3u8 {
+            let key = SigningKey::from_bytes(&[byte; 32].into()).unwrap();
+            let account = Account {
+                nonce: 0,
+                balance: U256::from(1_000_000_000),
+            };
+            storage.set_account(address_of(&key), account).unwrap();
+            let tx = Transaction {
+                to: Address::ZERO,
+                value: U256::from(1),
+                nonce: 0,
+                gas_price: U256::from(gas_price),
+                data: Vec::new(),
+            };
+            let (signature, recovery_id) =
+                key.sign_prehash_recoverable(tx.hash().as_slice()).unwrap();
+            let tx = SignedTransaction::new(tx, signature, recovery_id);
+            txpool.add(tx.hash(), tx).unwrap();
+        }
+
+        // Genesis is empty, so this block already starts lower: 1000 - 1000 / 8
+        let full = miner.mine_once();
+        assert_eq!(full.base_fee, U256::from(875));
+        assert_eq!(full.gas_used, 2 * TRANSFER_GAS);
+        // Only the tip over the base fee reaches the coinbase, the rest is burned
+        let tips = U256::from(2 * (gas_price - 875) * TRANSFER_GAS);
+        assert_eq!(storage.get_account(coinbase).balance, BLOCK_REWARD + tips);
+
+        // After a full block: up by 1/8
+        let at_target = miner.mine_once();
+        assert_eq!(at_target.base_fee, U256::from(875 + 109));
+        assert_eq!(at_target.transactions.len(), 1);
+
+        // Exactly at the target: unchanged
+        let empty = miner.mine_once();
+        assert_eq!(empty.base_fee, U256::from(984));
+        // After an empty block: down by 1/8
+        assert_eq!(next_base_fee(&empty), U256::from(984 - 123));
+    }
+
+    #[test]
+    fn test_instant_seal_mines_on_arrival() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut blocks = chain.subscribe_blocks();
+        let (stop_miner, shutdown) = mpsc::channel();
+        let miner = Miner::new(txpool.clone(), storage.clone(), chain.clone())
+            .with_seal_mode(SealMode::Instant)
+            .with_shutdown(shutdown);
+        let mining = thread::spawn(move || miner.start_mining());
+
+        let key = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        fund(&storage, &key);
+        let tx = signed_tx(&key, 0);
+        txpool.add(tx.hash(), tx.clone()).unwrap();
+        let block = blocks.blocking_recv().unwrap();
+        assert_eq!(block.id, 1);
+        assert_eq!(block.transactions, vec![tx]);
+
+        // Nothing else arrived: no other block once the miner is stopped
+        stop_miner.send(()).unwrap();
+        mining.join().unwrap();
+        assert!(blocks.try_recv().is_err());
+        assert_eq!(chain.last_block().id, 1);
+    }
+
+    #[test]
+    fn test_inclusion_proofs() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone());
+        // An odd count, so one node goes up a level without a sibling
+        for byte in 1..=5u8 {
+            let key = SigningKey::from_bytes(&[byte; 32].into()).unwrap();
+            fund(&storage, &key);
+            let tx = signed_tx(&key, 0);
+            txpool.add(tx.hash(), tx).unwrap();
+        }
+        let block = miner.mine_once();
+        assert_eq!(block.transactions.len(), 5);
+        assert_eq!(block.tx_root, tx_root(&block.transactions));
+
+        for tx in &block.transactions {
+            let proof = chain.inclusion_proof(tx.hash()).unwrap();
+            assert_eq!(proof.block_hash, block.k_hash);
+            assert!(verify_inclusion(&proof, block.tx_root));
+        }
+
+        let proof = chain.inclusion_proof(block.transactions[1].hash()).unwrap();
+        let mut tampered = proof.clone();
+        tampered.siblings[0] = B256::repeat_byte(0xab);
+        assert!(!verify_inclusion(&tampered, block.tx_root));
+        let moved = InclusionProof {
+            index: 0,
+            ..proof.clone()
+        };
+        assert!(!verify_inclusion(&moved, block.tx_root));
+        assert!(!verify_inclusion(&proof, B256::ZERO));
+        assert!(chain.inclusion_proof(B256::ZERO).is_none());
+    }
+
+    #[test]
+    fn test_priority_changes_inclusion_order() {
+        let keys: Vec<_> = (1..=3u8)
+            .map(|byte| SigningKey::from_bytes(&[byte; 32].into()).unwrap())
+            .collect();
+        let priced_tx = |key: &SigningKey, nonce, gas_price| {
+            let tx = Transaction {
+                to: Address::ZERO,
+                value: U256::from(1),
+                nonce,
+                gas_price: U256::from(gas_price),
+                data: Vec::new(),
+            };
+            let (signature, recovery_id) =
+                key.sign_prehash_recoverable(tx.hash().as_slice()).unwrap();
+            SignedTransaction::new(tx, signature, recovery_id)
+        };
+        // Arrival order: middle, cheap, expensive, then a pricier follow-up of the cheap sender
+        let txs = vec![
+            priced_tx(&keys[2], 0, 20),
+            priced_tx(&keys[0], 0, 1),
+            priced_tx(&keys[1], 0, 30),
+            priced_tx(&keys[0], 1, 40),
+        ];
+        let mine_with = |priority: TxPriority| {
+            let clock = MockClock::new(0);
+            let txpool = SharedTxPool::new().with_clock(clock.clone());
+            let storage = SharedStorage::new();
+            for key in &keys {
+                fund(&storage, key);
+            }
+            for tx in &txs {
+                txpool.add(tx.hash(), tx.clone()).unwrap();
+                // Distinct arrival times
+                clock.advance(Duration::from_secs(1));
+            }
+            let mut miner = Miner::new(txpool, storage, SharedChain::new()).with_priority(priority);
+            miner.mine_once().block.transactions
+        };
+
+        assert_eq!(mine_with(fifo), txs);
+        // The follow-up pays the most but can't go before its sender's nonce 0, which takes
+        // the first slot in its place
+        assert_eq!(
+            mine_with(highest_fee),
+            vec![
+                txs[1].clone(),
+                txs[2].clone(),
+                txs[0].clone(),
+                txs[3].clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compressed_block_round_trips() {
+        let txpool = SharedTxPool::new();
+        let storage = SharedStorage::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), SharedChain::new());
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        fund(&storage, &alice);
+        let tx = signed_tx(&alice, 0);
+        txpool.add(tx.hash(), tx).unwrap();
+        let mined = miner.mine_once();
+
+        let mut raw = Vec::new();
+        mined.block.encode(&mut raw);
+        let loaded = pethit_storage::decompress(&pethit_storage::compress(&raw)).unwrap();
+        let block = Block::decode(&mut loaded.as_slice()).unwrap();
+
+        assert_eq!(block.seal().k_hash, mined.k_hash);
+    }
+
+    #[test]
+    fn test_rules_decide_which_blocks_are_accepted() {
+        let genesis = GenesisConfig {
+            difficulty: 256,
+            ..Default::default()
+        };
+        let block_with = |parent: &SealedBlock, nonce: u64| {
+            Block {
+                id: parent.id + 1,
+                parent_hash: parent.k_hash,
+                difficulty: parent.difficulty,
+                extra_data: nonce.to_be_bytes().to_vec(),
+                ..Default::default()
+            }
+            .seal()
+        };
+        let meets_target = |block: &SealedBlock| {
+            PowRules
+                .validate_header(block, &genesis.genesis_block())
+                .is_ok()
+        };
+        let parent = genesis.genesis_block();
+        let sealed = (0..)
+            .map(|n| block_with(&parent, n))
+            .find(meets_target)
+            .unwrap();
+        let unsealed = (0..)
+            .map(|n| block_with(&parent, n))
+            .find(|b| !meets_target(b))
+            .unwrap();
+
+        let dev = SharedChain::from_genesis(&genesis);
+        dev.add_block(unsealed.clone(), Vec::new()).unwrap();
+
+        let pow = SharedChain::from_genesis(&genesis).with_rules(PowRules);
+        assert_eq!(
+            pow.add_block(unsealed, Vec::new()),
+            Err(ChainError::InvalidBlock(
+                "hash doesn't meet the difficulty".to_string()
+            ))
+        );
+        pow.add_block(sealed.clone(), Vec::new()).unwrap();
+        assert_eq!(pow.last_block().k_hash, sealed.k_hash);
+
+        // The difficulty can't be lowered to make sealing easier
+        let easier = Block {
+            difficulty: 1,
+            ..block_with(&sealed, 0).block
+        }
+        .seal();
+        assert!(pow.add_block(easier, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_miner_considers_at_most_the_cap() {
+        let config = pethit_txpool::PoolConfig {
+            max_per_sender: 1_000,
+            ..Default::default()
+        };
+        let txpool = SharedTxPool::with_config(config);
+        let storage = SharedStorage::new();
+        let key = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        let late = SigningKey::from_bytes(&[2u8; 32].into()).unwrap();
+        for key in [&key, &late] {
+            let account = Account {
+                nonce: 0,
+                balance: U256::from(u64::MAX),
+            };
+            storage.set_account(address_of(key), account).unwrap();
+        }
+
+        // The late sender's nonce 1 arrives early, its nonce 0 after the cap
+        let follow_up = signed_tx(&late, 1);
+        txpool.add(follow_up.hash(), follow_up).unwrap();
+        for nonce in 0..600 {
+            let tx = signed_tx(&key, nonce);
+            txpool.add(tx.hash(), tx).unwrap();
+        }
+        let first = signed_tx(&late, 0);
+        txpool.add(first.hash(), first).unwrap();
+
+        let mut miner = Miner::new(txpool.clone(), storage, SharedChain::new())
+            .with_max_candidates_considered(50);
+        let started = Instant::now();
+        let block = miner.mine_once();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // The first 50 by arrival, minus the follow-up that can't run without its nonce 0
+        assert_eq!(block.transactions.len(), 49);
+        assert!(
+            block
+                .transactions
+                .iter()
+                .all(|tx| tx.recover_sender().unwrap() == address_of(&key))
+        );
+        assert_eq!(txpool.len(), 602 - 49);
+    }
+
+    #[test]
+    fn test_mining_survives_poisoned_locks() {
+        let (mut miner, chain) = test_miner();
+        miner.mine_once();
+
+        // A thread panics while holding the chain lock, another while updating the storage
+        let poisoner = chain.clone();
+        let _ = thread::spawn(move || {
+            let _chain = poisoner.inner.lock().unwrap();
+            panic!("poison the chain");
+        })
+        .join();
+        let poisoner = miner.storage.clone();
+        let _ = thread::spawn(move || poisoner.update(|_| panic!("poison the storage"))).join();
+        assert!(chain.inner.is_poisoned());
+
+        let block = miner.mine_once();
+        assert_eq!(block.id, 2);
+        assert_eq!(chain.last_block().k_hash, block.k_hash);
+        assert_eq!(miner.storage.state_root(), block.state_root);
+    }
+
+    // Backend that refuses new entries past `capacity`, like a full disk
+    #[derive(Debug, Clone)]
+    struct BoundedBackend {
+        entries: BTreeMap<Vec<u8>, Vec<u8>>,
+        capacity: usize,
+        // Writes taken so far
+        puts: usize,
+    }
+
+    impl StorageBackend for BoundedBackend {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.entries.get(key).cloned()
+        }
+
+        fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError> {
+            if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+                return Err(StorageError::Full);
+            }
+            self.entries.insert(key, value);
+            self.puts += 1;
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+            self.entries.remove(key)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+            Box::new(
+                self.entries
+                    .iter()
+                    .map(|(k, v)| (k.as_slice(), v.as_slice())),
+            )
+        }
+    }
+
+    #[test]
+    fn test_miner_stops_when_the_storage_is_full() {
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        // Room for alice and the coinbase, not for a recipient on top
+        let backend = BoundedBackend {
+            entries: BTreeMap::new(),
+            capacity: 2,
+            puts: 0,
+        };
+        let storage = SharedStorage::with_backend(backend, 0);
+        let account = Account {
+            nonce: 0,
+            balance: U256::from(1_000_000),
+        };
+        storage.set_account(address_of(&alice), account).unwrap();
+        let txpool = SharedTxPool::new();
+        let chain = SharedChain::new();
+        let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone())
+            .with_coinbase(Address::with_last_byte(0xcb));
+        miner.try_mine_once().unwrap();
+        let root = storage.state_root();
+
+        // Writes go in key order: the coinbase and alice are applied before the recipient is
+        // refused
+        let tx = Transaction {
+            to: Address::repeat_byte(0xff),
+            ..signed_tx(&alice, 0).transaction().clone()
+        };
+        let (signature, recovery_id) = alice
+            .sign_prehash_recoverable(tx.hash().as_slice())
+            .unwrap();
+        let tx = SignedTransaction::new(tx, signature, recovery_id);
+        txpool.add(tx.hash(), tx).unwrap();
+        let puts = storage.view(|db| db.puts);
+        assert_eq!(
+            miner.try_mine_once().unwrap_err(),
+            MineError::Storage(StorageError::Full)
+        );
+        assert!(storage.view(|db| db.puts) > puts);
+        // Nothing of the block made it: not in the chain, not in the state, still pooled
+        assert_eq!(chain.last_block().id, 1);
+        assert_eq!(storage.state_root(), root);
+        assert_eq!(txpool.get_all_transactions().len(), 1);
+
+        // The heartbeat returns instead of panicking
+        let mining = thread::spawn(move || miner.start_mining());
+        assert!(mining.join().is_ok());
+        assert_eq!(chain.last_block().id, 1);
+    }
+
+    #[test]
+    fn test_miner_under_pow_rules() {
+        let alice = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        let mine_under = |difficulty: u64| {
+            let genesis = GenesisConfig {
+                difficulty,
+                ..Default::default()
+            };
+            let chain = SharedChain::from_genesis(&genesis).with_rules(PowRules);
+            let storage = SharedStorage::new();
+            let account = Account {
+                nonce: 0,
+                balance: U256::from(1_000_000),
+            };
+            storage.set_account(address_of(&alice), account).unwrap();
+            let txpool = SharedTxPool::new();
+            let tx = signed_tx(&alice, 0);
+            txpool.add(tx.hash(), tx).unwrap();
+            let root = storage.state_root();
+            let mut miner = Miner::new(txpool.clone(), storage.clone(), chain.clone());
+            let mined = miner.try_mine_once();
+            (mined, chain, storage, txpool, root)
+        };
+
+        // Every hash meets a difficulty of 1
+        let (mined, chain, _, txpool, _) = mine_under(1);
+        assert_eq!(chain.last_block().k_hash, mined.unwrap().block.k_hash);
+        assert!(txpool.is_empty());
+
+        // The miner doesn't grind: an error, not a panic, and nothing of the block is kept
+        let (mined, chain, storage, txpool, root) = mine_under(u64::MAX);
+        assert_eq!(
+            mined.unwrap_err(),
+            MineError::Chain(ChainError::InvalidBlock(
+                "hash doesn't meet the difficulty".to_string()
+            ))
+        );
+        assert_eq!(chain.last_block().id, 0);
+        assert_eq!(storage.state_root(), root);
+        assert_eq!(txpool.len(), 1);
+    }
+
+    #[test]
+    fn test_instant_miner_stops_on_shutdown() {
+        let (miner, chain) = test_miner();
+        let (stop_miner, shutdown) = mpsc::channel::<()>();
+        let miner = miner
+            .with_seal_mode(SealMode::Instant)
+            .with_shutdown(shutdown);
+        let mining = thread::spawn(move || miner.start_mining());
+
+        // Idle, waiting for transactions: a dropped sender stops it too
+        drop(stop_miner);
+        mining.join().unwrap();
+        assert_eq!(chain.last_block().id, 0);
     }
 }
@@ -1,7 +1,142 @@
-use alloy_primitives::{Address, B256, U256, keccak256};
+use alloy_primitives::{Address, B256, Bloom, U256, keccak256};
 use alloy_rlp::{BufMut, Decodable, Encodable, Error, Header, RlpDecodable, RlpEncodable};
 use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
-use pethit_storage::SimpleStorage;
+use pethit_storage::{Account, StorageBackend, StorageError};
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Gas used by a plain value transfer.
+pub const TRANSFER_GAS: u64 = 21_000;
+
+// Opcodes of the transaction VM. A transaction's `data` is run as code against the storage of
+// its sender, on a stack of 256-bit words. There are no jumps, so a program is checked and priced
+// before it runs (see `code_gas`).
+
+/// Ends the program (so does running out of code).
+pub const OP_STOP: u8 = 0x00;
+/// Pops two words and pushes their sum, wrapping on overflow.
+pub const OP_ADD: u8 = 0x01;
+/// Pops a slot and pushes its value (zero if never written).
+pub const OP_SLOAD: u8 = 0x54;
+/// Pops a slot, then a value, and writes the value to the slot.
+pub const OP_SSTORE: u8 = 0x55;
+/// `OP_PUSH1 + n - 1` pushes the next `n` bytes of code (1 to 32), big endian.
+pub const OP_PUSH1: u8 = 0x60;
+pub const OP_PUSH32: u8 = 0x7f;
+
+/// Deepest the VM stack can get.
+pub const MAX_STACK_DEPTH: usize = 1024;
+
+// Gas per opcode, on top of `TRANSFER_GAS`
+fn opcode_gas(op: u8) -> Option<u64> {
+    match op {
+        OP_STOP => Some(0),
+        OP_ADD => Some(3),
+        OP_SLOAD => Some(100),
+        OP_SSTORE => Some(5_000),
+        OP_PUSH1..=OP_PUSH32 => Some(3),
+        _ => None,
+    }
+}
+
+// How many words an opcode pops and pushes
+fn stack_effect(op: u8) -> (usize, usize) {
+    match op {
+        OP_ADD => (2, 1),
+        OP_SLOAD => (1, 1),
+        OP_SSTORE => (2, 0),
+        OP_PUSH1..=OP_PUSH32 => (0, 1),
+        _ => (0, 0),
+    }
+}
+
+/// Checks `code` and returns the gas running it takes.
+/// Fails on unknown opcodes, a push cut short by the end of the code, and stack under- or overflow.
+pub fn code_gas(code: &[u8]) -> Result<u64, String> {
+    let mut gas = 0;
+    let mut depth = 0;
+    let mut pc = 0;
+    while pc < code.len() {
+        let op = code[pc];
+        let op_gas =
+            opcode_gas(op).ok_or_else(|| format!("unknown opcode {:#04x} at {}", op, pc))?;
+        if op == OP_STOP {
+            break;
+        }
+        let (pops, pushes) = stack_effect(op);
+        if depth < pops {
+            return Err(format!("stack underflow at {}", pc));
+        }
+        depth = depth - pops + pushes;
+        if depth > MAX_STACK_DEPTH {
+            return Err(format!("stack overflow at {}", pc));
+        }
+        gas += op_gas;
+        pc += 1;
+        if (OP_PUSH1..=OP_PUSH32).contains(&op) {
+            pc += (op - OP_PUSH1 + 1) as usize;
+            if pc > code.len() {
+                return Err("push runs past the end of the code".to_string());
+            }
+        }
+    }
+    Ok(gas)
+}
+
+// Runs code that passed `code_gas` against the storage of `address`
+fn run_code<S: StorageBackend + ?Sized>(
+    storage: &mut S,
+    address: Address,
+    code: &[u8],
+) -> Result<(), StorageError> {
+    let mut stack: Vec<U256> = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let op = code[pc];
+        pc += 1;
+        match op {
+            OP_STOP => break,
+            OP_ADD => {
+                let (a, b) = (stack.pop().unwrap(), stack.pop().unwrap());
+                stack.push(a.wrapping_add(b));
+            }
+            OP_SLOAD => {
+                let slot = stack.pop().unwrap();
+                stack.push(storage.get_storage(&address, slot));
+            }
+            OP_SSTORE => {
+                let (slot, value) = (stack.pop().unwrap(), stack.pop().unwrap());
+                storage.set_storage(address, slot, value)?;
+            }
+            OP_PUSH1..=OP_PUSH32 => {
+                let len = (op - OP_PUSH1 + 1) as usize;
+                stack.push(U256::from_be_slice(&code[pc..pc + len]));
+                pc += len;
+            }
+            _ => unreachable!("checked by code_gas"),
+        }
+    }
+    Ok(())
+}
+
+/// The hash function behind transaction and block hashes.
+/// Production code always uses `Keccak`. Tests can plug in one whose output is easy to write by hand.
+pub trait Hasher {
+    fn hash(data: &[u8]) -> B256;
+}
+
+/// keccak256, as in Ethereum.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Keccak;
+
+impl Hasher for Keccak {
+    fn hash(data: &[u8]) -> B256 {
+        keccak256(data)
+    }
+}
 
 /// The "Raw" transaction (The Message).
 /// Data to sign.
@@ -13,27 +148,60 @@ pub struct Transaction {
     pub value: U256,
     /// Replay protection.
     pub nonce: u64,
+    /// Wei paid per unit of gas. The fee goes to the block's coinbase.
+    pub gas_price: U256,
+    /// Code run against the sender's storage (see `OP_STOP` and friends). Empty for a transfer.
+    pub data: Vec<u8>,
 }
 
 impl Transaction {
+    /// Gas the transaction uses: `TRANSFER_GAS` plus the run of its code.
+    /// Invalid code counts for nothing, such a transaction doesn't execute.
+    pub fn gas(&self) -> u64 {
+        TRANSFER_GAS + code_gas(&self.data).unwrap_or(0)
+    }
+
+    /// The fee this transaction pays, for all the gas it uses.
+    pub fn fee(&self) -> U256 {
+        U256::from(self.gas()).saturating_mul(self.gas_price)
+    }
+
     /// Hashes the transaction fields using RLP.
     pub fn hash(&self) -> B256 {
+        self.hash_with::<Keccak>()
+    }
+
+    /// Same as `hash`, with `H` instead of keccak256.
+    pub fn hash_with<H: Hasher>(&self) -> B256 {
         // Encode with RLP.
         let data_encode = alloy_rlp::encode(self);
-        // Hash the RLP data with keccak256.
-        keccak256(data_encode)
+        // Hash the RLP data.
+        H::hash(&data_encode)
     }
 }
 
 /// The "Signed" transaction.
 /// This is what is broadcasted to the network.
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Fields are read-only after signing, so the hash can be computed once and cached.
+#[derive(Debug, Clone)]
 pub struct SignedTransaction {
-    pub transaction: Transaction,
-    pub signature: Signature,    // The math proof (R + S)
-    pub recovery_id: RecoveryId, // The "V" value (needed to recover the public key fast)
+    transaction: Transaction,
+    signature: Signature,    // The math proof (R + S)
+    recovery_id: RecoveryId, // The "V" value (needed to recover the public key fast)
+    hash: OnceLock<B256>,    // Lazily computed by `hash()`
 }
 
+// The cache is derived data, so it is left out of the comparison
+impl PartialEq for SignedTransaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.transaction == other.transaction
+            && self.signature == other.signature
+            && self.recovery_id == other.recovery_id
+    }
+}
+
+impl Eq for SignedTransaction {}
+
 impl Encodable for SignedTransaction {
     fn encode(&self, out: &mut dyn BufMut) {
         let sig_bytes = self.signature.to_bytes();
@@ -110,26 +278,51 @@ impl Decodable for SignedTransaction {
         let recovery_id =
             RecoveryId::from_byte(recid_byte).ok_or(Error::Custom("Invalid recovery id"))?;
 
-        Ok(Self {
+        Ok(Self::new(transaction, signature, recovery_id))
+    }
+}
+
+impl SignedTransaction {
+    pub fn new(transaction: Transaction, signature: Signature, recovery_id: RecoveryId) -> Self {
+        Self {
             transaction,
             signature,
             recovery_id,
-        })
+            hash: OnceLock::new(),
+        }
     }
-}
 
-impl SignedTransaction {
-    /// Calculate the transaction hash (Keccak256 of the RLP encoding)
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    pub fn recovery_id(&self) -> RecoveryId {
+        self.recovery_id
+    }
+
+    /// Calculate the transaction hash (Keccak256 of the RLP encoding).
+    /// Computed on first use and cached afterwards.
     pub fn hash(&self) -> B256 {
-        // Encode self to RLP
-        let mut out = Vec::new();
-        self.encode(&mut out);
-        // Hash it
-        keccak256(&out)
+        *self.hash.get_or_init(|| {
+            // Encode self to RLP
+            let mut out = Vec::new();
+            self.encode(&mut out);
+            // Hash it
+            keccak256(&out)
+        })
     }
 
     // Recovers the Address of the signer.
+    // Only low-S signatures are accepted: the high-S twin of a signature is just as valid
+    // for ECDSA but gives another tx hash, which would get around dedup.
     pub fn recover_sender(&self) -> Result<Address, String> {
+        if self.signature.normalize_s().is_some() {
+            return Err("Invalid signature: high S".to_string());
+        }
         let tx_hash = self.transaction.hash();
 
         // Recover the Public Key from the signature and the message hash
@@ -148,6 +341,233 @@ impl SignedTransaction {
     }
 }
 
+/// Returns the low-S form of a signature, with the recovery id adjusted to match.
+/// Signers should run their output through this before building a `SignedTransaction`.
+pub fn normalize_signature(
+    signature: Signature,
+    recovery_id: RecoveryId,
+) -> (Signature, RecoveryId) {
+    match signature.normalize_s() {
+        // Negating S flips the parity of the recovered point
+        Some(low_s) => (
+            low_s,
+            RecoveryId::new(!recovery_id.is_y_odd(), recovery_id.is_x_reduced()),
+        ),
+        None => (signature, recovery_id),
+    }
+}
+
+/// An event emitted during execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Log {
+    /// Who emitted the event.
+    pub address: Address,
+    /// Indexed data. The first topic identifies the event.
+    pub topics: Vec<B256>,
+    /// Non-indexed data.
+    pub data: Vec<u8>,
+}
+
+impl Log {
+    /// The log of a value move, shaped like an ERC-20 `Transfer` event:
+    /// topics = [event signature, from, to], data = value.
+    pub fn transfer(from: Address, to: Address, value: U256) -> Self {
+        Self {
+            address: from,
+            topics: vec![
+                keccak256("Transfer(address,address,uint256)"),
+                from.into_word(),
+                to.into_word(),
+            ],
+            data: value.to_be_bytes::<32>().to_vec(),
+        }
+    }
+}
+
+/// The outcome of an executed transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub tx_hash: B256,
+    pub gas_used: u64,
+    /// Wei paid per unit of gas.
+    pub effective_gas_price: U256,
+    pub logs: Vec<Log>,
+}
+
+impl Receipt {
+    /// What the sender paid for the gas.
+    pub fn fee(&self) -> U256 {
+        U256::from(self.gas_used).saturating_mul(self.effective_gas_price)
+    }
+}
+
+/// An account written by a traced execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateWrite {
+    pub address: Address,
+    pub before: Account,
+    pub after: Account,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceChange {
+    pub address: Address,
+    pub before: U256,
+    pub after: U256,
+}
+
+/// What a traced execution touched, in the order it was first read and by address for writes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub reads: Vec<Address>,
+    pub writes: Vec<StateWrite>,
+    pub balance_changes: Vec<BalanceChange>,
+}
+
+/// What the block producer sets for the transactions of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockEnv {
+    /// Credited with the reward and the tips.
+    pub coinbase: Address,
+    /// Transactions priced below it are skipped. What they pay up to it is burned.
+    pub base_fee: U256,
+    /// Wei minted to the coinbase.
+    pub reward: U256,
+}
+
+/// Everything a block header needs from executing its transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockExecutionResult {
+    /// The transactions that made it, in order. Invalid ones are left out.
+    pub transactions: Vec<SignedTransaction>,
+    pub receipts: Vec<Receipt>,
+    /// Root of the state once the block is applied, coinbase payment included.
+    pub state_root: B256,
+    pub gas_used: u64,
+    pub logs_bloom: Bloom,
+    /// The entries the block wrote, in key order (applied to the storage by `execute_block`).
+    pub writes: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The transactions left out, in order, with why.
+    pub skipped: Vec<(SignedTransaction, ExecError)>,
+}
+
+/// Bloom over the addresses and topics of the logs in `receipts` (Ethereum-style).
+pub fn logs_bloom(receipts: &[Receipt]) -> Bloom {
+    let mut bloom = Bloom::ZERO;
+    for log in receipts.iter().flat_map(|receipt| &receipt.logs) {
+        bloom.accrue_raw_log(log.address, &log.topics);
+    }
+    bloom
+}
+
+// Backend that reads through to `base` and keeps the writes to itself, recording both.
+struct TracingOverlay<'a, S: ?Sized> {
+    base: &'a S,
+    // Sorted, so the trace is deterministic
+    writes: BTreeMap<Vec<u8>, Vec<u8>>,
+    // Interior mutability: reads go through `&self`
+    reads: RefCell<Vec<Vec<u8>>>,
+}
+
+impl<'a, S: ?Sized> TracingOverlay<'a, S> {
+    fn new(base: &'a S) -> Self {
+        TracingOverlay {
+            base,
+            writes: BTreeMap::new(),
+            reads: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl<S: StorageBackend + ?Sized> StorageBackend for TracingOverlay<'_, S> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut reads = self.reads.borrow_mut();
+        if !reads.iter().any(|read| read == key) {
+            reads.push(key.to_vec());
+        }
+        match self.writes.get(key) {
+            Some(value) => Some(value.clone()),
+            None => self.base.get(key),
+        }
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError> {
+        self.writes.insert(key, value);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.writes.remove(key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+        let base = self
+            .base
+            .iter()
+            .filter(|(key, _)| !self.writes.contains_key(*key));
+        let writes = self
+            .writes
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_slice()));
+        Box::new(base.chain(writes))
+    }
+}
+
+/// Why a transaction can't be executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecError {
+    /// No sender can be recovered from the signature.
+    InvalidSignature(String),
+    InvalidNonce {
+        expected: u64,
+        got: u64,
+    },
+    /// The sender can't cover value + fee. Accounts never seen before have a zero balance.
+    InsufficientBalance,
+    /// Crediting the recipient would take its balance past `U256::MAX`.
+    BalanceOverflow,
+    /// The data doesn't pass `code_gas`.
+    InvalidCode(String),
+    /// The gas price is below the base fee of the block.
+    BelowBaseFee {
+        gas_price: U256,
+        base_fee: U256,
+    },
+    /// The storage refused a write (e.g. it's full). Not the transaction's fault.
+    Storage(StorageError),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::InvalidSignature(reason) => write!(f, "{}", reason),
+            ExecError::InvalidNonce { expected, got } => {
+                write!(f, "Invalid nonce. Expected {}, got {}", expected, got)
+            }
+            ExecError::InsufficientBalance => write!(f, "Insufficient funds"),
+            ExecError::BalanceOverflow => write!(f, "Recipient balance overflow"),
+            ExecError::InvalidCode(reason) => write!(f, "Invalid code: {}", reason),
+            ExecError::BelowBaseFee {
+                gas_price,
+                base_fee,
+            } => write!(
+                f,
+                "Gas price {} is below the base fee of {}",
+                gas_price, base_fee
+            ),
+            ExecError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+impl From<StorageError> for ExecError {
+    fn from(e: StorageError) -> Self {
+        ExecError::Storage(e)
+    }
+}
+
 #[derive(Debug)]
 // The ExecutionEngine holds no state/data, it only holds the logic.
 pub struct ExecutionEngine;
@@ -164,37 +584,346 @@ impl ExecutionEngine {
         ExecutionEngine {}
     }
 
-    /// Verifies the signature and executes the transaction.
-    pub fn execute(storage: &mut SimpleStorage, tx: &SignedTransaction) -> Result<(), String> {
+    /// `Executor::validate` on `storage`.
+    pub fn validate<S: StorageBackend + ?Sized>(
+        storage: &S,
+        tx: &SignedTransaction,
+    ) -> Result<Address, ExecError> {
+        // Read-only: the overlay takes no write
+        Executor::new(TracingOverlay::new(storage)).validate(tx)
+    }
+
+    /// `Executor::execute` on `storage`.
+    pub fn execute<S: StorageBackend + ?Sized>(
+        storage: &mut S,
+        tx: &SignedTransaction,
+    ) -> Result<Receipt, ExecError> {
+        Executor::new(storage).execute(tx)
+    }
+
+    /// `Executor::trace` on `storage`, which is left as is.
+    pub fn trace<S: StorageBackend + ?Sized>(
+        storage: &S,
+        tx: &SignedTransaction,
+    ) -> (Result<Receipt, ExecError>, Trace) {
+        Executor::new(TracingOverlay::new(storage)).trace(tx)
+    }
+
+    /// `Executor::execute_batch` on `storage`.
+    pub fn execute_batch<S: StorageBackend + ?Sized>(
+        storage: &mut S,
+        txs: &[SignedTransaction],
+    ) -> Vec<Result<Receipt, ExecError>> {
+        Executor::new(storage).execute_batch(txs)
+    }
+
+    /// Runs the transactions of a block and pays its coinbase the reward plus the tips, all on
+    /// an overlay of `storage`. The writes are committed only once the whole block went through:
+    /// on error `storage` is left as it was, unless it refuses one of those writes
+    /// (`ExecError::Storage`), which leaves the ones before it applied.
+    ///
+    /// Invalid transactions are skipped (they don't touch the state) and reported in `skipped`.
+    pub fn execute_block<S: StorageBackend + ?Sized>(
+        storage: &mut S,
+        txs: Vec<SignedTransaction>,
+        env: &BlockEnv,
+    ) -> Result<BlockExecutionResult, ExecError> {
+        let result = Self::simulate_block(&*storage, txs, env)?;
+        for (key, value) in &result.writes {
+            storage.put(key.clone(), value.clone())?;
+        }
+        Ok(result)
+    }
+
+    /// Like `execute_block`, leaving `storage` as is: committing the block is up to the
+    /// caller, by applying `BlockExecutionResult::writes`.
+    pub fn simulate_block<S: StorageBackend + ?Sized>(
+        storage: &S,
+        txs: Vec<SignedTransaction>,
+        env: &BlockEnv,
+    ) -> Result<BlockExecutionResult, ExecError> {
+        let mut overlay = TracingOverlay::new(storage);
+        let mut transactions = Vec::new();
+        let mut receipts = Vec::new();
+        let mut skipped = Vec::new();
+        let mut tips = U256::ZERO;
+        for tx in txs {
+            let gas_price = tx.transaction.gas_price;
+            if gas_price < env.base_fee {
+                let e = ExecError::BelowBaseFee {
+                    gas_price,
+                    base_fee: env.base_fee,
+                };
+                skipped.push((tx, e));
+                continue;
+            }
+            match Self::execute(&mut overlay, &tx) {
+                Ok(receipt) => {
+                    // The base fee part is burned, only the tip goes to the miner
+                    let tip = (receipt.effective_gas_price - env.base_fee)
+                        .saturating_mul(U256::from(receipt.gas_used));
+                    tips = tips.saturating_add(tip);
+                    transactions.push(tx);
+                    receipts.push(receipt);
+                }
+                // The overlay takes any write, but a failing backend can't be skipped past
+                Err(ExecError::Storage(e)) => return Err(ExecError::Storage(e)),
+                Err(e) => skipped.push((tx, e)),
+            }
+        }
+
+        // Pay the miner: the fees were already debited from the senders (burned part included)
+        let mut coinbase_account = overlay.get_account(&env.coinbase);
+        coinbase_account.balance = coinbase_account
+            .balance
+            .checked_add(env.reward)
+            .and_then(|balance| balance.checked_add(tips))
+            .ok_or(ExecError::BalanceOverflow)?;
+        overlay.set_account(env.coinbase, coinbase_account)?;
+
+        // The root is taken on the overlay, so it's the state applying the writes produces
+        let state_root = overlay.state_root();
+        let writes: Vec<_> = overlay.writes.into_iter().collect();
+        Ok(BlockExecutionResult {
+            gas_used: receipts.iter().map(|receipt| receipt.gas_used).sum(),
+            logs_bloom: logs_bloom(&receipts),
+            transactions,
+            receipts,
+            state_root,
+            writes,
+            skipped,
+        })
+    }
+
+    /// Same outcome as `execute_batch`, but transactions that share no account run concurrently.
+    ///
+    /// Transactions are grouped by the accounts they touch (sender and recipient): any two that
+    /// share one land in the same group and keep their relative order. Each group runs serially
+    /// on its own overlay of `storage`, then the write sets are merged in group order.
+    pub fn execute_batch_parallel<S: StorageBackend + Sync + ?Sized>(
+        storage: &mut S,
+        txs: &[SignedTransaction],
+    ) -> Vec<Result<Receipt, ExecError>> {
+        let senders: Vec<_> = txs.par_iter().map(|tx| tx.recover_sender().ok()).collect();
+
+        // Union-find over the transaction indices, linked through the accounts they touch
+        let mut parent: Vec<usize> = (0..txs.len()).collect();
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        let mut owner: HashMap<Address, usize> = HashMap::new();
+        for (i, (tx, sender)) in txs.iter().zip(&senders).enumerate() {
+            // An unrecoverable sender fails without touching anything
+            let Some(sender) = sender else { continue };
+            for account in [*sender, tx.transaction.to] {
+                match owner.get(&account) {
+                    Some(&j) => {
+                        let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                        parent[a.max(b)] = a.min(b);
+                    }
+                    None => {
+                        owner.insert(account, i);
+                    }
+                }
+            }
+        }
+        let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for i in 0..txs.len() {
+            let root = find(&mut parent, i);
+            groups.entry(root).or_default().push(i);
+        }
+
+        let base = &*storage;
+        let outcomes: Vec<_> = groups
+            .into_values()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|group| {
+                let mut overlay = TracingOverlay::new(base);
+                let results: Vec<_> = group
+                    .iter()
+                    .map(|&i| (i, Self::execute(&mut overlay, &txs[i])))
+                    .collect();
+                (results, overlay.writes)
+            })
+            .collect();
+
+        let mut results: Vec<Option<Result<Receipt, ExecError>>> = vec![None; txs.len()];
+        for (group_results, writes) in outcomes {
+            // A group whose writes the storage refuses reports the failure for each of its
+            // transactions
+            let committed = writes
+                .into_iter()
+                .try_for_each(|(key, value)| storage.put(key, value));
+            for (i, result) in group_results {
+                results[i] = Some(match &committed {
+                    Ok(()) => result,
+                    Err(e) => Err(ExecError::Storage(e.clone())),
+                });
+            }
+        }
+        results.into_iter().flatten().collect()
+    }
+}
+
+/// An execution engine bound to a state: runs transactions against any `StorageBackend`
+/// (a node's storage, a simulation's scratch state, ...) it owns.
+///
+/// Pass `&mut backend` to keep ownership, e.g. `Executor::new(&mut storage)`.
+#[derive(Debug, Clone, Default)]
+pub struct Executor<S> {
+    state: S,
+}
+
+impl<S: StorageBackend> Executor<S> {
+    pub fn new(state: S) -> Self {
+        Executor { state }
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut S {
+        &mut self.state
+    }
+
+    pub fn into_state(self) -> S {
+        self.state
+    }
+
+    /// The account at `address` (zero nonce and balance if it was never seen).
+    pub fn account(&self, address: &Address) -> Account {
+        self.state.get_account(address)
+    }
+
+    /// Checks the admission rules (signature, code, nonce and balance) without touching the state.
+    /// Returns the recovered sender.
+    pub fn validate(&self, tx: &SignedTransaction) -> Result<Address, ExecError> {
         // Verify Signature & Recover Sender address.
-        let sender = tx.recover_sender()?;
-        // Get sender's account data.
-        let mut sender_account = storage.get_account(&sender);
+        let sender = tx.recover_sender().map_err(ExecError::InvalidSignature)?;
+        code_gas(&tx.transaction.data).map_err(ExecError::InvalidCode)?;
+        // Get sender's account data. A sender never seen before reads as nonce 0, balance 0
+        let sender_account = self.state.get_account(&sender);
         // Confirm correct Nonce and enough sender balance
         if tx.transaction.nonce != sender_account.nonce {
-            return Err(format!(
-                "Invalid nonce. Expected {}, got {}",
-                sender_account.nonce, tx.transaction.nonce
-            ));
+            return Err(ExecError::InvalidNonce {
+                expected: sender_account.nonce,
+                got: tx.transaction.nonce,
+            });
         }
-        if tx.transaction.value > sender_account.balance {
-            return Err("Insufficient funds".to_string());
+        // The sender must cover both the value and the fee
+        let cost = tx.transaction.value.checked_add(tx.transaction.fee());
+        if cost.is_none_or(|cost| cost > sender_account.balance) {
+            return Err(ExecError::InsufficientBalance);
         }
-        // Debit sender
-        sender_account.nonce += 1;
-        sender_account.balance -= tx.transaction.value;
-        // Update the balance in storage for sender
-        storage.set_account(sender, sender_account);
 
-        // Recover Receiver address.
+        Ok(sender)
+    }
+
+    /// Verifies the signature and executes the transaction.
+    /// Returns the receipt with the logs emitted along the way.
+    ///
+    /// The fee is debited from the sender here. Crediting it is up to the block producer.
+    ///
+    /// Every executed transaction uses a nonce and pays the fee, including:
+    /// - Self-transfers: the value doesn't move, only the nonce and the fee change the account.
+    /// - Zero-value transfers: allowed on purpose, since transactions will carry data.
+    ///
+    /// A storage that refuses a write (`ExecError::Storage`) can be left with part of the
+    /// transaction applied. `ExecutionEngine::execute_block` runs on an overlay, so blocks can't.
+    pub fn execute(&mut self, tx: &SignedTransaction) -> Result<Receipt, ExecError> {
+        let sender = self.validate(tx)?;
         let receiver = tx.transaction.to;
-        // Debit receiver.
-        let mut receiver_account = storage.get_account(&receiver);
-        receiver_account.balance += tx.transaction.value;
-        // Update the balance in storage receiver
-        storage.set_account(receiver, receiver_account);
+        let value = tx.transaction.value;
+        let mut logs = Vec::new();
 
-        Ok(())
+        // Checked all the way: a state that can't take the transaction refuses it instead of
+        // wrapping. Nothing is written before every balance is known to be in range
+        let mut sender_account = self.state.get_account(&sender);
+        sender_account.nonce += 1;
+        sender_account.balance = sender_account
+            .balance
+            .checked_sub(tx.transaction.fee())
+            .ok_or(ExecError::InsufficientBalance)?;
+
+        if receiver == sender {
+            // The value doesn't move, debit and credit cancel out
+            self.state.set_account(sender, sender_account)?;
+        } else {
+            // Debit sender
+            sender_account.balance = sender_account
+                .balance
+                .checked_sub(value)
+                .ok_or(ExecError::InsufficientBalance)?;
+
+            // Credit receiver.
+            let mut receiver_account = self.state.get_account(&receiver);
+            receiver_account.balance = receiver_account
+                .balance
+                .checked_add(value)
+                .ok_or(ExecError::BalanceOverflow)?;
+
+            self.state.set_account(sender, sender_account)?;
+            self.state.set_account(receiver, receiver_account)?;
+        }
+        logs.push(Log::transfer(sender, receiver, value));
+        // The code was checked by `validate`, it can't fail halfway
+        run_code(&mut self.state, sender, &tx.transaction.data)?;
+
+        Ok(Receipt {
+            tx_hash: tx.hash(),
+            gas_used: tx.transaction.gas(),
+            effective_gas_price: tx.transaction.gas_price,
+            logs,
+        })
+    }
+
+    /// Executes the transaction against a throwaway overlay of the state (which is left as is)
+    /// and reports what it read, wrote and which balances moved.
+    pub fn trace(&self, tx: &SignedTransaction) -> (Result<Receipt, ExecError>, Trace) {
+        let mut overlay = TracingOverlay::new(&self.state);
+        let result = Executor::new(&mut overlay).execute(tx);
+
+        // Only account entries are traced, not storage slots
+        let is_account = |key: &&Vec<u8>| key.len() == Address::len_bytes();
+        let mut trace = Trace {
+            reads: overlay
+                .reads
+                .borrow()
+                .iter()
+                .filter(is_account)
+                .map(|key| Address::from_slice(key))
+                .collect(),
+            ..Default::default()
+        };
+        for key in overlay.writes.keys().filter(is_account) {
+            let address = Address::from_slice(key);
+            let before = self.state.get_account(&address);
+            let after = overlay.get_account(&address);
+            if before.balance != after.balance {
+                trace.balance_changes.push(BalanceChange {
+                    address,
+                    before: before.balance,
+                    after: after.balance,
+                });
+            }
+            trace.writes.push(StateWrite {
+                address,
+                before,
+                after,
+            });
+        }
+        (result, trace)
+    }
+
+    /// Executes `txs` in order, one after the other. Failed transactions leave the state as is.
+    pub fn execute_batch(&mut self, txs: &[SignedTransaction]) -> Vec<Result<Receipt, ExecError>> {
+        txs.iter().map(|tx| self.execute(tx)).collect()
     }
 }
 
@@ -202,6 +931,45 @@ impl ExecutionEngine {
 mod tests {
     use super::*;
     use k256::ecdsa::SigningKey;
+    use pethit_storage::SimpleStorage;
+
+    fn test_key() -> SigningKey {
+        SigningKey::from_bytes(&[1u8; 32].into()).expect("Invalid bytes")
+    }
+
+    fn address_of(signing_key: &SigningKey) -> Address {
+        let pub_bytes = signing_key.verifying_key().to_encoded_point(false);
+        let pub_hash = keccak256(&pub_bytes.as_bytes()[1..]);
+        Address::from_slice(&pub_hash[12..])
+    }
+
+    // Helper to sign a transfer with the test key
+    fn signed_tx(to: Address, value: u64, nonce: u64) -> SignedTransaction {
+        let tx = Transaction {
+            to,
+            value: U256::from(value),
+            nonce,
+            gas_price: U256::from(1),
+            data: Vec::new(),
+        };
+        let (signature, recovery_id) = test_key()
+            .sign_prehash_recoverable(tx.hash().as_slice())
+            .expect("signing failed");
+        SignedTransaction::new(tx, signature, recovery_id)
+    }
+
+    // Helper to create a storage where the test key holds `balance`
+    fn funded_storage(balance: u64) -> SimpleStorage {
+        let mut storage = SimpleStorage::new();
+        let account = Account {
+            nonce: 0,
+            balance: U256::from(balance),
+        };
+        storage
+            .set_account(address_of(&test_key()), account)
+            .unwrap();
+        storage
+    }
 
     #[test]
     fn test_valid_signature_recovery() {
@@ -220,6 +988,8 @@ mod tests {
             to: Address::ZERO,
             value: U256::from(100),
             nonce: 0,
+            gas_price: U256::from(1),
+            data: Vec::new(),
         };
 
         // Sign it
@@ -228,14 +998,455 @@ mod tests {
             .sign_prehash_recoverable(tx_hash.as_slice())
             .expect("signing failed");
 
-        let signed_tx = SignedTransaction {
-            transaction: tx,
-            signature,
-            recovery_id,
-        };
+        let signed_tx = SignedTransaction::new(tx, signature, recovery_id);
 
         // Verify
         let recovered_sender = signed_tx.recover_sender().unwrap();
         assert_eq!(recovered_sender, expected_sender);
     }
+
+    #[test]
+    fn test_cached_hash_matches_fresh_computation() {
+        let signing_key = SigningKey::from_bytes(&[1u8; 32].into()).expect("Invalid bytes");
+        let tx = Transaction {
+            to: Address::ZERO,
+            value: U256::from(100),
+            nonce: 0,
+            gas_price: U256::from(1),
+            data: Vec::new(),
+        };
+        let (signature, recovery_id) = signing_key
+            .sign_prehash_recoverable(tx.hash().as_slice())
+            .expect("signing failed");
+        let signed_tx = SignedTransaction::new(tx, signature, recovery_id);
+
+        // First call fills the cache, second one reads it
+        let first = signed_tx.hash();
+        let cached = signed_tx.hash();
+
+        let fresh = keccak256(alloy_rlp::encode(&signed_tx));
+        assert_eq!(first, fresh);
+        assert_eq!(cached, fresh);
+    }
+
+    #[test]
+    fn test_transfer_emits_log() {
+        let mut storage = funded_storage(1_000_000);
+        let sender = address_of(&test_key());
+        let receiver = Address::with_last_byte(7);
+        let tx = signed_tx(receiver, 100, 0);
+
+        let receipt = ExecutionEngine::execute(&mut storage, &tx).unwrap();
+
+        assert_eq!(receipt.tx_hash, tx.hash());
+        assert_eq!(receipt.logs.len(), 1);
+        let log = &receipt.logs[0];
+        assert_eq!(log.address, sender);
+        assert_eq!(log.topics[1], sender.into_word());
+        assert_eq!(log.topics[2], receiver.into_word());
+        assert_eq!(U256::from_be_slice(&log.data), U256::from(100));
+    }
+
+    #[test]
+    fn test_self_transfer_only_increments_nonce() {
+        let mut storage = funded_storage(1_000_000);
+        let sender = address_of(&test_key());
+
+        ExecutionEngine::execute(&mut storage, &signed_tx(sender, 300, 0)).unwrap();
+
+        // Only the fee is gone
+        let account = storage.get_account(&sender);
+        assert_eq!(account.balance, U256::from(1_000_000 - TRANSFER_GAS));
+        assert_eq!(account.nonce, 1);
+    }
+
+    #[test]
+    fn test_zero_value_transfer_increments_nonce() {
+        let mut storage = funded_storage(1_000_000);
+        let sender = address_of(&test_key());
+        let receiver = Address::with_last_byte(7);
+
+        ExecutionEngine::execute(&mut storage, &signed_tx(receiver, 0, 0)).unwrap();
+
+        let account = storage.get_account(&sender);
+        assert_eq!(account.balance, U256::from(1_000_000 - TRANSFER_GAS));
+        assert_eq!(account.nonce, 1);
+        assert_eq!(storage.get_account(&receiver).balance, U256::ZERO);
+    }
+
+    #[test]
+    fn test_fee_is_debited_and_reported() {
+        let mut storage = funded_storage(1_000_000);
+        let sender = address_of(&test_key());
+        let receiver = Address::with_last_byte(7);
+
+        let receipt = ExecutionEngine::execute(&mut storage, &signed_tx(receiver, 100, 0)).unwrap();
+
+        assert_eq!(receipt.gas_used, TRANSFER_GAS);
+        assert_eq!(receipt.fee(), U256::from(TRANSFER_GAS));
+        let account = storage.get_account(&sender);
+        assert_eq!(account.balance, U256::from(1_000_000 - 100 - TRANSFER_GAS));
+        assert_eq!(storage.get_account(&receiver).balance, U256::from(100));
+    }
+
+    #[test]
+    fn test_value_plus_fee_must_be_covered() {
+        // Enough for the value alone, not for value + fee
+        let mut storage = funded_storage(TRANSFER_GAS + 99);
+        let tx = signed_tx(Address::with_last_byte(7), 100, 0);
+
+        let result = ExecutionEngine::execute(&mut storage, &tx);
+
+        assert_eq!(result, Err(ExecError::InsufficientBalance));
+    }
+
+    #[test]
+    fn test_first_transaction_of_a_new_account() {
+        let sender = address_of(&test_key());
+        let tx = signed_tx(Address::with_last_byte(7), 100, 0);
+
+        // Never seen: zero balance, nothing gets written
+        let mut storage = SimpleStorage::new();
+        let result = ExecutionEngine::execute(&mut storage, &tx);
+        assert_eq!(result, Err(ExecError::InsufficientBalance));
+        assert!(storage.get(sender.as_slice()).is_none());
+
+        // Funded but never used: nonce 0 comes first
+        let mut storage = funded_storage(1_000_000);
+        assert_eq!(
+            ExecutionEngine::execute(&mut storage, &signed_tx(Address::ZERO, 1, 1)),
+            Err(ExecError::InvalidNonce {
+                expected: 0,
+                got: 1
+            })
+        );
+        ExecutionEngine::execute(&mut storage, &tx).unwrap();
+        assert_eq!(storage.get_account(&sender).nonce, 1);
+    }
+
+    #[test]
+    fn test_high_s_signature_is_rejected() {
+        let low = signed_tx(Address::ZERO, 100, 0);
+        let (r, s) = low.signature().split_scalars();
+        // Same signature with S mirrored (n - s), recovery parity flipped
+        let high_signature = Signature::from_scalars(r.to_bytes(), (-*s).to_bytes()).unwrap();
+        let high_recovery_id = RecoveryId::new(
+            !low.recovery_id().is_y_odd(),
+            low.recovery_id().is_x_reduced(),
+        );
+        let high =
+            SignedTransaction::new(low.transaction().clone(), high_signature, high_recovery_id);
+        assert_ne!(high.hash(), low.hash());
+
+        assert_eq!(
+            high.recover_sender(),
+            Err("Invalid signature: high S".to_string())
+        );
+        assert_eq!(low.recover_sender(), Ok(address_of(&test_key())));
+
+        // Normalizing brings back the accepted form
+        let (signature, recovery_id) = normalize_signature(high_signature, high_recovery_id);
+        assert_eq!(signature, *low.signature());
+        assert_eq!(recovery_id, low.recovery_id());
+    }
+
+    #[test]
+    fn test_trace_reports_transfer() {
+        let storage = funded_storage(1_000_000);
+        let sender = address_of(&test_key());
+        let receiver = Address::with_last_byte(7);
+
+        let (result, trace) = ExecutionEngine::trace(&storage, &signed_tx(receiver, 100, 0));
+
+        assert!(result.is_ok());
+        assert_eq!(trace.reads, vec![sender, receiver]);
+        let balance = 1_000_000 - 100 - TRANSFER_GAS;
+        assert_eq!(
+            trace.balance_changes,
+            vec![
+                BalanceChange {
+                    address: receiver,
+                    before: U256::ZERO,
+                    after: U256::from(100),
+                },
+                BalanceChange {
+                    address: sender,
+                    before: U256::from(1_000_000),
+                    after: U256::from(balance),
+                },
+            ]
+        );
+        // The nonce write of the sender
+        let sender_write = trace.writes.iter().find(|w| w.address == sender).unwrap();
+        assert_eq!(
+            (sender_write.before.nonce, sender_write.after.nonce),
+            (0, 1)
+        );
+        // Nothing was committed
+        assert_eq!(storage.get_account(&sender).nonce, 0);
+    }
+
+    #[test]
+    fn test_parallel_batch_matches_serial() {
+        let keys: Vec<_> = (1..=4u8)
+            .map(|byte| SigningKey::from_bytes(&[byte; 32].into()).unwrap())
+            .collect();
+        let funded = || {
+            let mut storage = SimpleStorage::new();
+            for key in &keys {
+                let account = Account {
+                    nonce: 0,
+                    balance: U256::from(1_000_000),
+                };
+                storage.set_account(address_of(key), account).unwrap();
+            }
+            storage
+        };
+        let sign = |key: &SigningKey, to: Address, value: u64, nonce: u64| {
+            let tx = Transaction {
+                to,
+                value: U256::from(value),
+                nonce,
+                gas_price: U256::from(1),
+                data: Vec::new(),
+            };
+            let (signature, recovery_id) = key
+                .sign_prehash_recoverable(tx.hash().as_slice())
+                .expect("signing failed");
+            SignedTransaction::new(tx, signature, recovery_id)
+        };
+        let [a, b, c, d] = [0, 1, 2, 3].map(|i| address_of(&keys[i]));
+        let txs = vec![
+            // Independent transfers
+            sign(&keys[0], Address::with_last_byte(1), 100, 0),
+            sign(&keys[1], Address::with_last_byte(2), 200, 0),
+            // c pays d, then d spends more than it had before receiving it
+            sign(&keys[2], d, 500_000, 0),
+            sign(&keys[3], c, 1_200_000, 0),
+            // Wrong nonce, fails in both
+            sign(&keys[1], a, 1, 5),
+            // a's second transaction, now touching b
+            sign(&keys[0], b, 300, 1),
+        ];
+
+        let mut serial = funded();
+        let mut storage = funded();
+        let expected = ExecutionEngine::execute_batch(&mut serial, &txs);
+        let results = ExecutionEngine::execute_batch_parallel(&mut storage, &txs);
+
+        assert_eq!(results, expected);
+        assert!(results[3].is_ok());
+        assert!(results[4].is_err());
+        assert_eq!(storage.state_root(), serial.state_root());
+    }
+
+    // A backend from outside pethit-storage, like a simulation server would bring
+    #[derive(Default)]
+    struct MapBackend(HashMap<Vec<u8>, Vec<u8>>);
+
+    impl StorageBackend for MapBackend {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.0.get(key).cloned()
+        }
+
+        fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError> {
+            self.0.insert(key, value);
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+            self.0.remove(key)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+            Box::new(self.0.iter().map(|(k, v)| (k.as_slice(), v.as_slice())))
+        }
+    }
+
+    #[test]
+    fn test_executor_runs_against_custom_backend() {
+        let sender = address_of(&test_key());
+        let receiver = Address::repeat_byte(0x22);
+        let mut backend = MapBackend::default();
+        let funded = Account {
+            nonce: 0,
+            balance: U256::from(100_000),
+        };
+        backend.set_account(sender, funded).unwrap();
+
+        let mut executor = Executor::new(backend);
+        let tx = signed_tx(receiver, 500, 0);
+        assert_eq!(executor.validate(&tx), Ok(sender));
+        let receipt = executor.execute(&tx).unwrap();
+        assert_eq!(receipt.gas_used, TRANSFER_GAS);
+        assert_eq!(executor.account(&receiver).balance, U256::from(500));
+        assert_eq!(executor.account(&sender).nonce, 1);
+        // The nonce was used
+        assert!(executor.execute(&tx).is_err());
+
+        // Borrowing works the same, and leaves the backend with the caller
+        let mut backend = executor.into_state();
+        let next = signed_tx(receiver, 1, 1);
+        let (result, trace) = Executor::new(&mut backend).trace(&next);
+        assert!(result.is_ok());
+        assert_eq!(trace.balance_changes.len(), 2);
+        assert_eq!(backend.get_account(&receiver).balance, U256::from(500));
+        Executor::new(&mut backend).execute(&next).unwrap();
+        assert_eq!(backend.get_account(&receiver).balance, U256::from(501));
+    }
+
+    #[test]
+    fn test_credit_overflow_is_an_error() {
+        let receiver = Address::repeat_byte(0x22);
+        let mut storage = funded_storage(100_000);
+        let nearly_full = Account {
+            nonce: 0,
+            balance: U256::MAX - U256::from(10),
+        };
+        storage.set_account(receiver, nearly_full.clone()).unwrap();
+        let sender_before = storage.get_account(&address_of(&test_key()));
+
+        let tx = signed_tx(receiver, 11, 0);
+        let result = ExecutionEngine::execute(&mut storage, &tx);
+
+        assert_eq!(result.unwrap_err(), ExecError::BalanceOverflow);
+        // Neither side moved, and the balance didn't wrap to a small number
+        assert_eq!(storage.get_account(&receiver), nearly_full);
+        assert_eq!(storage.get_account(&address_of(&test_key())), sender_before);
+
+        // Exactly up to the max is fine
+        let tx = signed_tx(receiver, 10, 0);
+        ExecutionEngine::execute(&mut storage, &tx).unwrap();
+        assert_eq!(storage.get_account(&receiver).balance, U256::MAX);
+    }
+
+    #[test]
+    fn test_execute_block_commits_its_state_root() {
+        let mut storage = funded_storage(1_000_000);
+        let coinbase = Address::with_last_byte(0xcb);
+        let env = BlockEnv {
+            coinbase,
+            base_fee: U256::from(1),
+            reward: U256::from(500),
+        };
+        let txs = vec![
+            signed_tx(Address::with_last_byte(1), 100, 0),
+            // Wrong nonce, skipped
+            signed_tx(Address::with_last_byte(2), 100, 5),
+            signed_tx(Address::with_last_byte(2), 200, 1),
+        ];
+
+        let result = ExecutionEngine::execute_block(&mut storage, txs.clone(), &env).unwrap();
+
+        assert_eq!(result.transactions, vec![txs[0].clone(), txs[2].clone()]);
+        let nonce_error = ExecError::InvalidNonce {
+            expected: 1,
+            got: 5,
+        };
+        assert_eq!(result.skipped, vec![(txs[1].clone(), nonce_error)]);
+        assert_eq!(result.receipts.len(), 2);
+        assert_eq!(result.gas_used, 2 * TRANSFER_GAS);
+        assert_eq!(result.logs_bloom, logs_bloom(&result.receipts));
+        assert_eq!(result.state_root, storage.state_root());
+        // Priced at the base fee: no tips, only the reward
+        assert_eq!(storage.get_account(&coinbase).balance, U256::from(500));
+    }
+
+    #[test]
+    fn test_execute_block_leaves_state_on_error() {
+        let mut storage = funded_storage(1_000_000);
+        let coinbase = Address::with_last_byte(0xcb);
+        storage
+            .set_account(
+                coinbase,
+                Account {
+                    nonce: 0,
+                    balance: U256::MAX,
+                },
+            )
+            .unwrap();
+        let root = storage.state_root();
+        let env = BlockEnv {
+            coinbase,
+            base_fee: U256::ZERO,
+            reward: U256::from(1),
+        };
+
+        let txs = vec![signed_tx(Address::with_last_byte(1), 100, 0)];
+        let result = ExecutionEngine::execute_block(&mut storage, txs, &env);
+
+        assert_eq!(result, Err(ExecError::BalanceOverflow));
+        // The transfer that went through before the coinbase payment isn't committed either
+        assert_eq!(storage.state_root(), root);
+    }
+
+    // Helper to sign a zero-value self-transfer carrying `code`
+    fn code_tx(code: Vec<u8>, nonce: u64) -> SignedTransaction {
+        let tx = Transaction {
+            to: address_of(&test_key()),
+            value: U256::ZERO,
+            nonce,
+            gas_price: U256::from(1),
+            data: code,
+        };
+        let (signature, recovery_id) = test_key()
+            .sign_prehash_recoverable(tx.hash().as_slice())
+            .expect("signing failed");
+        SignedTransaction::new(tx, signature, recovery_id)
+    }
+
+    #[test]
+    fn test_code_stores_in_sender_storage() {
+        let mut storage = funded_storage(1_000_000);
+        let sender = address_of(&test_key());
+
+        // PUSH 5; PUSH 7 (the slot); SSTORE
+        let tx = code_tx(vec![OP_PUSH1, 5, OP_PUSH1, 7, OP_SSTORE], 0);
+        let receipt = ExecutionEngine::execute(&mut storage, &tx).unwrap();
+
+        assert_eq!(storage.get_storage(&sender, U256::from(7)), U256::from(5));
+        assert_eq!(storage.get_storage(&sender, U256::from(5)), U256::ZERO);
+        // Two pushes and a store, paid for at the gas price
+        let gas = TRANSFER_GAS + 3 + 3 + 5_000;
+        assert_eq!(receipt.gas_used, gas);
+        assert_eq!(
+            storage.get_account(&sender).balance,
+            U256::from(1_000_000 - gas)
+        );
+
+        // Slot 8 = slot 7 + 2, with a two-byte push
+        let code = vec![
+            OP_PUSH1,
+            7,
+            OP_SLOAD,
+            OP_PUSH1 + 1,
+            0,
+            2,
+            OP_ADD,
+            OP_PUSH1,
+            8,
+            OP_SSTORE,
+        ];
+        ExecutionEngine::execute(&mut storage, &code_tx(code, 1)).unwrap();
+        assert_eq!(storage.get_storage(&sender, U256::from(8)), U256::from(7));
+    }
+
+    #[test]
+    fn test_invalid_code_is_refused() {
+        let mut storage = funded_storage(1_000_000);
+        let root = storage.state_root();
+
+        for (code, reason) in [
+            (vec![OP_SSTORE], "stack underflow at 0"),
+            (vec![0xfe], "unknown opcode 0xfe at 0"),
+            (vec![OP_PUSH1 + 1, 1], "push runs past the end of the code"),
+        ] {
+            let result = ExecutionEngine::execute(&mut storage, &code_tx(code, 0));
+            assert_eq!(result, Err(ExecError::InvalidCode(reason.to_string())));
+        }
+        assert_eq!(storage.state_root(), root);
+
+        // Nothing after STOP runs or is checked
+        assert_eq!(code_gas(&[OP_PUSH1, 1, OP_STOP, 0xfe]), Ok(3));
+    }
 }
@@ -1,63 +1,898 @@
+use alloy_primitives::{Address, B256, U256};
+use alloy_rlp::Encodable;
+use clap::Parser;
+use pethit_client::Client;
+use pethit_consensus::{GenesisConfig, MinedBlock, Miner, SealMode, SharedChain, import_blocks};
+use pethit_rpc::{
+    DEFAULT_BODY_LIMIT, DEFAULT_MAX_CONNECTIONS, DEFAULT_REQUEST_TIMEOUT, DevConfig, ServerConfig,
+    parse_hex_data, start_server,
+};
+use pethit_storage::{Recovery, SharedStorage, compress, decompress, is_compressed};
+use pethit_txpool::{PoolConfig, SharedTxPool};
 use std::fs;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use alloy_primitives::{Address, U256};
-use pethit_consensus::{Miner, SharedChain};
-use pethit_rpc::start_server;
-use pethit_storage::{SharedStorage, Account};
-use pethit_txpool::SharedTxPool;
-
-// Helper to load genesis state
-fn load_genesis_state(storage: &SharedStorage) {
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// pETHit node
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Run the miner. With `--mine=false` the node only serves the RPC (read-only)
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    mine: bool,
+    /// Lowest gas price (wei) the pool accepts
+    #[arg(long, default_value_t = 0)]
+    min_gas_price: u64,
+    /// How much higher (in percent) a replacement's gas price must be than the pooled one's
+    #[arg(long, default_value_t = PoolConfig::default().price_bump_percent)]
+    price_bump_percent: u64,
+    /// Drop transactions pending for this many seconds instead of mining them (default: never)
+    #[arg(long)]
+    txpool_ttl_secs: Option<u64>,
+    /// Seconds an RPC request can take before it's answered with 408
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT.as_secs())]
+    rpc_timeout_secs: u64,
+    /// Largest RPC request body in bytes (bigger ones get 413). Imports have their own limit
+    #[arg(long, default_value_t = DEFAULT_BODY_LIMIT)]
+    rpc_body_limit: usize,
+    /// Most RPC connections open at once. Past it new ones wait until one closes
+    #[arg(long, default_value_t = DEFAULT_MAX_CONNECTIONS)]
+    rpc_max_connections: usize,
+    /// Serve the RPC under this path, e.g. `--rpc-base-path /pethit` (default: the root)
+    #[arg(long, value_parser = parse_base_path)]
+    rpc_base_path: Option<String>,
+    /// Keep only the last N blocks in memory, e.g. `--prune-blocks keep=1000`
+    #[arg(long, value_parser = parse_prune_blocks)]
+    prune_blocks: Option<u64>,
+    /// Trusted block hash at a height, e.g. `--checkpoint 1000=0xabc...`. Can be repeated
+    #[arg(long = "checkpoint", value_parser = parse_checkpoint)]
+    checkpoints: Vec<(u64, B256)>,
+    /// Address credited with block rewards and fees. Defaults to the zero address (rewards are burned)
+    #[arg(long, value_parser = parse_coinbase)]
+    coinbase: Option<Address>,
+    /// Directory the pool, chain and state are saved to on Ctrl-C, and restored from on startup.
+    /// Created on first run, readable by the owner only
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Compress the blocks saved to `--data-dir` (lz4). Either kind is read back on startup
+    #[arg(long)]
+    compress_blocks: bool,
+    /// Seal a block as soon as transactions arrive instead of on a timer
+    #[arg(long)]
+    instant_seal: bool,
+    /// Dev mode: enables the faucet endpoint
+    #[arg(long)]
+    dev: bool,
+    /// API key the faucet requires (dev mode only)
+    #[arg(long, requires = "dev")]
+    faucet_api_key: Option<String>,
+    /// RPC URL of a peer node pending transactions are re-sent to. Can be repeated
+    #[arg(long = "peer")]
+    peers: Vec<String>,
+    /// Seconds a transaction stays pending before it's re-sent to the peers, and between re-sends
+    #[arg(long, default_value_t = DEFAULT_REBROADCAST_INTERVAL.as_secs())]
+    rebroadcast_interval_secs: u64,
+}
+
+/// How long a transaction is pending before it's re-sent to the peers, by default.
+const DEFAULT_REBROADCAST_INTERVAL: Duration = Duration::from_secs(30);
+
+// Parses the `keep=N` of `--prune-blocks`
+fn parse_prune_blocks(input: &str) -> Result<u64, String> {
+    input
+        .strip_prefix("keep=")
+        .and_then(|keep| keep.parse().ok())
+        .ok_or_else(|| format!("expected keep=N, got '{}'", input))
+}
+
+// Parses the `NUMBER=HASH` of `--checkpoint`
+fn parse_checkpoint(input: &str) -> Result<(u64, B256), String> {
+    input
+        .split_once('=')
+        .and_then(|(number, hash)| Some((number.parse().ok()?, B256::from_str(hash).ok()?)))
+        .ok_or_else(|| format!("expected NUMBER=HASH, got '{}'", input))
+}
+
+// Checks the `/PATH` of `--rpc-base-path`
+fn parse_base_path(input: &str) -> Result<String, String> {
+    if !input.starts_with('/') {
+        return Err(format!(
+            "expected a path starting with '/', got '{}'",
+            input
+        ));
+    }
+    Ok(input.to_string())
+}
+
+// Parses the address of `--coinbase`
+fn parse_coinbase(input: &str) -> Result<Address, String> {
+    Address::from_str(input).map_err(|e| format!("invalid coinbase address '{}': {}", input, e))
+}
+
+/// Whether this node produces blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeMode {
+    /// Mines and serves the RPC.
+    Full,
+    /// Only serves the RPC. The chain is expected to advance through P2P sync.
+    ReadOnly,
+}
+
+impl From<&Cli> for NodeMode {
+    fn from(cli: &Cli) -> Self {
+        if cli.mine {
+            NodeMode::Full
+        } else {
+            NodeMode::ReadOnly
+        }
+    }
+}
+
+/// Layout of `--data-dir`. Every path the node persists to comes from here:
+///
+/// ```text
+/// <root>/state/state      storage export
+/// <root>/state/journal    writes of the block being applied (only while it is)
+/// <root>/blocks/chain     blocks after genesis (RLP, maybe compressed)
+/// <root>/mempool/txpool   pooled transactions (RLP)
+/// ```
+#[derive(Debug, Clone)]
+struct DataDir {
+    root: PathBuf,
+}
+
+impl DataDir {
+    /// Uses `root`, creating it and its subdirectories (owner-only on unix) if missing.
+    fn open(root: &Path) -> std::io::Result<Self> {
+        let dir = Self {
+            root: root.to_path_buf(),
+        };
+        for sub in [
+            dir.root.clone(),
+            dir.state_dir(),
+            dir.blocks_dir(),
+            dir.mempool_dir(),
+        ] {
+            fs::create_dir_all(&sub)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&sub, fs::Permissions::from_mode(0o700))?;
+            }
+        }
+        Ok(dir)
+    }
+
+    fn state_dir(&self) -> PathBuf {
+        self.root.join("state")
+    }
+
+    fn blocks_dir(&self) -> PathBuf {
+        self.root.join("blocks")
+    }
+
+    fn mempool_dir(&self) -> PathBuf {
+        self.root.join("mempool")
+    }
+
+    fn state_file(&self) -> PathBuf {
+        self.state_dir().join("state")
+    }
+
+    fn journal_file(&self) -> PathBuf {
+        self.state_dir().join("journal")
+    }
+
+    fn chain_file(&self) -> PathBuf {
+        self.blocks_dir().join("chain")
+    }
+
+    fn txpool_file(&self) -> PathBuf {
+        self.mempool_dir().join("txpool")
+    }
+}
+
+/// The components the node runs, shared with the miner and the RPC.
+#[derive(Clone)]
+struct NodeState {
+    storage: SharedStorage,
+    txpool: SharedTxPool,
+    chain: SharedChain,
+    // Compress the saved chain
+    compress_blocks: bool,
+}
+
+impl NodeState {
+    /// Saves the pool, the chain and the state to `data_dir`, in that order, then flushes the
+    /// storage backend. The storage goes last so it's never behind what the chain says.
+    /// A failed step is logged and the others still run. Returns the steps that failed.
+    fn persist_all(&self, data_dir: &DataDir) -> Vec<&'static str> {
+        // Evaluated in order, each regardless of how the previous one went
+        let steps = [
+            (
+                "mempool",
+                fs::write(data_dir.txpool_file(), self.txpool.export()),
+            ),
+            (
+                "blocks",
+                fs::write(data_dir.chain_file(), self.export_chain()),
+            ),
+            (
+                "state",
+                fs::File::create(data_dir.state_file())
+                    .and_then(|file| self.storage.export_to_writer(BufWriter::new(file))),
+            ),
+            ("storage flush", self.storage.flush()),
+        ];
+
+        let mut failed = Vec::new();
+        for (step, result) in steps {
+            if let Err(e) = result {
+                eprintln!("Failed to persist {}: {}", step, e);
+                failed.push(step);
+            }
+        }
+        failed
+    }
+
+    // The blocks after genesis, compressed if configured
+    fn export_chain(&self) -> Vec<u8> {
+        let blocks = self.chain.export(1, self.chain.last_block().id);
+        if self.compress_blocks {
+            compress(&blocks)
+        } else {
+            blocks
+        }
+    }
+
+    /// Loads what `persist_all` saved in `data_dir` on top of a node fresh from `genesis`.
+    /// The chain is replayed on a scratch copy of the genesis state, so its receipts come back,
+    /// then the saved state replaces the storage, and the journal of a block a crash cut short
+    /// is finished on top of it (if that block follows the chain). Missing files (first start)
+    /// are skipped, a file that fails to load is logged and the others still load.
+    fn restore(&self, genesis: &GenesisConfig, data_dir: &DataDir) {
+        if let Ok(data) = fs::read(data_dir.chain_file()) {
+            let blocks = if is_compressed(&data) {
+                decompress(&data)
+            } else {
+                Ok(data)
+            };
+            let scratch = SharedStorage::new();
+            genesis
+                .apply(&scratch)
+                .expect("in-memory storage takes any write");
+            match blocks.map_err(|e| e.to_string()).and_then(|blocks| {
+                import_blocks(&self.chain, &scratch, &blocks).map_err(|e| e.to_string())
+            }) {
+                Ok(count) => println!("Restored {} blocks", count),
+                Err(e) => eprintln!("Failed to restore the chain: {}", e),
+            }
+        }
+        if let Ok(file) = fs::File::open(data_dir.state_file()) {
+            match self.storage.import_from_reader(BufReader::new(file)) {
+                Ok(()) => self.storage.commit_version(self.chain.last_block().id),
+                Err(e) => eprintln!("Failed to restore the state: {}", e),
+            }
+        }
+        let tip = self.chain.last_block().id;
+        match self.storage.recover(tip + 1) {
+            Ok(Recovery::Clean) => {}
+            Ok(Recovery::Replayed { block }) => println!("Replayed the journal of block {}", block),
+            Ok(Recovery::RolledBack) => println!("Dropped a torn journal"),
+            Ok(Recovery::Stale { block }) => println!(
+                "Dropped the journal of block {}, the chain is at block {}",
+                block, tip
+            ),
+            Err(e) => panic!("Cannot recover the storage journal: {}", e),
+        }
+        if let Ok(data) = fs::read(data_dir.txpool_file()) {
+            match self.txpool.import(&data) {
+                Ok((count, skipped)) => {
+                    for (k_hash, e) in skipped {
+                        println!("Skipping pooled tx {}: {}", k_hash, e);
+                    }
+                    println!("Restored {} pooled transactions", count)
+                }
+                Err(e) => eprintln!("Failed to restore the pool: {}", e),
+            }
+        }
+    }
+}
+
+// Helper to load the genesis config
+fn load_genesis_config() -> GenesisConfig {
     // Read Json file
-    let genesis_str = fs::read_to_string("genesis.json").expect("Failed to read genesis.json. Make sure it exists in the workspace root.");
-    let genesis_json: serde_json::Value = serde_json::from_str(&genesis_str).expect("Invalid JSON in genesis.json");
+    let genesis_str = fs::read_to_string("genesis.json")
+        .expect("Failed to read genesis.json. Make sure it exists in the workspace root.");
+    let genesis_json: serde_json::Value =
+        serde_json::from_str(&genesis_str).expect("Invalid JSON in genesis.json");
+
+    let mut config = GenesisConfig::default();
+    if let Some(chain_id) = genesis_json.get("chain_id") {
+        config.chain_id = chain_id.as_u64().expect("chain_id must be a number");
+    }
+    if let Some(difficulty) = genesis_json.get("difficulty") {
+        config.difficulty = difficulty.as_u64().expect("difficulty must be a number");
+    }
+    if let Some(gas_limit) = genesis_json.get("gas_limit") {
+        config.gas_limit = gas_limit.as_u64().expect("gas_limit must be a number");
+    }
+    if let Some(base_fee) = genesis_json.get("base_fee") {
+        let base_fee = base_fee.as_str().expect("base_fee must be a string");
+        config.base_fee = U256::from_str(base_fee).expect("Invalid base_fee format");
+    }
+    if let Some(timestamp) = genesis_json.get("timestamp") {
+        config.timestamp = timestamp.as_u64().expect("timestamp must be a number");
+    }
+    if let Some(extra_data) = genesis_json.get("extra_data") {
+        let extra_data = extra_data
+            .as_str()
+            .expect("extra_data must be a hex string");
+        config.extra_data = parse_hex_data(extra_data).expect("Invalid extra_data");
+    }
 
     // Parse the alloc object
-    let alloc = genesis_json["alloc"].as_object().expect("Missing 'alloc' object in genesis");
+    let alloc = genesis_json["alloc"]
+        .as_object()
+        .expect("Missing 'alloc' object in genesis");
 
-    // Insert each account into storage
     for (addr_str, account_data) in alloc {
         let address = Address::from_str(addr_str).expect("Invalid address format in genesis");
 
         // Parse balance
-        let balance_str = account_data["balance"].as_str().expect("Balance must be a string");
+        let balance_str = account_data["balance"]
+            .as_str()
+            .expect("Balance must be a string");
         let balance = U256::from_str(balance_str).expect("Invalid balance format");
-        
-        let account = Account {
-            nonce: 0,
-            balance,
-        };
 
-        storage.set_account(address, account);
+        config.alloc.insert(address, balance);
         println!("Funded {} with {} Wei", address, balance);
     }
+    config
+}
+
+// Configures the Miner the node runs
+fn build_miner(
+    seal_mode: SealMode,
+    coinbase: Address,
+    txpool: SharedTxPool,
+    storage: SharedStorage,
+    chain: SharedChain,
+) -> Miner {
+    Miner::new(txpool, storage, chain)
+        .with_seal_mode(seal_mode)
+        .with_coinbase(coinbase)
+        .with_on_mined(log_skipped)
 }
 
+// Logs the packed transactions a mined block left out
+fn log_skipped(mined: &MinedBlock) {
+    for (tx, e) in &mined.skipped {
+        println!("Skipped invalid tx {}: {}", tx.hash(), e);
+    }
+}
+
+// Every `interval`, re-sends the transactions pending for that long to every peer, so the ones
+// a peer missed (or dropped) still get mined
+fn spawn_rebroadcaster(txpool: SharedTxPool, peers: &[String], interval: Duration) {
+    let peers: Vec<(String, Client)> = peers
+        .iter()
+        .map(|url| (url.clone(), Client::new(url.clone())))
+        .collect();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for tx in txpool.due_for_rebroadcast(interval) {
+                let mut raw_tx = Vec::new();
+                tx.encode(&mut raw_tx);
+                for (url, client) in &peers {
+                    if let Err(e) = client.send_raw_tx(&raw_tx).await {
+                        eprintln!("Failed to re-broadcast {} to {}: {}", tx.hash(), url, e);
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Launch the Miner in the background (Full mode only)
+// `tokio::task::spawn_blocking` is used because the Miner uses `thread::sleep`, which shouldn't block the async executor.
+fn spawn_miner(
+    mode: NodeMode,
+    seal_mode: SealMode,
+    coinbase: Address,
+    txpool: SharedTxPool,
+    storage: SharedStorage,
+    chain: SharedChain,
+    shutdown: mpsc::Receiver<()>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    if mode == NodeMode::ReadOnly {
+        println!("Read-only mode: the miner is disabled");
+        return None;
+    }
+
+    Some(tokio::task::spawn_blocking(move || {
+        build_miner(seal_mode, coinbase, txpool, storage, chain)
+            .with_shutdown(shutdown)
+            .start_mining();
+    }))
+}
 
 #[tokio::main] // turns `main` into an async function
 async fn main() {
+    let cli = Cli::parse();
     println!("Starting pETHit node...");
 
+    let data_dir = cli.data_dir.as_deref().map(|root| {
+        DataDir::open(root)
+            .unwrap_or_else(|e| panic!("Cannot create the data dir {}: {}", root.display(), e))
+    });
+
     // Start the shared components
-    let shared_storage = SharedStorage::new();
-    let shared_txpool = SharedTxPool::new();
-    let shared_chain = SharedChain::new();
+    let shared_storage = match &data_dir {
+        // What a crash left in the journal is dealt with by `restore`
+        Some(data_dir) => SharedStorage::new().with_journal(data_dir.journal_file()),
+        None => SharedStorage::new(),
+    };
+    let shared_txpool = SharedTxPool::with_config(PoolConfig {
+        min_gas_price: U256::from(cli.min_gas_price),
+        price_bump_percent: cli.price_bump_percent,
+        ttl: cli.txpool_ttl_secs.map(Duration::from_secs),
+        ..Default::default()
+    });
 
-    // Load genesis (only in storage. Will be added to the chain after implementing MPT)
-    load_genesis_state(&shared_storage);
+    // Load genesis: the allocations go to storage, the chain starts from its block
+    let genesis = load_genesis_config();
+    genesis
+        .apply(&shared_storage)
+        .expect("cannot write the genesis allocations");
+    shared_storage.commit_version(0);
+    let mut shared_chain = SharedChain::from_genesis(&genesis);
+    if let Some(keep) = cli.prune_blocks {
+        shared_chain = shared_chain.with_prune_blocks(keep);
+    }
+    if !cli.checkpoints.is_empty() {
+        shared_chain = shared_chain.with_checkpoints(cli.checkpoints.iter().copied().collect());
+    }
+    println!("Genesis hash: {}", shared_chain.genesis_hash());
+
+    let node = NodeState {
+        storage: shared_storage.clone(),
+        txpool: shared_txpool.clone(),
+        chain: shared_chain.clone(),
+        compress_blocks: cli.compress_blocks,
+    };
+    if let Some(data_dir) = &data_dir {
+        node.restore(&genesis, data_dir);
+    }
 
     // Setup the Miner
-    let miner_txpool = shared_storage.clone();
-    let miner_storage = shared_txpool.clone();
-    let miner_chain = shared_chain.clone();
-
-    // Launch the Miner in the background
-    // `tokio::task::spawn_blocking` is used because the Miner uses `thread::sleep`, which shouldn't block the async executor.
-    tokio::task::spawn_blocking(move || {
-        let miner = Miner::new(miner_storage, miner_txpool, miner_chain);
-        miner.start_mining();
+    let seal_mode = if cli.instant_seal {
+        SealMode::Instant
+    } else {
+        SealMode::Interval
+    };
+    let coinbase = cli.coinbase.unwrap_or_else(|| {
+        println!("Warning: no --coinbase set, block rewards go to the zero address and are burned");
+        Address::ZERO
     });
+    let (stop_miner, shutdown) = mpsc::channel();
+    let miner = spawn_miner(
+        NodeMode::from(&cli),
+        seal_mode,
+        coinbase,
+        shared_txpool.clone(),
+        shared_storage.clone(),
+        shared_chain.clone(),
+        shutdown,
+    );
+
+    if !cli.peers.is_empty() {
+        spawn_rebroadcaster(
+            shared_txpool.clone(),
+            &cli.peers,
+            Duration::from_secs(cli.rebroadcast_interval_secs),
+        );
+    }
+
+    // Start the RPC server. Pause here until the server stops (never) or Ctrl-C
+    let server_config = ServerConfig {
+        request_timeout: Duration::from_secs(cli.rpc_timeout_secs),
+        body_limit: cli.rpc_body_limit,
+        max_connections: cli.rpc_max_connections,
+        base_path: cli.rpc_base_path.clone(),
+        dev: cli.dev.then(|| DevConfig {
+            api_key: cli.faucet_api_key.clone(),
+        }),
+    };
+    let server = start_server(shared_storage, shared_txpool, shared_chain, server_config);
+    tokio::select! {
+        _ = server => {}
+        _ = tokio::signal::ctrl_c() => {
+            println!("Shutting down...");
+            // Let the miner finish its block first, so the chain and the state saved below
+            // agree. Fails if the miner already stopped on its own, which is fine
+            let _ = stop_miner.send(());
+            if let Some(miner) = miner
+                && let Err(e) = miner.await
+            {
+                eprintln!("The miner panicked: {}", e);
+            }
+            match &data_dir {
+                Some(data_dir) => {
+                    node.persist_all(data_dir);
+                }
+                None => {
+                    if let Err(e) = node.storage.flush() {
+                        eprintln!("Failed to flush the storage: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_mode_from_flag() {
+        let cli = Cli::parse_from(["pethit-node"]);
+        assert_eq!(NodeMode::from(&cli), NodeMode::Full);
+
+        let cli = Cli::parse_from(["pethit-node", "--mine=false"]);
+        assert_eq!(NodeMode::from(&cli), NodeMode::ReadOnly);
+    }
+
+    #[test]
+    fn test_prune_blocks_flag() {
+        let cli = Cli::parse_from(["pethit-node", "--prune-blocks", "keep=10"]);
+        assert_eq!(cli.prune_blocks, Some(10));
 
-    // Start the RPC server. Pause here until the server stops (never)
-    start_server(shared_storage, shared_txpool, shared_chain).await;
+        assert!(Cli::try_parse_from(["pethit-node", "--prune-blocks", "10"]).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_flag() {
+        let hash = B256::repeat_byte(0xab);
+        let cli = Cli::parse_from([
+            "pethit-node".to_string(),
+            "--checkpoint".to_string(),
+            format!("10={}", hash),
+            "--checkpoint".to_string(),
+            format!("20={}", B256::ZERO),
+        ]);
+        assert_eq!(cli.checkpoints, vec![(10, hash), (20, B256::ZERO)]);
+
+        assert!(Cli::try_parse_from(["pethit-node", "--checkpoint", "10"]).is_err());
+        assert!(Cli::try_parse_from(["pethit-node", "--checkpoint", "x=0x00"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_only_mode_does_not_mine() {
+        let chain = SharedChain::new();
+        let (stop_miner, shutdown) = mpsc::channel();
+
+        let miner = spawn_miner(
+            NodeMode::ReadOnly,
+            SealMode::Interval,
+            Address::ZERO,
+            SharedTxPool::new(),
+            SharedStorage::new(),
+            chain.clone(),
+            shutdown,
+        );
+        // No miner task, and nothing kept the shutdown receiver to run one later
+        assert!(miner.is_none());
+        assert!(stop_miner.send(()).is_err());
+        assert_eq!(chain.last_block().id, 0);
+    }
+
+    #[tokio::test]
+    async fn test_miner_stops_on_shutdown() {
+        let chain = SharedChain::new();
+        let (stop_miner, shutdown) = mpsc::channel();
+        let miner = spawn_miner(
+            NodeMode::Full,
+            SealMode::Interval,
+            Address::ZERO,
+            SharedTxPool::new(),
+            SharedStorage::new(),
+            chain.clone(),
+            shutdown,
+        )
+        .unwrap();
+        while chain.last_block().id == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Well before the next heartbeat (5s away)
+        stop_miner.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(2), miner)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(chain.last_block().id, 1);
+    }
+
+    #[test]
+    fn test_coinbase_flag() {
+        let cli = Cli::parse_from(["pethit-node"]);
+        assert_eq!(cli.coinbase, None);
+
+        let coinbase = Address::with_last_byte(0xcb);
+        let cli = Cli::parse_from([
+            "pethit-node".to_string(),
+            "--coinbase".to_string(),
+            coinbase.to_string(),
+        ]);
+        assert_eq!(cli.coinbase, Some(coinbase));
+
+        assert!(Cli::try_parse_from(["pethit-node", "--coinbase", "0x1234"]).is_err());
+        assert!(Cli::try_parse_from(["pethit-node", "--coinbase", "miner"]).is_err());
+    }
+
+    #[test]
+    fn test_mined_blocks_credit_the_coinbase() {
+        let coinbase = Address::with_last_byte(0xcb);
+        let storage = SharedStorage::new();
+        let chain = SharedChain::new();
+        let mut miner = build_miner(
+            SealMode::Interval,
+            coinbase,
+            SharedTxPool::new(),
+            storage.clone(),
+            chain.clone(),
+        );
+
+        let block = miner.mine_once();
+        assert_eq!(block.coinbase, coinbase);
+        miner.mine_once();
+        assert_eq!(
+            storage.get_account(coinbase).balance,
+            pethit_consensus::BLOCK_REWARD * U256::from(2)
+        );
+    }
+
+    #[test]
+    fn test_persist_all_and_restore() {
+        use k256::ecdsa::SigningKey;
+        use pethit_execution::{SignedTransaction, Transaction};
+
+        let signing_key = SigningKey::from_bytes(&[1u8; 32].into()).unwrap();
+        let public_key = signing_key.verifying_key().to_encoded_point(false);
+        let sender =
+            Address::from_slice(&alloy_primitives::keccak256(&public_key.as_bytes()[1..])[12..]);
+        let transfer = |nonce| {
+            let tx = Transaction {
+                to: Address::with_last_byte(1),
+                value: U256::from(100),
+                nonce,
+                gas_price: U256::from(10),
+                data: Vec::new(),
+            };
+            let (signature, recid) = signing_key
+                .sign_prehash_recoverable(tx.hash().as_slice())
+                .unwrap();
+            SignedTransaction::new(tx, signature, recid)
+        };
+
+        let mut genesis = GenesisConfig::default();
+        genesis.alloc.insert(sender, U256::from(10u64.pow(18)));
+        let fresh_node = |compress_blocks| {
+            let node = NodeState {
+                storage: SharedStorage::new(),
+                txpool: SharedTxPool::new(),
+                chain: SharedChain::from_genesis(&genesis),
+                compress_blocks,
+            };
+            genesis.apply(&node.storage).unwrap();
+            node
+        };
+
+        // Two blocks, the second with a transfer, and one more transfer left in the pool
+        let node = fresh_node(false);
+        let mut miner = build_miner(
+            SealMode::Interval,
+            Address::with_last_byte(0xcb),
+            node.txpool.clone(),
+            node.storage.clone(),
+            node.chain.clone(),
+        );
+        miner.mine_once();
+        let tx = transfer(0);
+        node.txpool.add(tx.hash(), tx).unwrap();
+        miner.mine_once();
+        let pending = transfer(1);
+        node.txpool.add(pending.hash(), pending.clone()).unwrap();
+
+        let root = std::env::temp_dir().join(format!("pethit-node-persist-{}", std::process::id()));
+        let dir = DataDir::open(&root).unwrap();
+        assert!(node.persist_all(&dir).is_empty());
+
+        let restored = fresh_node(false);
+        restored.restore(&genesis, &dir);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(restored.chain.last_block().id, 2);
+        assert_eq!(
+            restored.chain.last_block().k_hash,
+            node.chain.last_block().k_hash
+        );
+        assert_eq!(restored.storage.state_root(), node.storage.state_root());
+        assert_eq!(restored.txpool.get_all_transactions(), vec![pending]);
+    }
+
+    #[test]
+    fn test_compressed_chain_restores() {
+        let genesis = GenesisConfig::default();
+        let fresh_node = |compress_blocks| NodeState {
+            storage: SharedStorage::new(),
+            txpool: SharedTxPool::new(),
+            chain: SharedChain::from_genesis(&genesis),
+            compress_blocks,
+        };
+        let node = fresh_node(true);
+        let mut miner = build_miner(
+            SealMode::Interval,
+            Address::ZERO,
+            node.txpool.clone(),
+            node.storage.clone(),
+            node.chain.clone(),
+        );
+        for _ in 0..3 {
+            miner.mine_once();
+        }
+
+        let root =
+            std::env::temp_dir().join(format!("pethit-node-compressed-{}", std::process::id()));
+        let dir = DataDir::open(&root).unwrap();
+        assert!(node.persist_all(&dir).is_empty());
+        assert!(is_compressed(&fs::read(dir.chain_file()).unwrap()));
+
+        // Read back whatever the reading node is configured to write
+        let restored = fresh_node(false);
+        restored.restore(&genesis, &dir);
+        assert_eq!(
+            restored.chain.last_block().k_hash,
+            node.chain.last_block().k_hash
+        );
+
+        // A corrupt file restores nothing
+        let mut data = fs::read(dir.chain_file()).unwrap();
+        data.truncate(data.len() - 8);
+        fs::write(dir.chain_file(), data).unwrap();
+        let corrupted = fresh_node(false);
+        corrupted.restore(&genesis, &dir);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(corrupted.chain.last_block().id, 0);
+    }
+
+    #[test]
+    fn test_data_dirs_are_isolated() {
+        let base = std::env::temp_dir().join(format!("pethit-node-dirs-{}", std::process::id()));
+        let genesis = GenesisConfig::default();
+        let node_funding = |address: Option<Address>| {
+            let node = NodeState {
+                storage: SharedStorage::new(),
+                txpool: SharedTxPool::new(),
+                chain: SharedChain::from_genesis(&genesis),
+                compress_blocks: false,
+            };
+            if let Some(address) = address {
+                let mut account = node.storage.get_account(address);
+                account.balance = U256::from(7);
+                node.storage.set_account(address, account).unwrap();
+            }
+            node
+        };
+        let (alice, bob) = (Address::with_last_byte(0xa), Address::with_last_byte(0xb));
+
+        let dir_a = DataDir::open(&base.join("a")).unwrap();
+        let dir_b = DataDir::open(&base.join("b")).unwrap();
+        for sub in [dir_a.state_dir(), dir_a.blocks_dir(), dir_a.mempool_dir()] {
+            assert!(sub.is_dir());
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = fs::metadata(&sub).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o700);
+            }
+        }
+
+        assert!(node_funding(Some(alice)).persist_all(&dir_a).is_empty());
+        assert!(node_funding(Some(bob)).persist_all(&dir_b).is_empty());
+
+        let restored_a = node_funding(None);
+        restored_a.restore(&genesis, &dir_a);
+        let restored_b = node_funding(None);
+        restored_b.restore(&genesis, &dir_b);
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(restored_a.storage.get_account(alice).balance, U256::from(7));
+        assert_eq!(restored_a.storage.get_account(bob).balance, U256::ZERO);
+        assert_eq!(restored_b.storage.get_account(bob).balance, U256::from(7));
+        assert_eq!(restored_b.storage.get_account(alice).balance, U256::ZERO);
+    }
+
+    #[test]
+    fn test_storage_journals_to_the_data_dir() {
+        use pethit_storage::{Account, BlockWrites, StorageError};
+        use std::panic::{self, AssertUnwindSafe};
+
+        let root = std::env::temp_dir().join(format!("pethit-node-journal-{}", std::process::id()));
+        let dir = DataDir::open(&root).unwrap();
+        let genesis = GenesisConfig::default();
+        let fresh_node = || {
+            let node = NodeState {
+                storage: SharedStorage::new().with_journal(dir.journal_file()),
+                txpool: SharedTxPool::new(),
+                chain: SharedChain::from_genesis(&genesis),
+                compress_blocks: false,
+            };
+            genesis.apply(&node.storage).unwrap();
+            node
+        };
+        let alice = Address::with_last_byte(0xa1);
+        let funded = Account {
+            nonce: 0,
+            balance: U256::from(7),
+        };
+        // Dies once the writes of `block` are journaled and applied, before the chain has it
+        let crash_during = |node: &NodeState, block: u64| {
+            let mut value = Vec::new();
+            funded.encode(&mut value);
+            let writes = BlockWrites {
+                block,
+                entries: vec![(alice.to_vec(), value)],
+            };
+            let crashed = panic::catch_unwind(AssertUnwindSafe(|| {
+                node.storage.update_block(
+                    |_| Ok::<_, StorageError>(((), writes)),
+                    |()| -> Result<(), StorageError> { panic!("simulated crash") },
+                )
+            }));
+            assert!(crashed.is_err());
+            assert!(dir.journal_file().exists());
+        };
+
+        // Block 1 is mined and saved, then the node dies while applying block 2
+        let node = fresh_node();
+        build_miner(
+            SealMode::Interval,
+            Address::with_last_byte(0xcb),
+            node.txpool.clone(),
+            node.storage.clone(),
+            node.chain.clone(),
+        )
+        .mine_once();
+        // A mined block leaves no journal behind
+        assert!(!dir.journal_file().exists());
+        assert!(node.persist_all(&dir).is_empty());
+        let saved_root = node.storage.state_root();
+        crash_during(&node, 2);
+
+        // The journal follows the restored tip: replayed over the saved state
+        let restored = fresh_node();
+        restored.restore(&genesis, &dir);
+        assert_eq!(restored.chain.last_block().id, 1);
+        assert_eq!(restored.storage.get_account(alice), funded);
+        assert_ne!(restored.storage.state_root(), saved_root);
+        assert!(!dir.journal_file().exists());
+
+        // A journal that doesn't follow the tip is dropped, the saved state stays as it was
+        crash_during(&restored, 5);
+        let restored = fresh_node();
+        restored.restore(&genesis, &dir);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(restored.chain.last_block().id, 1);
+        assert_eq!(restored.storage.get_account(alice), Account::default());
+        assert_eq!(restored.storage.state_root(), saved_root);
+    }
 }
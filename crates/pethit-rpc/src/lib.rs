@@ -1,17 +1,57 @@
-use alloy_primitives::{Address, B256};
+use alloy_primitives::{Address, B256, U256};
 use alloy_rlp::Decodable;
 use axum::{
     Json, Router,
-    extract::State,
-    routing::post,
+    body::{Bytes, to_bytes},
+    extract::DefaultBodyLimit,
+    extract::{Query, State},
+    http::{StatusCode, header},
+    middleware::map_response,
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Response},
+    routing::{get, post},
 };
-use pethit_consensus::SharedChain;
-use pethit_execution::SignedTransaction;
-use pethit_storage::SharedStorage;
-use pethit_txpool::SharedTxPool;
+use futures_util::stream::{self, Stream};
+use pethit_consensus::{
+    BLOCK_REWARD, BlockLookup, ChainError, SealedBlock, SharedChain, TxLocation, import_blocks,
+    next_base_fee,
+};
+use pethit_execution::{BlockEnv, ExecError, ExecutionEngine, Log, SignedTransaction, Trace};
+use pethit_storage::{SharedStorage, StorageBackend, StorageError};
+use pethit_txpool::{PoolError, SharedTxPool};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower_http::timeout::TimeoutLayer;
+
+/// How long a request can take before the server gives up on it (408), by default.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest request body accepted by default (413 above). Transactions take a few hundred bytes.
+pub const DEFAULT_BODY_LIMIT: usize = 64 * 1024;
+
+/// Most connections the server keeps open at once, by default. Past it new ones wait in
+/// the OS backlog until one closes.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Most blocks GET /blocks returns at once.
+pub const MAX_BLOCKS_PAGE: u64 = 100;
+
+/// How many recent blocks GET /gas_price looks at.
+pub const GAS_PRICE_BLOCKS: u64 = 20;
+
+// POST /import takes whole block exports, so it gets more room than the rest
+const IMPORT_BODY_LIMIT: usize = 256 * 1024 * 1024;
 
 // Raw tx hex the wallet sends
 #[derive(Deserialize)]
@@ -19,6 +59,71 @@ struct PutTransactionRequest {
     pub raw_tx: String,
 }
 
+#[derive(Serialize)]
+pub struct SendTransactionResponse {
+    pub tx_hash: String,
+}
+
+#[derive(Serialize)]
+pub struct ValidateTransactionResponse {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DecodedTransactionResponse {
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: String,
+    pub nonce: u64,
+    pub gas_price: String,
+    pub data: String,
+}
+
+#[derive(Deserialize)]
+struct CallRequest {
+    raw_tx: String,
+    // Also report what the execution touched
+    #[serde(default)]
+    trace: bool,
+}
+
+#[derive(Serialize)]
+pub struct CallResponse {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub gas_used: u64,
+    pub logs: Vec<LogResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceResponse>,
+}
+
+#[derive(Serialize)]
+pub struct TraceResponse {
+    pub reads: Vec<String>,
+    pub writes: Vec<StateWriteResponse>,
+    pub balance_changes: Vec<BalanceChangeResponse>,
+}
+
+#[derive(Serialize)]
+pub struct StateWriteResponse {
+    pub address: String,
+    pub nonce_before: u64,
+    pub nonce_after: u64,
+    pub balance_before: String,
+    pub balance_after: String,
+}
+
+#[derive(Serialize)]
+pub struct BalanceChangeResponse {
+    pub address: String,
+    pub before: String,
+    pub after: String,
+}
+
 // Raw address hex the wallet sends
 #[derive(Deserialize)]
 struct GetAccountRequest {
@@ -32,52 +137,517 @@ pub struct AccountResponse {
     pub balance: String,
 }
 
-// #[derive(Deserialize)]
-// struct GetTransactionRequest {
-//     hash: String,
-// }
+// Query string of account
+#[derive(Deserialize)]
+struct AccountQuery {
+    address: String,
+}
+
+#[derive(Serialize)]
+pub struct AccountInfoResponse {
+    pub balance: String,
+    pub nonce: u64,
+    /// Whether the storage has an entry for the address (zeros are reported either way).
+    pub exists: bool,
+}
+
+#[derive(Deserialize)]
+struct GetTransactionRequest {
+    hash: String,
+}
+
+#[derive(Serialize)]
+pub struct TransactionResponse {
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: String,
+    pub nonce: u64,
+    pub gas_price: String,
+    pub data: String,
+    pub block_hash: String,
+    pub block_number: u64,
+}
+
+#[derive(Deserialize)]
+struct GetLogsRequest {
+    from: u64,
+    to: u64,
+    address: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LogResponse {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+}
+
+#[derive(Serialize)]
+pub struct ReceiptResponse {
+    pub tx_hash: String,
+    pub block_hash: String,
+    pub block_number: u64,
+    pub gas_used: u64,
+    pub effective_gas_price: String,
+    pub logs: Vec<LogResponse>,
+}
+
+// Body of every failed JSON request
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    // Machine-readable reason, on the errors a client can act on (see `send_transaction`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+// Error half of the JSON handlers: a status code plus an ErrorResponse body
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn api_error(status: StatusCode, message: &str) -> ApiError {
+    (
+        status,
+        Json(ErrorResponse {
+            error: message.to_string(),
+            code: None,
+        }),
+    )
+}
+
+// A 400 carrying `code` next to the message
+fn coded_error(code: &str, message: &str) -> ApiError {
+    let (status, Json(mut body)) = api_error(StatusCode::BAD_REQUEST, message);
+    body.code = Some(code.to_string());
+    (status, Json(body))
+}
+
+fn pool_error_code(e: &PoolError) -> &'static str {
+    match e {
+        PoolError::InvalidSignature(_) => "invalid_signature",
+        PoolError::Underpriced { .. } => "fee_too_low",
+        PoolError::SenderLimit { .. } => "sender_limit_reached",
+        PoolError::ReplacementUnderpriced { .. } => "replacement_underpriced",
+        PoolError::InsufficientBalance { .. } => "insufficient_funds",
+    }
+}
+
+fn exec_error_code(e: &ExecError) -> &'static str {
+    match e {
+        ExecError::InvalidSignature(_) => "invalid_signature",
+        ExecError::InvalidNonce { .. } => "nonce_too_low",
+        ExecError::InsufficientBalance => "insufficient_funds",
+        ExecError::BalanceOverflow => "balance_overflow",
+        ExecError::InvalidCode(_) => "invalid_code",
+        ExecError::BelowBaseFee { .. } => "fee_too_low",
+        ExecError::Storage(_) => "storage_error",
+    }
+}
+
+/// Encodes a quantity the JSON-RPC way: "0x" plus minimal lowercase hex, so zero is "0x0".
+/// Takes u64 or U256.
+///
+/// Every wei amount in a response (balances, values, gas prices, fees) goes through it, as a
+/// JSON number can't hold a U256. What fits in a u64 stays a JSON number on purpose, since
+/// pethit-client and the wallet read them as such: nonces, block numbers (`number`,
+/// `block_number`, heights), `timestamp`, gas amounts (`gas_used`) and counts. The exception is
+/// `/block_tx_count`, whose whole answer is one quantity, as in
+/// `eth_getBlockTransactionCountByHash`.
+pub fn hex_quantity(value: impl std::fmt::LowerHex) -> String {
+    format!("{value:#x}")
+}
+
+/// Encodes bytes as "0x" plus two hex characters per byte ("0x" when empty).
+pub fn hex_data(data: &[u8]) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+/// Parses a quantity written by `hex_quantity`. Leading zeros ("0x01") are rejected.
+pub fn parse_hex_quantity(input: &str) -> Result<U256, String> {
+    let digits = input
+        .strip_prefix("0x")
+        .ok_or_else(|| format!("invalid quantity {input:?}: missing 0x prefix"))?;
+    if digits.is_empty() {
+        return Err(format!("invalid quantity {input:?}: no digits"));
+    }
+    if digits.len() > 1 && digits.starts_with('0') {
+        return Err(format!("invalid quantity {input:?}: leading zeros"));
+    }
+    U256::from_str_radix(digits, 16).map_err(|e| format!("invalid quantity {input:?}: {e}"))
+}
+
+/// Parses byte data written by `hex_data`. An odd number of hex characters is rejected.
+pub fn parse_hex_data(input: &str) -> Result<Vec<u8>, String> {
+    let digits = input
+        .strip_prefix("0x")
+        .ok_or_else(|| format!("invalid data {input:?}: missing 0x prefix"))?;
+    hex::decode(digits).map_err(|e| format!("invalid data {input:?}: {e}"))
+}
+
+// Parses a 32-byte hex hash (0x prefix optional).
+// A malformed hash is a 400, so handlers can keep 404 for hashes that are just absent.
+fn parse_hash(input: &str) -> Result<B256, ApiError> {
+    let hex_part = input.strip_prefix("0x").unwrap_or(input);
+    if hex_part.len() != 64 {
+        let message = format!(
+            "invalid hash: expected 64 hex characters, got {}",
+            hex_part.len()
+        );
+        return Err(api_error(StatusCode::BAD_REQUEST, &message));
+    }
+
+    B256::from_str(hex_part)
+        .map_err(|_| api_error(StatusCode::BAD_REQUEST, "invalid hash: not a hex string"))
+}
 
 #[derive(Deserialize)]
 struct GetBlockRequest {
     hash: String,
 }
 
+#[derive(Deserialize)]
+struct GetBlockByNumberRequest {
+    number: u64,
+}
+
+// Query string of get_block. Like eth_getBlockByHash, `full=false` returns only tx hashes
+#[derive(Deserialize)]
+struct GetBlockQuery {
+    #[serde(default = "default_full")]
+    full: bool,
+}
+
+fn default_full() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+pub struct BlockResponse {
+    pub number: u64,
+    /// Block 0: its parent hash is zero and it has no transactions.
+    pub genesis: bool,
+    pub hash: String,
+    pub parent_hash: String,
+    pub timestamp: u64,
+    pub coinbase: String,
+    pub chain_id: u64,
+    pub extra_data: String,
+    pub transactions: BlockTransactions,
+}
+
+// Either the decoded transactions or just their hashes
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum BlockTransactions {
+    Full(Vec<TransactionResponse>),
+    Hashes(Vec<String>),
+}
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    pub status: String,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    pub height: u64,
+    pub total_transactions: u64,
+    pub avg_block_time_secs: f64,
+    pub pending_tx_count: usize,
+}
+
+#[derive(Serialize)]
+pub struct MempoolResponse {
+    pub count: usize,
+    pub oldest_age_secs: u64,
+    pub total_bytes: usize,
+    // Fees in wei, null when the pool is empty
+    pub min_fee: Option<String>,
+    pub max_fee: Option<String>,
+}
+
+/// Pooled transactions keyed by sender, then by nonce.
+pub type PoolContent = BTreeMap<String, BTreeMap<u64, PooledTransactionResponse>>;
+
+// `pending` transactions can be mined next: their nonces follow the sender's account nonce
+// without gaps. The rest wait in `queued`.
+#[derive(Serialize)]
+pub struct TxPoolContentResponse {
+    pub pending: PoolContent,
+    pub queued: PoolContent,
+}
+
+#[derive(Serialize)]
+pub struct PooledTransactionResponse {
+    pub hash: String,
+    pub to: String,
+    pub value: String,
+    pub nonce: u64,
+    pub gas_price: String,
+}
+
+#[derive(Serialize)]
+pub struct SyncStatusResponse {
+    pub syncing: bool,
+    pub current_block: u64,
+    pub highest_block: u64,
+}
+
+/// Dev mode settings. A node started without them serves no dev-only endpoint (the faucet).
+#[derive(Debug, Clone, Default)]
+pub struct DevConfig {
+    /// When set, dev-only requests must carry it
+    pub api_key: Option<String>,
+}
+
+/// How the server runs, besides the state it serves.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Requests running longer get a 408. The handlers re-executing blocks (`/import`,
+    /// `/debug/replay_block`) run off the async threads for it; the others only take short
+    /// locks and answer before they could time out.
+    pub request_timeout: Duration,
+    /// Largest request body accepted, in bytes.
+    pub body_limit: usize,
+    /// Most connections open at once.
+    pub max_connections: usize,
+    /// Prefix every route is served under (e.g. "/pethit"), None serves them at the root.
+    pub base_path: Option<String>,
+    /// Dev mode settings, None serves no dev-only endpoint.
+    pub dev: Option<DevConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            body_limit: DEFAULT_BODY_LIMIT,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            base_path: None,
+            dev: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AdminRequest {
+    #[serde(default)]
+    api_key: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PeerResponse {
+    pub address: String,
+    pub height: u64,
+    pub connected_secs: u64,
+}
+
+#[derive(Serialize)]
+pub struct GasPriceResponse {
+    pub gas_price: String,
+}
+
+// Query string of block_tx_count, raw_block and debug/replay_block
+#[derive(Deserialize)]
+struct BlockHashQuery {
+    hash: String,
+}
+
+// Query string of raw_tx
+#[derive(Deserialize)]
+struct TxHashQuery {
+    hash: String,
+}
+
+#[derive(Serialize)]
+pub struct ReplayBlockResponse {
+    pub recomputed_root: String,
+    pub stored_root: String,
+    pub matches: bool,
+}
+
+#[derive(Serialize)]
+pub struct FlushResponse {
+    pub flushed: bool,
+}
+
+#[derive(Deserialize)]
+struct FaucetRequest {
+    to: String,
+    // Wei, as a decimal string
+    amount: String,
+    #[serde(default)]
+    api_key: Option<String>,
+}
+
 #[derive(Clone)]
 struct AppState {
     storage: SharedStorage,
     txpool: SharedTxPool,
     chain: SharedChain,
+    dev: Option<DevConfig>,
+    // Bytes, for every endpoint but /import
+    body_limit: usize,
+}
+
+// Strip "0x", then decode the hex and the RLP into a SignedTransaction
+fn decode_raw_tx(raw_tx: &str) -> Result<SignedTransaction, String> {
+    let hex_data = raw_tx.strip_prefix("0x").unwrap_or(raw_tx);
+
+    let rlp_bytes = hex::decode(hex_data).map_err(|_| "Error: Invalid Hex string".to_string())?;
+
+    SignedTransaction::decode(&mut rlp_bytes.as_slice())
+        .map_err(|e| format!("Error decoding RLP: {}", e))
 }
 
 // Handler for POST /send_tx endpoint.
 async fn send_transaction(
     State(state): State<AppState>,
     Json(payload): Json<PutTransactionRequest>,
-) -> String {
-    // Strip "0x" and Decode Hex
-    let hex_data = payload.raw_tx.strip_prefix("0x").unwrap_or(&payload.raw_tx);
-
-    let rlp_bytes = match hex::decode(hex_data) {
-        Ok(b) => b,
-        Err(_) => return "Error: Invalid Hex string".to_string(),
-    };
-
-    // Decode RLP to SignedTransaction
-    let sig_tx = match SignedTransaction::decode(&mut rlp_bytes.as_slice()) {
-        Ok(tx) => tx,
-        Err(e) => return format!("Error decoding RLP: {}", e),
-    };
+) -> Result<Json<SendTransactionResponse>, ApiError> {
+    let sig_tx = decode_raw_tx(&payload.raw_tx).map_err(|e| coded_error("invalid_encoding", &e))?;
 
     // Calculate hash and add to the pool
-    let tx_hash = alloy_primitives::keccak256(&rlp_bytes);
+    let tx_hash = sig_tx.hash();
+
+    // The pool only dedups what it holds, mined transactions are checked against the chain
+    if let Some((_, location)) = state.chain.get_transaction_by_hash(tx_hash) {
+        return Err(coded_error(
+            "already_mined",
+            &format!(
+                "Transaction {} already mined in block #{}",
+                tx_hash, location.block_number
+            ),
+        ));
+    }
 
-    if let Err(e) = state.txpool.add(tx_hash, sig_tx) {
-        return format!("Error adding to the pool: {}", e);
+    // Against the current state. A nonce ahead of the account's can still be mined once the
+    // sender's pending transactions are, so only a used one is refused
+    match state
+        .storage
+        .view(|db| ExecutionEngine::validate(db, &sig_tx))
+    {
+        Err(ExecError::InvalidNonce { expected, got }) if got > expected => {}
+        Err(e) => return Err(coded_error(exec_error_code(&e), &e.to_string())),
+        Ok(_) => {}
     }
 
+    // Together with what the sender already has pooled
+    let balance = match sig_tx.recover_sender() {
+        Ok(sender) => state.storage.get_account(sender).balance,
+        Err(e) => return Err(coded_error("invalid_signature", &e)),
+    };
+    state
+        .txpool
+        .add_with_balance(tx_hash, sig_tx, balance)
+        .map_err(|e| {
+            coded_error(
+                pool_error_code(&e),
+                &format!("Error adding to the pool: {}", e),
+            )
+        })?;
+
     // Reply to the user
     println!("\n Added to pool tx with hash={:?}", tx_hash);
-    "Transaction received!".to_string()
+    Ok(Json(SendTransactionResponse {
+        tx_hash: tx_hash.to_string(),
+    }))
+}
+
+// Handler for POST /validate_tx
+// Runs the admission checks against the current state without pooling the transaction.
+async fn validate_transaction(
+    State(state): State<AppState>,
+    Json(payload): Json<PutTransactionRequest>,
+) -> Result<Json<ValidateTransactionResponse>, ApiError> {
+    let sig_tx =
+        decode_raw_tx(&payload.raw_tx).map_err(|e| api_error(StatusCode::BAD_REQUEST, &e))?;
+
+    let result = state
+        .storage
+        .view(|db| ExecutionEngine::validate(db, &sig_tx));
+
+    Ok(Json(ValidateTransactionResponse {
+        valid: result.is_ok(),
+        reason: result.err().map(|e| e.to_string()),
+    }))
+}
+
+// Handler for POST /decode_tx
+// Shows what a raw transaction contains. Nothing is pooled or executed.
+async fn decode_transaction(
+    Json(payload): Json<PutTransactionRequest>,
+) -> Result<Json<DecodedTransactionResponse>, ApiError> {
+    let sig_tx =
+        decode_raw_tx(&payload.raw_tx).map_err(|e| api_error(StatusCode::BAD_REQUEST, &e))?;
+    let from = sig_tx
+        .recover_sender()
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, &e))?;
+
+    let tx = sig_tx.transaction();
+    Ok(Json(DecodedTransactionResponse {
+        hash: sig_tx.hash().to_string(),
+        from: from.to_checksum(None),
+        to: tx.to.to_checksum(None),
+        value: hex_quantity(tx.value),
+        nonce: tx.nonce,
+        gas_price: hex_quantity(tx.gas_price),
+        data: hex_data(&tx.data),
+    }))
+}
+
+// Handler for POST /call
+// Executes the transaction against the current state without committing anything.
+async fn call(
+    State(state): State<AppState>,
+    Json(payload): Json<CallRequest>,
+) -> Result<Json<CallResponse>, ApiError> {
+    let sig_tx =
+        decode_raw_tx(&payload.raw_tx).map_err(|e| api_error(StatusCode::BAD_REQUEST, &e))?;
+
+    let (result, trace) = state.storage.view(|db| ExecutionEngine::trace(db, &sig_tx));
+
+    let (gas_used, logs, error) = match result {
+        Ok(receipt) => (receipt.gas_used, receipt.logs, None),
+        Err(e) => (0, Vec::new(), Some(e.to_string())),
+    };
+    Ok(Json(CallResponse {
+        success: error.is_none(),
+        error,
+        gas_used,
+        logs: logs.iter().map(log_response).collect(),
+        trace: payload.trace.then(|| trace_response(&trace)),
+    }))
+}
+
+fn trace_response(trace: &Trace) -> TraceResponse {
+    TraceResponse {
+        reads: trace
+            .reads
+            .iter()
+            .map(|address| address.to_checksum(None))
+            .collect(),
+        writes: trace
+            .writes
+            .iter()
+            .map(|write| StateWriteResponse {
+                address: write.address.to_checksum(None),
+                nonce_before: write.before.nonce,
+                nonce_after: write.after.nonce,
+                balance_before: hex_quantity(write.before.balance),
+                balance_after: hex_quantity(write.after.balance),
+            })
+            .collect(),
+        balance_changes: trace
+            .balance_changes
+            .iter()
+            .map(|change| BalanceChangeResponse {
+                address: change.address.to_checksum(None),
+                before: hex_quantity(change.before),
+                after: hex_quantity(change.after),
+            })
+            .collect(),
+    }
 }
 
 // Handler for POST /get_account
@@ -91,92 +661,2136 @@ async fn get_account_by_address(
     //Get account from storage
     let account = state.storage.get_account(address);
 
-    // Return JSON
+    // Return JSON (EIP-55 checksummed, whatever casing the client sent)
     Json(AccountResponse {
-        address: payload.address,
+        address: address.to_checksum(None),
         nonce: account.nonce,
-        balance: account.balance.to_string(),
+        balance: hex_quantity(account.balance),
     })
 }
 
-// TODO: Refactor get_tx so it is searched in the chain, not in the storage.
-// Probably will require a new block method to return a tx given the hash. Is there a fast way to get a tx?
-// Probably this will be implemented in iteration 4 after the block history is part of the db, not some random chain variable
-// // Handler
-// // This function runs when someone hits the GET /get_tx endpoint.
-// async fn get_transaction(
-//     State(state): State<AppState>,
-//     Json(payload): Json<GetTransactionRequest>,
-// ) -> String {
-//     let tx_hash = payload.hash.into_bytes();
-
-//     // Get it from the shared storage
-//     let tx = match state.storage.get(&tx_hash) {
-//         Some(tx) => tx,
-//         None => {
-//             return "Error getting transaction from hash".to_string();
-//         }
-//     };
-
-//     // Reply to the user
-//     println!(
-//         "Got transaction with that hash. Sent to={:?} with value={:?}",
-//         String::from_utf8_lossy(&tx.to),
-//         String::from_utf8_lossy(&tx.value)
-//     );
-//     "Transaction retrieved and printed!".to_string()
-// }
-
-// Handler for GET /get_block
+// Handler for GET /account?address=...
+// Balance and nonce in one call, read under the same lock
+async fn get_account_info(
+    State(state): State<AppState>,
+    Query(query): Query<AccountQuery>,
+) -> Result<Json<AccountInfoResponse>, ApiError> {
+    let address = Address::from_str(&query.address)
+        .map_err(|_| api_error(StatusCode::BAD_REQUEST, "invalid address"))?;
+
+    let (exists, account) = state.storage.view(|db| {
+        let exists = db.get(address.as_slice()).is_some();
+        (exists, db.get_account(&address))
+    });
+
+    Ok(Json(AccountInfoResponse {
+        balance: hex_quantity(account.balance),
+        nonce: account.nonce,
+        exists,
+    }))
+}
+
+// Handler for POST /get_tx
+// Transactions are looked up in the chain (only mined ones are found).
+async fn get_transaction(
+    State(state): State<AppState>,
+    Json(payload): Json<GetTransactionRequest>,
+) -> Result<Json<TransactionResponse>, ApiError> {
+    let hash = parse_hash(&payload.hash)?;
+
+    // Get it from the shared blockchain
+    let (sig_tx, location) = state
+        .chain
+        .get_transaction_by_hash(hash)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "not found"))?;
+
+    Ok(Json(transaction_response(&sig_tx, location)))
+}
+
+// Handler for GET /raw_tx: the mined transaction's RLP, as `send_tx` takes it
+async fn get_raw_transaction(
+    State(state): State<AppState>,
+    Query(query): Query<TxHashQuery>,
+) -> Result<Json<String>, ApiError> {
+    let hash = parse_hash(&query.hash)?;
+    let (sig_tx, _) = state
+        .chain
+        .get_transaction_by_hash(hash)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "not found"))?;
+    Ok(Json(hex_data(&alloy_rlp::encode(&sig_tx))))
+}
+
+// Decoded view of a mined transaction
+fn transaction_response(sig_tx: &SignedTransaction, location: TxLocation) -> TransactionResponse {
+    // Mined transactions were already verified, so recovery can't fail here
+    let from = sig_tx.recover_sender().unwrap_or(Address::ZERO);
+
+    TransactionResponse {
+        hash: sig_tx.hash().to_string(),
+        from: from.to_checksum(None),
+        to: sig_tx.transaction().to.to_checksum(None),
+        value: hex_quantity(sig_tx.transaction().value),
+        nonce: sig_tx.transaction().nonce,
+        gas_price: hex_quantity(sig_tx.transaction().gas_price),
+        data: hex_data(&sig_tx.transaction().data),
+        block_hash: location.block_hash.to_string(),
+        block_number: location.block_number,
+    }
+}
+
+// Handler for POST /get_receipt
+async fn get_receipt(
+    State(state): State<AppState>,
+    Json(payload): Json<GetTransactionRequest>,
+) -> Result<Json<ReceiptResponse>, ApiError> {
+    let hash = parse_hash(&payload.hash)?;
+
+    let (receipt, location) = state
+        .chain
+        .get_receipt(hash)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "not found"))?;
+
+    Ok(Json(ReceiptResponse {
+        tx_hash: receipt.tx_hash.to_string(),
+        block_hash: location.block_hash.to_string(),
+        block_number: location.block_number,
+        gas_used: receipt.gas_used,
+        effective_gas_price: hex_quantity(receipt.effective_gas_price),
+        logs: receipt.logs.iter().map(log_response).collect(),
+    }))
+}
+
+// Handler for POST /get_logs (eth_getLogs-style)
+async fn get_logs(
+    State(state): State<AppState>,
+    Json(payload): Json<GetLogsRequest>,
+) -> Result<Json<Vec<LogResponse>>, ApiError> {
+    if payload.from > payload.to {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "from must not be greater than to",
+        ));
+    }
+
+    let address = payload
+        .address
+        .map(|address| Address::from_str(&address))
+        .transpose()
+        .map_err(|_| api_error(StatusCode::BAD_REQUEST, "invalid address format"))?;
+
+    let logs = state.chain.get_logs(payload.from, payload.to, address);
+    Ok(Json(logs.iter().map(log_response).collect()))
+}
+
+fn log_response(log: &Log) -> LogResponse {
+    LogResponse {
+        address: log.address.to_checksum(None),
+        topics: log.topics.iter().map(|topic| topic.to_string()).collect(),
+        data: hex_data(&log.data),
+    }
+}
+
+// Handler for POST /get_block
 async fn get_block_by_hash(
     State(state): State<AppState>,
+    Query(query): Query<GetBlockQuery>,
     Json(payload): Json<GetBlockRequest>,
-) -> String {
-    let hash = match B256::from_str(&payload.hash) {
-        Ok(hash) => hash,
-        Err(_) => return "Invalid hash format".to_string(),
-    };
+) -> Result<Json<BlockResponse>, ApiError> {
+    let hash = parse_hash(&payload.hash)?;
 
     // Get it from the shared blockchain
-    let block = match state.chain.get_block_by_hash(hash) {
-        Some(block) => block,
-        None => {
-            return "Error getting block".to_string();
+    let block = state
+        .chain
+        .get_block_by_hash(hash)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "not found"))?;
+
+    Ok(Json(block_response(&block, query.full)))
+}
+
+// Handler for POST /get_block_by_number
+// Pruned blocks are a 410: the chain still knows their hash, but not their contents
+async fn get_block_by_number(
+    State(state): State<AppState>,
+    Query(query): Query<GetBlockQuery>,
+    Json(payload): Json<GetBlockByNumberRequest>,
+) -> Result<Json<BlockResponse>, ApiError> {
+    match state.chain.lookup_block_by_number(payload.number) {
+        BlockLookup::Found(block) => Ok(Json(block_response(&block, query.full))),
+        BlockLookup::Pruned { number, hash } => {
+            let message = format!("block {} ({}) was pruned", number, hash);
+            Err(api_error(StatusCode::GONE, &message))
         }
-    };
+        BlockLookup::NotFound => Err(api_error(StatusCode::NOT_FOUND, "not found")),
+    }
+}
 
-    // Reply to the user
-    format!(
-        "Found Block!\nNumber: {}\nHash: {}\nParent: {}\nTxs: {} \n",
-        block.id,
-        block.k_hash,
-        block.parent_hash,
-        block.transactions.len()
-    )
+// Handler for GET /head
+// Returns the tip of the chain
+async fn get_head(
+    State(state): State<AppState>,
+    Query(query): Query<GetBlockQuery>,
+) -> Json<BlockResponse> {
+    Json(block_response(&state.chain.last_block(), query.full))
 }
 
-// The Server Builder
-pub async fn start_server(storage: SharedStorage, txpool: SharedTxPool, chain: SharedChain) {
-    // Create the state object
-    let state = AppState {
-        storage,
-        txpool,
-        chain,
-    };
+// Handler for GET /block_tx_count?hash=...
+// Like eth_getBlockTransactionCountByHash: the count alone, as a hex quantity
+async fn get_block_tx_count(
+    State(state): State<AppState>,
+    Query(query): Query<BlockHashQuery>,
+) -> Result<Json<String>, ApiError> {
+    let hash = parse_hash(&query.hash)?;
+    match state.chain.lookup_block_by_hash(hash) {
+        BlockLookup::Found(block) => Ok(Json(hex_quantity(block.transactions.len()))),
+        BlockLookup::Pruned { number, hash } => {
+            let message = format!("block {} ({}) was pruned", number, hash);
+            Err(api_error(StatusCode::GONE, &message))
+        }
+        BlockLookup::NotFound => Err(api_error(StatusCode::NOT_FOUND, "not found")),
+    }
+}
 
-    // Build the router and inject the state
-    let app = Router::new()
-        .route("/send_tx", post(send_transaction))
-        //.route("/get_tx", get(get_transaction))
-        .route("/get_account", post(get_account_by_address))
-        .route("/get_block", post(get_block_by_hash))
-        .with_state(state);
+// Handler for GET /raw_block: the block's RLP, as `export` writes it and `import` takes it
+async fn get_raw_block(
+    State(state): State<AppState>,
+    Query(query): Query<BlockHashQuery>,
+) -> Result<Json<String>, ApiError> {
+    let hash = parse_hash(&query.hash)?;
+    match state.chain.lookup_block_by_hash(hash) {
+        BlockLookup::Found(block) => Ok(Json(hex_data(&alloy_rlp::encode(&block.block)))),
+        BlockLookup::Pruned { number, hash } => {
+            let message = format!("block {} ({}) was pruned", number, hash);
+            Err(api_error(StatusCode::GONE, &message))
+        }
+        BlockLookup::NotFound => Err(api_error(StatusCode::NOT_FOUND, "not found")),
+    }
+}
 
-    // Define the address
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    println!("RPC Server listening on {}", addr);
+// Handler for GET /gas_price
+// Median gas price of the transactions in the last GAS_PRICE_BLOCKS blocks, never below what the
+// pool and the next block accept (the floor is the answer when there's no history)
+async fn get_gas_price(State(state): State<AppState>) -> Json<GasPriceResponse> {
+    let tip = state.chain.last_block();
+    let floor = state.txpool.min_gas_price().max(next_base_fee(&tip));
 
-    //start the server
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let from = (tip.id + 1).saturating_sub(GAS_PRICE_BLOCKS);
+    let mut prices: Vec<U256> = state
+        .chain
+        .get_blocks(from, tip.id)
+        .iter()
+        .flat_map(|block| &block.transactions)
+        .map(|tx| tx.transaction().gas_price)
+        .collect();
+    prices.sort();
+    let median = prices.get(prices.len() / 2).copied().unwrap_or(U256::ZERO);
+
+    Json(GasPriceResponse {
+        gas_price: hex_quantity(median.max(floor)),
+    })
+}
+
+// Query string of get_blocks, both bounds included
+#[derive(Deserialize)]
+struct GetBlocksQuery {
+    from: u64,
+    to: u64,
+}
+
+// Handler for GET /blocks: summaries (tx hashes only) of the blocks `from..=to`, in ascending order.
+// Ranges past the tip are cut at the tip, ranges wider than MAX_BLOCKS_PAGE are a 400
+async fn get_blocks(
+    State(state): State<AppState>,
+    Query(query): Query<GetBlocksQuery>,
+) -> Result<Json<Vec<BlockResponse>>, ApiError> {
+    if query.to < query.from {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "invalid range: `to` is below `from`",
+        ));
+    }
+    // `to - from + 1` overflows for the full u64 range
+    let span = query.to - query.from;
+    if span >= MAX_BLOCKS_PAGE {
+        let message = format!(
+            "range of {} blocks exceeds the maximum of {}, request a smaller range",
+            u128::from(span) + 1,
+            MAX_BLOCKS_PAGE
+        );
+        return Err(api_error(StatusCode::BAD_REQUEST, &message));
+    }
+
+    let blocks = state.chain.get_blocks(query.from, query.to);
+    Ok(Json(
+        blocks
+            .iter()
+            .map(|block| block_response(block, false))
+            .collect(),
+    ))
+}
+
+// Sent instead of the blocks a slow subscriber missed
+#[derive(Serialize)]
+pub struct LaggedResponse {
+    pub missed: u64,
+    pub message: String,
+}
+
+// Handler for GET /admin/peers: an empty list when the node has no peers
+async fn get_peers(State(state): State<AppState>) -> Json<Vec<PeerResponse>> {
+    let peers = state.chain.peers().into_iter().map(|peer| PeerResponse {
+        address: peer.address,
+        height: peer.height,
+        connected_secs: peer.connected_for.as_secs(),
+    });
+    Json(peers.collect())
+}
+
+// Handler for GET /subscribe_blocks: a server-sent `block` event (with tx hashes) per new block.
+// A subscriber that falls too far behind gets a `lagged` event, then the oldest block still buffered
+async fn subscribe_blocks(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let receiver = state.chain.subscribe_blocks();
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(block) => Event::default()
+                .event("block")
+                .json_data(block_response(&block, false)),
+            Err(RecvError::Lagged(missed)) => {
+                Event::default().event("lagged").json_data(LaggedResponse {
+                    missed,
+                    message: "you missed blocks, resync".to_string(),
+                })
+            }
+            Err(RecvError::Closed) => return None,
+        };
+        Some((event, receiver))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+fn block_response(block: &SealedBlock, full: bool) -> BlockResponse {
+    let transactions = if full {
+        let txs = block
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(index, sig_tx)| {
+                let location = TxLocation {
+                    block_hash: block.k_hash,
+                    block_number: block.id,
+                    index,
+                };
+                transaction_response(sig_tx, location)
+            });
+        BlockTransactions::Full(txs.collect())
+    } else {
+        let hashes = block.transactions.iter().map(|tx| tx.hash().to_string());
+        BlockTransactions::Hashes(hashes.collect())
+    };
+
+    BlockResponse {
+        number: block.id,
+        genesis: block.id == 0,
+        hash: block.k_hash.to_string(),
+        parent_hash: block.parent_hash.to_string(),
+        timestamp: block.timestamp,
+        coinbase: block.coinbase.to_checksum(None),
+        chain_id: block.chain_id,
+        extra_data: hex_data(&block.extra_data),
+        transactions,
+    }
+}
+
+// Handler for GET /health: answers as long as the server does (for load balancers)
+async fn get_health() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok".to_string(),
+    })
+}
+
+// Handler for GET /stats
+async fn get_stats(State(state): State<AppState>) -> Json<StatsResponse> {
+    let stats = state.chain.stats();
+
+    Json(StatsResponse {
+        height: stats.height,
+        total_transactions: stats.total_transactions,
+        avg_block_time_secs: stats.avg_block_time_secs,
+        pending_tx_count: state.txpool.len(),
+    })
+}
+
+// Handler for GET /mempool
+async fn get_mempool(State(state): State<AppState>) -> Json<MempoolResponse> {
+    let stats = state.txpool.stats();
+
+    Json(MempoolResponse {
+        count: stats.count,
+        oldest_age_secs: stats.oldest_age.as_secs(),
+        total_bytes: stats.total_bytes,
+        min_fee: stats.min_fee.map(hex_quantity),
+        max_fee: stats.max_fee.map(hex_quantity),
+    })
+}
+
+// Handler for GET /txpool_content
+async fn get_txpool_content(State(state): State<AppState>) -> Json<TxPoolContentResponse> {
+    let mut content = TxPoolContentResponse {
+        pending: BTreeMap::new(),
+        queued: BTreeMap::new(),
+    };
+
+    for (sender, txs) in state.txpool.content_by_sender() {
+        let mut next_nonce = state.storage.get_account(sender).nonce;
+        for (nonce, pooled) in txs {
+            let tx = pooled.tx.transaction();
+            let response = PooledTransactionResponse {
+                hash: pooled.tx.hash().to_string(),
+                to: tx.to.to_checksum(None),
+                value: hex_quantity(tx.value),
+                nonce,
+                gas_price: hex_quantity(tx.gas_price),
+            };
+            // Nonces come in order, so the first gap sends the rest to `queued`
+            let group = if nonce == next_nonce {
+                next_nonce += 1;
+                &mut content.pending
+            } else {
+                &mut content.queued
+            };
+            group
+                .entry(sender.to_checksum(None))
+                .or_default()
+                .insert(nonce, response);
+        }
+    }
+    Json(content)
+}
+
+// Handler for GET /sync_status (eth_syncing-style)
+async fn get_sync_status(State(state): State<AppState>) -> Json<SyncStatusResponse> {
+    let status = state.chain.sync_status();
+
+    Json(SyncStatusResponse {
+        syncing: status.syncing,
+        current_block: status.current_block,
+        highest_block: status.highest_block,
+    })
+}
+
+// Query string of export. Missing bounds mean the whole chain
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    from: u64,
+    #[serde(default = "default_to")]
+    to: u64,
+}
+
+fn default_to() -> u64 {
+    u64::MAX
+}
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    pub imported: usize,
+    pub head: u64,
+}
+
+// Handler for GET /export
+// Downloads the RLP-encoded blocks `from..=to` (cut at the tip)
+async fn export_blocks(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    let data = state.chain.export(query.from, query.to);
+    (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"blocks.rlp\"",
+            ),
+        ],
+        data,
+    )
+}
+
+// Runs `work` on the blocking pool. Handlers are plain code under std locks, so one that takes
+// long (re-executing blocks) would never yield and the request timeout could never answer.
+// After a timeout the work still runs to the end, only its answer is dropped
+async fn run_blocking<T, F>(work: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .unwrap_or_else(|e| Err(api_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())))
+}
+
+// Handler for POST /import
+// Takes the raw bytes of an export. Blocks are re-executed and validated before being appended.
+// Blocks already in the chain are skipped, a different block at a known height is a 409
+async fn import(
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<ImportResponse>, ApiError> {
+    run_blocking(move || {
+        let imported = import_blocks(&state.chain, &state.storage, &body).map_err(|e| {
+            let status = match e {
+                ChainError::Conflict { .. } => StatusCode::CONFLICT,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            api_error(status, &e.to_string())
+        })?;
+
+        Ok(Json(ImportResponse {
+            imported,
+            head: state.chain.last_block().id,
+        }))
+    })
+    .await
+}
+
+// Dev-only endpoints answer 403 outside dev mode, and 401 without the node's API key (if it has one)
+fn require_dev(state: &AppState, api_key: &Option<String>, endpoint: &str) -> Result<(), ApiError> {
+    let Some(dev) = &state.dev else {
+        let message = format!("{} is disabled: the node is not in dev mode", endpoint);
+        return Err(api_error(StatusCode::FORBIDDEN, &message));
+    };
+    if dev.api_key.is_some() && dev.api_key != *api_key {
+        return Err(api_error(StatusCode::UNAUTHORIZED, "invalid API key"));
+    }
+    Ok(())
+}
+
+// Handler for POST /debug/replay_block?hash=...
+// Re-executes a block on the state after its parent and compares the root it gets to the one
+// the block was sealed with. Needs the parent's version to still be in the storage history.
+async fn replay_block(
+    State(state): State<AppState>,
+    Query(query): Query<BlockHashQuery>,
+) -> Result<Json<ReplayBlockResponse>, ApiError> {
+    let hash = parse_hash(&query.hash)?;
+    run_blocking(move || replay(&state, hash)).await
+}
+
+// Body of replay_block, run on the blocking pool
+fn replay(state: &AppState, hash: B256) -> Result<Json<ReplayBlockResponse>, ApiError> {
+    let block = state
+        .chain
+        .get_block_by_hash(hash)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "not found"))?;
+    if block.id == 0 {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "the genesis block has no parent to replay on",
+        ));
+    }
+    let mut parent_state = state.storage.state_at(block.id - 1).ok_or_else(|| {
+        let message = format!("the state after block {} is not available", block.id - 1);
+        api_error(StatusCode::GONE, &message)
+    })?;
+
+    let env = BlockEnv {
+        coinbase: block.coinbase,
+        base_fee: block.base_fee,
+        reward: BLOCK_REWARD,
+    };
+    let result =
+        ExecutionEngine::execute_block(&mut parent_state, block.transactions.clone(), &env)
+            .map_err(|e| {
+                let message = format!("replay failed: {}", e);
+                api_error(StatusCode::INTERNAL_SERVER_ERROR, &message)
+            })?;
+
+    Ok(Json(ReplayBlockResponse {
+        recomputed_root: result.state_root.to_string(),
+        stored_root: block.state_root.to_string(),
+        matches: result.state_root == block.state_root,
+    }))
+}
+
+// Handler for POST /admin/flush (dev mode only)
+// Answers once the storage backend has made every write durable
+async fn flush_storage(
+    State(state): State<AppState>,
+    Json(payload): Json<AdminRequest>,
+) -> Result<Json<FlushResponse>, ApiError> {
+    require_dev(&state, &payload.api_key, "flush")?;
+    state
+        .storage
+        .flush()
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))?;
+
+    Ok(Json(FlushResponse { flushed: true }))
+}
+
+// Handler for POST /faucet (dev mode only)
+// Credits the address in storage directly, no transaction involved
+async fn faucet(
+    State(state): State<AppState>,
+    Json(payload): Json<FaucetRequest>,
+) -> Result<Json<AccountResponse>, ApiError> {
+    require_dev(&state, &payload.api_key, "faucet")?;
+    let address = Address::from_str(&payload.to)
+        .map_err(|_| api_error(StatusCode::BAD_REQUEST, "invalid address"))?;
+    let amount = U256::from_str(&payload.amount)
+        .map_err(|_| api_error(StatusCode::BAD_REQUEST, "invalid amount"))?;
+
+    let account = state
+        .storage
+        .update(|db| {
+            let mut account = db.get_account(&address);
+            account.balance = account.balance.saturating_add(amount);
+            db.set_account(address, account.clone()).map(|()| account)
+        })
+        .map_err(|e| {
+            let status = match e {
+                StorageError::Full => StatusCode::INSUFFICIENT_STORAGE,
+                StorageError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            api_error(status, &e.to_string())
+        })?;
+
+    Ok(Json(AccountResponse {
+        address: address.to_checksum(None),
+        nonce: account.nonce,
+        balance: hex_quantity(account.balance),
+    }))
+}
+
+// Build the router and inject the state
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/send_tx", post(send_transaction))
+        .route("/validate_tx", post(validate_transaction))
+        .route("/decode_tx", post(decode_transaction))
+        .route("/call", post(call))
+        .route("/get_tx", post(get_transaction))
+        .route("/get_account", post(get_account_by_address))
+        .route("/account", get(get_account_info))
+        .route("/raw_tx", get(get_raw_transaction))
+        .route("/get_receipt", post(get_receipt))
+        .route("/get_logs", post(get_logs))
+        .route("/get_block", post(get_block_by_hash))
+        .route("/get_block_by_number", post(get_block_by_number))
+        .route("/head", get(get_head))
+        .route("/blocks", get(get_blocks))
+        .route("/block_tx_count", get(get_block_tx_count))
+        .route("/raw_block", get(get_raw_block))
+        .route("/gas_price", get(get_gas_price))
+        .route("/subscribe_blocks", get(subscribe_blocks))
+        .route("/health", get(get_health))
+        .route("/stats", get(get_stats))
+        .route("/sync_status", get(get_sync_status))
+        .route("/mempool", get(get_mempool))
+        .route("/txpool_content", get(get_txpool_content))
+        .route("/export", get(export_blocks))
+        .route(
+            "/import",
+            post(import).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)),
+        )
+        .route("/faucet", post(faucet))
+        .route("/debug/replay_block", post(replay_block))
+        .route("/admin/flush", post(flush_storage))
+        .route("/admin/peers", get(get_peers))
+        .fallback(|| async { api_error(StatusCode::NOT_FOUND, "not found") })
+        .layer(DefaultBodyLimit::max(state.body_limit))
+        .layer(map_response(json_errors))
+        .with_state(state)
+}
+
+// Mounts every route under `base_path` (e.g. "/pethit"), or at the root for None or "/".
+// Anything outside of it is a 404.
+fn with_base_path(router: Router, base_path: Option<&str>) -> Router {
+    match base_path.map(|path| path.trim_end_matches('/')) {
+        None | Some("") => router,
+        Some(path) => Router::new()
+            .nest(path, router)
+            .fallback(|| async { api_error(StatusCode::NOT_FOUND, "not found") }),
+    }
+}
+
+// Answers 408 to requests whose handler runs longer than `timeout`.
+// Read and write endpoints share it.
+fn with_timeout(router: Router, timeout: Duration) -> Router {
+    router
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            timeout,
+        ))
+        // The 408 is produced outside the router, so it needs its own pass
+        .layer(map_response(json_errors))
+}
+
+// Rewrites the errors axum and tower produce on their own (bad JSON bodies, bad query
+// strings, timeouts...) as plain text or empty bodies into an ErrorResponse.
+async fn json_errors(response: Response) -> Response {
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "application/json");
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+
+    let body = to_bytes(response.into_body(), 64 * 1024)
+        .await
+        .unwrap_or_default();
+    let message = match String::from_utf8_lossy(&body).trim() {
+        "" => status.canonical_reason().unwrap_or("error").to_lowercase(),
+        text => text.to_string(),
+    };
+    api_error(status, &message).into_response()
+}
+
+// Accepts a connection only once it has a permit, so at most `max` are open at a time.
+// The rest aren't refused: they queue in the OS backlog (and time out there if it's full).
+struct LimitedListener {
+    listener: TcpListener,
+    permits: Arc<Semaphore>,
+}
+
+impl LimitedListener {
+    fn new(listener: TcpListener, max: usize) -> Self {
+        LimitedListener {
+            listener,
+            permits: Arc::new(Semaphore::new(max)),
+        }
+    }
+}
+
+impl axum::serve::Listener for LimitedListener {
+    type Io = LimitedStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        // The semaphore is never closed
+        let permit = self.permits.clone().acquire_owned().await.unwrap();
+        let (stream, addr) = axum::serve::Listener::accept(&mut self.listener).await;
+        (
+            LimitedStream {
+                stream,
+                _permit: permit,
+            },
+            addr,
+        )
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.listener.local_addr()
+    }
+}
+
+// A connection holding its permit; dropping it (the connection closed) frees the slot.
+struct LimitedStream {
+    stream: TcpStream,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl AsyncRead for LimitedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for LimitedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+// Serves `app` on `listener`, with at most `max_connections` open at once.
+async fn serve(listener: TcpListener, app: Router, max_connections: usize) -> io::Result<()> {
+    axum::serve(LimitedListener::new(listener, max_connections), app).await
+}
+
+// The Server Builder
+pub async fn start_server(
+    storage: SharedStorage,
+    txpool: SharedTxPool,
+    chain: SharedChain,
+    config: ServerConfig,
+) {
+    // Create the state object
+    let state = AppState {
+        storage,
+        txpool,
+        chain,
+        dev: config.dev,
+        body_limit: config.body_limit,
+    };
+
+    let app = with_timeout(
+        with_base_path(router(state), config.base_path.as_deref()),
+        config.request_timeout,
+    );
+
+    // Define the address
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    println!("RPC Server listening on {}", addr);
+
+    //start the server
+    let listener = TcpListener::bind(addr).await.unwrap();
+    serve(listener, app, config.max_connections).await.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{U256, keccak256};
+    use alloy_rlp::Encodable;
+    use axum::body::Body;
+    use axum::http::Request;
+    use futures_util::StreamExt;
+    use k256::ecdsa::SigningKey;
+    use pethit_consensus::{BLOCK_REWARD, Block, handshake, next_base_fee, tx_root};
+    use pethit_execution::{BlockEnv, Transaction};
+    use tower::ServiceExt;
+
+    fn test_state() -> AppState {
+        AppState {
+            storage: SharedStorage::new(),
+            txpool: SharedTxPool::new(),
+            chain: SharedChain::new(),
+            dev: None,
+            body_limit: DEFAULT_BODY_LIMIT,
+        }
+    }
+
+    // Helper for a state where the test sender holds some funds
+    fn funded_state() -> AppState {
+        let state = test_state();
+        let account = pethit_storage::Account {
+            nonce: 0,
+            balance: U256::from(1_000_000),
+        };
+        state.storage.set_account(test_sender(), account).unwrap();
+        state
+    }
+
+    fn test_key() -> SigningKey {
+        SigningKey::from_bytes(&[1u8; 32].into()).unwrap()
+    }
+
+    fn test_sender() -> Address {
+        let public_key = test_key().verifying_key().to_encoded_point(false);
+        let hash = keccak256(&public_key.as_bytes()[1..]);
+        Address::from_slice(&hash[12..])
+    }
+
+    // Helper to sign a transaction with a fixed key
+    fn signed_tx(nonce: u64) -> SignedTransaction {
+        let signing_key = test_key();
+        let tx = Transaction {
+            to: Address::ZERO,
+            value: U256::from(100),
+            nonce,
+            gas_price: U256::from(1),
+            data: Vec::new(),
+        };
+        let (signature, recovery_id) = signing_key
+            .sign_prehash_recoverable(tx.hash().as_slice())
+            .unwrap();
+
+        SignedTransaction::new(tx, signature, recovery_id)
+    }
+
+    // Helper to hex encode a signed transaction as the wallet does
+    fn raw_tx(sig_tx: &SignedTransaction) -> String {
+        let mut rlp_bytes = Vec::new();
+        sig_tx.encode(&mut rlp_bytes);
+        hex::encode(rlp_bytes)
+    }
+
+    // Helper to send a request and return the status and parsed body
+    async fn send(state: AppState, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = router(state).oneshot(request).await.unwrap();
+
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    async fn get_json(state: AppState, uri: &str) -> (StatusCode, serde_json::Value) {
+        send(state, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    // Helper to POST a JSON body and return the status and parsed body
+    async fn post_json(
+        state: AppState,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        send(state, request).await
+    }
+
+    // Helper to execute and mine a block with the given transactions on top of the tip
+    // (same rules as the miner, so the blocks can be imported elsewhere)
+    fn mine(state: &AppState, transactions: Vec<SignedTransaction>) -> SealedBlock {
+        let coinbase = Address::with_last_byte(0xcb);
+        let count = transactions.len();
+        let parent = state.chain.last_block();
+        let base_fee = next_base_fee(&parent);
+        let env = BlockEnv {
+            coinbase,
+            base_fee,
+            reward: BLOCK_REWARD,
+        };
+        let result = state
+            .storage
+            .update(|db| ExecutionEngine::execute_block(db, transactions, &env))
+            .unwrap();
+        assert_eq!(
+            result.transactions.len(),
+            count,
+            "all transactions must be valid"
+        );
+
+        let block = Block {
+            id: parent.id + 1,
+            tx_root: tx_root(&result.transactions),
+            transactions: result.transactions,
+            parent_hash: parent.k_hash,
+            // One block every 5 seconds
+            timestamp: parent.timestamp + 5,
+            coinbase,
+            chain_id: parent.chain_id,
+            difficulty: parent.difficulty,
+            state_root: result.state_root,
+            logs_bloom: result.logs_bloom,
+            gas_limit: parent.gas_limit,
+            gas_used: result.gas_used,
+            base_fee,
+            extra_data: Vec::new(),
+        }
+        .seal();
+        state
+            .chain
+            .add_block(block.clone(), result.receipts)
+            .unwrap();
+        state.storage.commit_version(block.id);
+        block
+    }
+
+    #[tokio::test]
+    async fn test_get_tx_found() {
+        let state = funded_state();
+        let sig_tx = signed_tx(0);
+        let tx_hash = sig_tx.hash();
+
+        // Mine it into block #1
+        let block = mine(&state, vec![sig_tx]);
+
+        let (status, body) = post_json(
+            state,
+            "/get_tx",
+            serde_json::json!({ "hash": tx_hash.to_string() }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["hash"], tx_hash.to_string());
+        assert_eq!(body["value"], "0x64");
+        assert_eq!(body["nonce"], 0);
+        assert_eq!(body["block_hash"], block.k_hash.to_string());
+        assert_eq!(body["block_number"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_tx_not_found() {
+        let state = test_state();
+
+        let (status, body) = post_json(
+            state,
+            "/get_tx",
+            serde_json::json!({ "hash": B256::ZERO.to_string() }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, serde_json::json!({ "error": "not found" }));
+    }
+
+    #[tokio::test]
+    async fn test_get_account_returns_checksummed_address() {
+        let state = test_state();
+
+        // EIP-55 test vector, sent all lowercase
+        let (status, body) = post_json(
+            state,
+            "/get_account",
+            serde_json::json!({ "address": "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed" }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["address"],
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+    }
+
+    // Helper to fund the test sender and validate a transaction
+    async fn validate(sig_tx: &SignedTransaction, balance: u64) -> serde_json::Value {
+        let state = test_state();
+        state
+            .storage
+            .set_account(
+                test_sender(),
+                pethit_storage::Account {
+                    nonce: 0,
+                    balance: U256::from(balance),
+                },
+            )
+            .unwrap();
+
+        let (status, body) = post_json(
+            state.clone(),
+            "/validate_tx",
+            serde_json::json!({ "raw_tx": raw_tx(sig_tx) }),
+        )
+        .await;
+
+        // Nothing gets pooled
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.txpool.get_all_transactions().is_empty());
+        body
+    }
+
+    #[tokio::test]
+    async fn test_decode_tx() {
+        let sig_tx = signed_tx(4);
+
+        let (status, body) = post_json(
+            test_state(),
+            "/decode_tx",
+            serde_json::json!({ "raw_tx": raw_tx(&sig_tx) }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["from"], test_sender().to_checksum(None));
+        assert_eq!(body["to"], Address::ZERO.to_checksum(None));
+        assert_eq!(body["value"], "0x64");
+        assert_eq!(body["nonce"], 4);
+        assert_eq!(body["hash"], sig_tx.hash().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_decode_tx_unrecoverable_signature() {
+        let valid_tx = signed_tx(0);
+        let sig_tx = SignedTransaction::new(
+            valid_tx.transaction().clone(),
+            *valid_tx.signature(),
+            k256::ecdsa::RecoveryId::from_byte(2).unwrap(),
+        );
+
+        let (status, body) = post_json(
+            test_state(),
+            "/decode_tx",
+            serde_json::json!({ "raw_tx": raw_tx(&sig_tx) }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Invalid signature");
+    }
+
+    #[tokio::test]
+    async fn test_validate_tx_valid() {
+        let body = validate(&signed_tx(0), 1_000_000).await;
+
+        assert_eq!(body, serde_json::json!({ "valid": true }));
+    }
+
+    #[tokio::test]
+    async fn test_validate_tx_invalid_signature() {
+        let valid_tx = signed_tx(0);
+        // An x-reduced recovery id can't recover a key for this signature
+        let sig_tx = SignedTransaction::new(
+            valid_tx.transaction().clone(),
+            *valid_tx.signature(),
+            k256::ecdsa::RecoveryId::from_byte(2).unwrap(),
+        );
+
+        let body = validate(&sig_tx, 1_000_000).await;
+
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["reason"], "Invalid signature");
+    }
+
+    #[tokio::test]
+    async fn test_validate_tx_invalid_nonce() {
+        let body = validate(&signed_tx(3), 1_000_000).await;
+
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["reason"], "Invalid nonce. Expected 0, got 3");
+    }
+
+    #[tokio::test]
+    async fn test_validate_tx_insufficient_funds() {
+        let body = validate(&signed_tx(0), 10).await;
+
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["reason"], "Insufficient funds");
+    }
+
+    #[tokio::test]
+    async fn test_validate_tx_malformed() {
+        let (status, body) = post_json(
+            test_state(),
+            "/validate_tx",
+            serde_json::json!({ "raw_tx": "0xnot-hex" }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Error: Invalid Hex string");
+    }
+
+    #[tokio::test]
+    async fn test_get_block_full() {
+        let state = funded_state();
+        let sig_tx = signed_tx(0);
+        let block = mine(&state, vec![sig_tx.clone()]);
+
+        let (status, body) = post_json(
+            state,
+            "/get_block",
+            serde_json::json!({ "hash": block.k_hash.to_string() }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["number"], 1);
+        assert_eq!(body["parent_hash"], block.parent_hash.to_string());
+
+        let tx = &body["transactions"][0];
+        assert_eq!(tx["hash"], sig_tx.hash().to_string());
+        assert_eq!(tx["to"], Address::ZERO.to_checksum(None));
+        assert_eq!(tx["value"], "0x64");
+        assert_eq!(tx["nonce"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_block_hashes_only() {
+        let state = funded_state();
+        let sig_tx = signed_tx(0);
+        let block = mine(&state, vec![sig_tx.clone()]);
+
+        let (status, body) = post_json(
+            state,
+            "/get_block?full=false",
+            serde_json::json!({ "hash": block.k_hash.to_string() }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["transactions"],
+            serde_json::json!([sig_tx.hash().to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_block_malformed_hash() {
+        // Too short
+        let (status, body) = post_json(
+            test_state(),
+            "/get_block",
+            serde_json::json!({ "hash": "0x1234" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["error"],
+            "invalid hash: expected 64 hex characters, got 4"
+        );
+
+        // Right length, not hex
+        let (status, body) = post_json(
+            test_state(),
+            "/get_block",
+            serde_json::json!({ "hash": format!("0x{}", "zz".repeat(32)) }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid hash: not a hex string");
+    }
+
+    #[tokio::test]
+    async fn test_get_block_not_found() {
+        let (status, body) = post_json(
+            test_state(),
+            "/get_block",
+            serde_json::json!({ "hash": B256::repeat_byte(0xab).to_string() }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, serde_json::json!({ "error": "not found" }));
+    }
+
+    #[tokio::test]
+    async fn test_export_then_import_reproduces_tip() {
+        let state = funded_state();
+        for nonce in 0..3 {
+            mine(&state, vec![signed_tx(nonce)]);
+        }
+
+        // Range past the tip, genesis left out
+        let request = Request::get("/export?from=1&to=100")
+            .body(Body::empty())
+            .unwrap();
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/octet-stream"
+        );
+        let exported = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        // A fresh node from the same genesis state
+        let fresh = funded_state();
+        let request = Request::post("/import").body(Body::from(exported)).unwrap();
+        let (status, body) = send(fresh.clone(), request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({ "imported": 3, "head": 3 }));
+        assert_eq!(
+            fresh.chain.last_block().k_hash,
+            state.chain.last_block().k_hash
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_garbage() {
+        let request = Request::post("/import")
+            .body(Body::from(vec![0xffu8; 8]))
+            .unwrap();
+        let (status, _) = send(test_state(), request).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_import_conflicting_block_is_409() {
+        let state = funded_state();
+        mine(&state, vec![signed_tx(0)]);
+        // Another block 1, without the transaction
+        let other = funded_state();
+        mine(&other, Vec::new());
+
+        let request = Request::post("/import")
+            .body(Body::from(state.chain.export(1, 1)))
+            .unwrap();
+        let (status, _) = send(other, request).await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_sync_status_not_syncing() {
+        let state = test_state();
+        mine(&state, Vec::new());
+
+        let (status, body) = get_json(state, "/sync_status").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            serde_json::json!({ "syncing": false, "current_block": 1, "highest_block": 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_status_behind_peer() {
+        let state = test_state();
+        // A peer announces a tip ahead of ours
+        state.chain.report_peer_height(10);
+
+        let (_, body) = get_json(state.clone(), "/sync_status").await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "syncing": true, "current_block": 0, "highest_block": 10 })
+        );
+
+        // Once caught up
+        for _ in 0..10 {
+            mine(&state, Vec::new());
+        }
+        let (_, body) = get_json(state, "/sync_status").await;
+        assert_eq!(body["syncing"], false);
+    }
+
+    #[tokio::test]
+    async fn test_get_receipt_with_logs() {
+        let state = funded_state();
+        let sig_tx = signed_tx(0);
+        let block = mine(&state, vec![sig_tx.clone()]);
+
+        let (status, body) = post_json(
+            state,
+            "/get_receipt",
+            serde_json::json!({ "hash": sig_tx.hash().to_string() }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["block_hash"], block.k_hash.to_string());
+        let log = &body["logs"][0];
+        assert_eq!(log["address"], test_sender().to_checksum(None));
+        assert_eq!(log["topics"][1], test_sender().into_word().to_string());
+        assert_eq!(log["topics"][2], Address::ZERO.into_word().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_get_logs() {
+        let state = funded_state();
+        mine(&state, vec![signed_tx(0)]);
+        mine(&state, vec![signed_tx(1)]);
+
+        let (status, body) = post_json(
+            state.clone(),
+            "/get_logs",
+            serde_json::json!({ "from": 2, "to": 2, "address": test_sender().to_string() }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+
+        let (status, _) = post_json(
+            state,
+            "/get_logs",
+            serde_json::json!({ "from": 2, "to": 1 }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_send_tx_pools_transaction() {
+        let state = funded_state();
+        let sig_tx = signed_tx(0);
+
+        let (status, body) = post_json(
+            state.clone(),
+            "/send_tx",
+            serde_json::json!({ "raw_tx": raw_tx(&sig_tx) }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["tx_hash"], sig_tx.hash().to_string());
+        assert_eq!(state.txpool.get_all_transactions(), vec![sig_tx]);
+    }
+
+    #[tokio::test]
+    async fn test_send_tx_rejects_mined_transaction() {
+        let state = funded_state();
+        let sig_tx = signed_tx(0);
+        mine(&state, vec![sig_tx.clone()]);
+
+        let (status, body) = post_json(
+            state.clone(),
+            "/send_tx",
+            serde_json::json!({ "raw_tx": raw_tx(&sig_tx) }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["error"],
+            format!("Transaction {} already mined in block #1", sig_tx.hash())
+        );
+        assert!(state.txpool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mempool() {
+        let state = test_state();
+        let (_, body) = get_json(state.clone(), "/mempool").await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "count": 0,
+                "oldest_age_secs": 0,
+                "total_bytes": 0,
+                "min_fee": null,
+                "max_fee": null
+            })
+        );
+
+        let txs = [signed_tx(0), signed_tx(1)];
+        for tx in &txs {
+            state.txpool.add(tx.hash(), tx.clone()).unwrap();
+        }
+        let (status, body) = get_json(state, "/mempool").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["count"], 2);
+        assert_eq!(body["total_bytes"], txs[0].length() + txs[1].length());
+        assert_eq!(body["min_fee"], hex_quantity(txs[0].transaction().fee()));
+    }
+
+    #[tokio::test]
+    async fn test_txpool_content_groups_by_sender() {
+        let state = test_state();
+        let other_key = SigningKey::from_bytes(&[2u8; 32].into()).unwrap();
+        let other_tx = {
+            let tx = Transaction {
+                to: Address::ZERO,
+                value: U256::from(5),
+                nonce: 0,
+                gas_price: U256::from(1),
+                data: Vec::new(),
+            };
+            let (signature, recovery_id) = other_key
+                .sign_prehash_recoverable(tx.hash().as_slice())
+                .unwrap();
+            SignedTransaction::new(tx, signature, recovery_id)
+        };
+        let other_sender = other_tx.recover_sender().unwrap();
+        // Nonce 2 is missing, so 3 can't be mined yet
+        for tx in [signed_tx(3), signed_tx(1), signed_tx(0), other_tx.clone()] {
+            state.txpool.add(tx.hash(), tx).unwrap();
+        }
+
+        let (status, body) = get_json(state, "/txpool_content").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let sender = test_sender().to_checksum(None);
+        let pending = &body["pending"][&sender];
+        assert_eq!(pending.as_object().unwrap().len(), 2);
+        assert_eq!(pending["0"]["nonce"], 0);
+        assert_eq!(pending["1"]["hash"], signed_tx(1).hash().to_string());
+        assert_eq!(body["queued"][&sender]["3"]["nonce"], 3);
+        assert_eq!(
+            body["pending"][other_sender.to_checksum(None)]["0"]["hash"],
+            other_tx.hash().to_string()
+        );
+        assert_eq!(body["queued"].as_object().unwrap().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_timeout() {
+        let slow = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "done"
+            }),
+        );
+        let app = with_timeout(slow, Duration::from_millis(20));
+
+        let request = Request::get("/slow").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    }
+
+    #[tokio::test]
+    async fn test_blocking_handler_times_out() {
+        // The handler blocks until the test releases it, after the timeout has answered
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let released = Arc::new(std::sync::Mutex::new(released));
+        let stuck = Router::new().route(
+            "/stuck",
+            get(move || async move {
+                run_blocking(move || {
+                    let _ = released.lock().unwrap().recv();
+                    Ok("done")
+                })
+                .await
+            }),
+        );
+        let app = with_timeout(stuck, Duration::from_millis(20));
+
+        let request = Request::get("/stuck").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        drop(release);
+
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected() {
+        let state = AppState {
+            body_limit: 1024,
+            ..test_state()
+        };
+        let body = serde_json::json!({ "raw_tx": "00".repeat(1024) });
+
+        let (status, body) = post_json(state.clone(), "/send_tx", body).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(body["error"].as_str().unwrap().contains("length limit"));
+
+        // Under the limit the body reaches the handler
+        let body = serde_json::json!({ "raw_tx": "zz" });
+        let (status, _) = post_json(state, "/send_tx", body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_fast_request_within_timeout() {
+        let app = with_timeout(router(test_state()), Duration::from_secs(5));
+
+        let request = Request::get("/head").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_json() {
+        // Success
+        let request = Request::get("/head").body(Body::empty()).unwrap();
+        let response = router(test_state()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        // Body that isn't JSON (axum rejects it as text/plain on its own)
+        let request = Request::post("/get_tx")
+            .header("content-type", "application/json")
+            .body(Body::from("{not json"))
+            .unwrap();
+        let response = router(test_state()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        // Unknown route
+        let (status, body) = get_json(test_state(), "/nope").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, serde_json::json!({ "error": "not found" }));
+    }
+
+    #[tokio::test]
+    async fn test_call_with_trace() {
+        let state = funded_state();
+        let sig_tx = signed_tx(0);
+
+        let (status, body) = post_json(
+            state.clone(),
+            "/call",
+            serde_json::json!({ "raw_tx": raw_tx(&sig_tx), "trace": true }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+        assert_eq!(body["gas_used"], 21000);
+        let changes = body["trace"]["balance_changes"].as_array().unwrap();
+        assert_eq!(changes.len(), 2);
+        let sender_write = body["trace"]["writes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|write| write["address"] == test_sender().to_checksum(None))
+            .unwrap();
+        assert_eq!(sender_write["nonce_before"], 0);
+        assert_eq!(sender_write["nonce_after"], 1);
+        // Nothing was committed
+        assert_eq!(state.storage.get_account(test_sender()).nonce, 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_without_trace() {
+        let (status, body) = post_json(
+            test_state(),
+            "/call",
+            serde_json::json!({ "raw_tx": raw_tx(&signed_tx(0)) }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"], "Insufficient funds");
+        assert!(body.get("trace").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_gas_price_is_recent_median() {
+        let priced_tx = |nonce, gas_price| {
+            let tx = Transaction {
+                to: Address::ZERO,
+                value: U256::from(100),
+                nonce,
+                gas_price: U256::from(gas_price),
+                data: Vec::new(),
+            };
+            let (signature, recovery_id) = test_key()
+                .sign_prehash_recoverable(tx.hash().as_slice())
+                .unwrap();
+            SignedTransaction::new(tx, signature, recovery_id)
+        };
+
+        // No history: the pool's floor
+        let state = AppState {
+            txpool: SharedTxPool::with_config(pethit_txpool::PoolConfig {
+                min_gas_price: U256::from(3),
+                ..Default::default()
+            }),
+            ..funded_state()
+        };
+        let (status, body) = get_json(state.clone(), "/gas_price").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["gas_price"], "0x3");
+
+        mine(&state, vec![priced_tx(0, 2), priced_tx(1, 8)]);
+        mine(&state, vec![priced_tx(2, 4)]);
+        mine(&state, Vec::new());
+        let (_, body) = get_json(state.clone(), "/gas_price").await;
+        assert_eq!(body["gas_price"], "0x4");
+
+        // Still floored when the median is below it
+        let state = AppState {
+            txpool: SharedTxPool::with_config(pethit_txpool::PoolConfig {
+                min_gas_price: U256::from(5),
+                ..Default::default()
+            }),
+            ..state
+        };
+        let (_, body) = get_json(state, "/gas_price").await;
+        assert_eq!(body["gas_price"], "0x5");
+    }
+
+    #[tokio::test]
+    async fn test_get_genesis_block() {
+        let state = test_state();
+        let genesis_hash = state.chain.genesis_hash();
+        mine(&state, vec![]);
+
+        let by_number = serde_json::json!({ "number": 0 });
+        let (status, body) = post_json(state.clone(), "/get_block_by_number", by_number).await;
+        assert_eq!(status, StatusCode::OK);
+        let by_hash = serde_json::json!({ "hash": genesis_hash.to_string() });
+        let (status, same) = post_json(state.clone(), "/get_block", by_hash).await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(body, same);
+        assert_eq!(body["number"], 0);
+        assert_eq!(body["genesis"], true);
+        assert_eq!(body["hash"], genesis_hash.to_string());
+        assert_eq!(body["parent_hash"], B256::ZERO.to_string());
+        assert_eq!(body["transactions"], serde_json::json!([]));
+
+        // Any other block isn't genesis
+        let (_, body) = post_json(
+            state.clone(),
+            "/get_block_by_number",
+            serde_json::json!({ "number": 1 }),
+        )
+        .await;
+        assert_eq!(body["genesis"], false);
+        let (status, _) = post_json(
+            state,
+            "/get_block_by_number",
+            serde_json::json!({ "number": 2 }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_head() {
+        let state = test_state();
+        let block = mine(&state, Vec::new());
+
+        let (status, body) = get_json(state, "/head").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["number"], 1);
+        assert_eq!(body["hash"], block.k_hash.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let state = funded_state();
+        mine(&state, vec![signed_tx(0)]);
+        mine(&state, Vec::new());
+        mine(&state, vec![signed_tx(1), signed_tx(2)]);
+        let pending = signed_tx(3);
+        state.txpool.add(pending.hash(), pending).unwrap();
+
+        let (status, body) = get_json(state, "/stats").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["height"], 3);
+        assert_eq!(body["total_transactions"], 3);
+        assert_eq!(body["avg_block_time_secs"], 5.0);
+        assert_eq!(body["pending_tx_count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_faucet_credits_in_dev_mode() {
+        let state = AppState {
+            dev: Some(DevConfig {
+                api_key: Some("secret".to_string()),
+            }),
+            ..funded_state()
+        };
+        let body = serde_json::json!({
+            "to": test_sender().to_string(),
+            "amount": "500",
+            "api_key": "secret",
+        });
+
+        let (status, response) = post_json(state.clone(), "/faucet", body).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["balance"], "0xf4434");
+        assert_eq!(
+            state.storage.get_account(test_sender()).balance,
+            U256::from(1_000_500)
+        );
+
+        // Wrong key
+        let body = serde_json::json!({ "to": test_sender().to_string(), "amount": "500" });
+        let (status, _) = post_json(state, "/faucet", body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_faucet_rejected_outside_dev_mode() {
+        let state = test_state();
+        let body = serde_json::json!({ "to": test_sender().to_string(), "amount": "500" });
+
+        let (status, response) = post_json(state.clone(), "/faucet", body).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            response["error"],
+            "faucet is disabled: the node is not in dev mode"
+        );
+        assert_eq!(state.storage.get_account(test_sender()).balance, U256::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_admin_flush() {
+        let (status, _) = post_json(test_state(), "/admin/flush", serde_json::json!({})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let state = AppState {
+            dev: Some(DevConfig::default()),
+            ..test_state()
+        };
+        let (status, body) = post_json(state, "/admin/flush", serde_json::json!({})).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["flushed"], true);
+    }
+
+    #[test]
+    fn test_hex_quantity_is_minimal() {
+        assert_eq!(hex_quantity(0u64), "0x0");
+        assert_eq!(hex_quantity(1u64), "0x1");
+        assert_eq!(hex_quantity(0x400u64), "0x400");
+        assert_eq!(hex_quantity(U256::MAX), format!("0x{}", "f".repeat(64)));
+    }
+
+    #[test]
+    fn test_parse_hex_quantity_rejects_non_minimal() {
+        assert_eq!(parse_hex_quantity("0x0"), Ok(U256::ZERO));
+        assert_eq!(parse_hex_quantity("0x400"), Ok(U256::from(1024)));
+        assert!(parse_hex_quantity("0x00").is_err());
+        assert!(parse_hex_quantity("0x0400").is_err());
+        assert!(parse_hex_quantity("0x").is_err());
+        assert!(parse_hex_quantity("400").is_err());
+        assert!(parse_hex_quantity("0xzz").is_err());
+    }
+
+    #[test]
+    fn test_hex_data_keeps_leading_zeros() {
+        assert_eq!(hex_data(&[]), "0x");
+        assert_eq!(hex_data(&[0, 0, 1]), "0x000001");
+        assert_eq!(parse_hex_data("0x000001"), Ok(vec![0, 0, 1]));
+        assert!(parse_hex_data("0x001").is_err());
+        assert!(parse_hex_data("000001").is_err());
+    }
+
+    #[test]
+    fn test_hex_round_trips() {
+        for value in [U256::ZERO, U256::from(1), U256::from(1_000_500), U256::MAX] {
+            assert_eq!(parse_hex_quantity(&hex_quantity(value)), Ok(value));
+        }
+        for data in [vec![], vec![0], vec![0xde, 0xad, 0xbe, 0xef]] {
+            assert_eq!(parse_hex_data(&hex_data(&data)), Ok(data));
+        }
+    }
+
+    // Reads the next server-sent event of `body` as (event name, JSON data)
+    async fn next_event(
+        body: &mut (impl Stream<Item = Result<Bytes, axum::Error>> + Unpin),
+    ) -> (String, serde_json::Value) {
+        let mut text = String::new();
+        while !text.contains("\n\n") {
+            let chunk = body.next().await.unwrap().unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        let field = |name: &str| {
+            text.lines()
+                .find_map(|line| line.strip_prefix(name))
+                .unwrap()
+                .to_string()
+        };
+        let data = serde_json::from_str(&field("data: ")).unwrap();
+        (field("event: "), data)
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_blocks() {
+        let state = test_state();
+        let request = Request::get("/subscribe_blocks")
+            .body(Body::empty())
+            .unwrap();
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+
+        let block = mine(&state, Vec::new());
+        let (event, data) = next_event(&mut body).await;
+        assert_eq!(event, "block");
+        assert_eq!(data["number"], 1);
+        assert_eq!(data["hash"], block.k_hash.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_slow_block_subscriber_is_told_to_resync() {
+        let state = AppState {
+            chain: SharedChain::new().with_block_channel_capacity(1),
+            ..test_state()
+        };
+        let request = Request::get("/subscribe_blocks")
+            .body(Body::empty())
+            .unwrap();
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        let mut body = response.into_body().into_data_stream();
+
+        // Mining goes on while the subscriber doesn't read
+        for _ in 0..3 {
+            mine(&state, Vec::new());
+        }
+        assert_eq!(state.chain.last_block().id, 3);
+
+        let (event, data) = next_event(&mut body).await;
+        assert_eq!(event, "lagged");
+        assert_eq!(data["missed"], 2);
+        assert_eq!(data["message"], "you missed blocks, resync");
+        let (event, data) = next_event(&mut body).await;
+        assert_eq!(event, "block");
+        assert_eq!(data["number"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_blocks_range() {
+        let state = test_state();
+        let mined: Vec<_> = (0..4).map(|_| mine(&state, Vec::new())).collect();
+
+        let (status, body) = get_json(state.clone(), "/blocks?from=2&to=3").await;
+        assert_eq!(status, StatusCode::OK);
+        let blocks = body.as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["number"], 2);
+        assert_eq!(blocks[0]["hash"], mined[1].k_hash.to_string());
+        assert_eq!(blocks[1]["number"], 3);
+
+        // Cut at the tip
+        let (_, body) = get_json(state.clone(), "/blocks?from=3&to=50").await;
+        let numbers: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b["number"].clone())
+            .collect();
+        assert_eq!(numbers, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_get_blocks_page_cap() {
+        let (status, body) = get_json(test_state(), "/blocks?from=0&to=99").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+
+        let (status, body) = get_json(test_state(), "/blocks?from=0&to=100").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["error"],
+            "range of 101 blocks exceeds the maximum of 100, request a smaller range"
+        );
+
+        let (status, _) = get_json(test_state(), "/blocks?from=5&to=4").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let uri = format!("/blocks?from=0&to={}", u64::MAX);
+        let (status, body) = get_json(test_state(), &uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["error"],
+            "range of 18446744073709551616 blocks exceeds the maximum of 100, request a smaller range"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_peers() {
+        let (first, second) = (test_state(), test_state());
+        let (status, body) = get_json(first.clone(), "/admin/peers").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!([]));
+
+        mine(&second, Vec::new());
+        handshake(
+            &first.chain,
+            "127.0.0.1:30301",
+            &second.chain,
+            "127.0.0.1:30302",
+        )
+        .unwrap();
+
+        let (_, body) = get_json(first, "/admin/peers").await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["address"], "127.0.0.1:30302");
+        assert_eq!(body[0]["height"], 1);
+        assert_eq!(body[0]["connected_secs"], 0);
+        let (_, body) = get_json(second, "/admin/peers").await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["address"], "127.0.0.1:30301");
+        assert_eq!(body[0]["height"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_send_tx_error_codes() {
+        let state = AppState {
+            txpool: SharedTxPool::with_config(pethit_txpool::PoolConfig {
+                min_gas_price: U256::from(2),
+                ..Default::default()
+            }),
+            ..funded_state()
+        };
+        let send = |sig_tx: SignedTransaction| {
+            let body = serde_json::json!({ "raw_tx": raw_tx(&sig_tx) });
+            post_json(state.clone(), "/send_tx", body)
+        };
+
+        // Pays 1, the pool wants 2
+        let (status, body) = send(signed_tx(0)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "fee_too_low");
+        assert_eq!(
+            body["error"],
+            "Error adding to the pool: Gas price 1 is below the minimum of 2"
+        );
+
+        // The account is already at nonce 1
+        let account = pethit_storage::Account {
+            nonce: 1,
+            balance: U256::from(1_000_000),
+        };
+        state.storage.set_account(test_sender(), account).unwrap();
+        let (status, body) = send(signed_tx(0)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "nonce_too_low");
+        assert_eq!(body["error"], "Invalid nonce. Expected 1, got 0");
+
+        let (_, body) = post_json(
+            state.clone(),
+            "/send_tx",
+            serde_json::json!({ "raw_tx": "zz" }),
+        )
+        .await;
+        assert_eq!(body["code"], "invalid_encoding");
+        assert!(state.txpool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_tx_accepts_future_nonce() {
+        let state = funded_state();
+        let sig_tx = signed_tx(3);
+        let body = serde_json::json!({ "raw_tx": raw_tx(&sig_tx) });
+
+        let (status, _) = post_json(state.clone(), "/send_tx", body).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.txpool.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_tx_insufficient_funds() {
+        let sig_tx = signed_tx(0);
+        let body = serde_json::json!({ "raw_tx": raw_tx(&sig_tx) });
+
+        let (status, body) = post_json(test_state(), "/send_tx", body).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "insufficient_funds");
+    }
+
+    #[tokio::test]
+    async fn test_replay_block_matches() {
+        let state = funded_state();
+        state.storage.commit_version(0);
+        mine(&state, vec![signed_tx(0)]);
+        let block = mine(&state, vec![signed_tx(1)]);
+
+        let uri = format!("/debug/replay_block?hash={}", block.k_hash);
+        let (status, body) = post_json(state.clone(), &uri, serde_json::json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["matches"], true);
+        assert_eq!(body["recomputed_root"], block.state_root.to_string());
+        assert_eq!(body["stored_root"], block.state_root.to_string());
+
+        // Without the parent's state there's nothing to replay on
+        state.storage.prune_history(2);
+        let (status, _) = post_json(state, &uri, serde_json::json!({})).await;
+        assert_eq!(status, StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn test_block_tx_count() {
+        let state = funded_state();
+        let block = mine(&state, vec![signed_tx(0), signed_tx(1), signed_tx(2)]);
+
+        let uri = format!("/block_tx_count?hash={}", block.k_hash);
+        let (status, body) = get_json(state.clone(), &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "0x3");
+
+        let uri = format!("/block_tx_count?hash={}", B256::repeat_byte(1));
+        let (status, _) = get_json(state, &uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_account_info_after_a_transfer() {
+        let state = funded_state();
+        let recipient = Address::ZERO;
+        mine(&state, vec![signed_tx(0)]);
+
+        let uri = format!("/account?address={}", recipient);
+        let (status, body) = get_json(state.clone(), &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            serde_json::json!({ "balance": "0x64", "nonce": 0, "exists": true })
+        );
+
+        let uri = format!("/account?address={}", test_sender());
+        let (_, body) = get_json(state.clone(), &uri).await;
+        assert_eq!(body["nonce"], 1);
+        assert_eq!(body["exists"], true);
+
+        let uri = format!("/account?address={}", Address::with_last_byte(0x42));
+        let (_, body) = get_json(state.clone(), &uri).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "balance": "0x0", "nonce": 0, "exists": false })
+        );
+
+        let (status, _) = get_json(state, "/account?address=0x1234").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_connections_past_the_limit_wait_for_a_slot() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, router(test_state()), 2));
+        let request = b"GET /stats HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n";
+
+        // Two idle connections take both slots
+        let first = TcpStream::connect(addr).await.unwrap();
+        let _second = TcpStream::connect(addr).await.unwrap();
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        third.write_all(request).await.unwrap();
+        let mut response = Vec::new();
+        let waiting =
+            tokio::time::timeout(Duration::from_millis(200), third.read_to_end(&mut response));
+        assert!(waiting.await.is_err());
+        assert!(response.is_empty());
+
+        // Closing one lets the queued connection in, and it's served normally
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(5), third.read_to_end(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn test_routes_nest_under_the_base_path() {
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+        let app = with_base_path(router(test_state()), Some("/api"));
+        let response = app.clone().oneshot(get("/api/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, serde_json::json!({ "status": "ok" }));
+
+        let response = app.oneshot(get("/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, serde_json::json!({ "error": "not found" }));
+
+        // "/" is the root
+        let app = with_base_path(router(test_state()), Some("/"));
+        let response = app.oneshot(get("/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_raw_tx_and_block_decode_back() {
+        let state = funded_state();
+        let tx = signed_tx(0);
+        let block = mine(&state, vec![tx.clone()]);
+
+        let (status, body) = get_json(state.clone(), &format!("/raw_tx?hash={}", tx.hash())).await;
+        assert_eq!(status, StatusCode::OK);
+        let raw = parse_hex_data(body.as_str().unwrap()).unwrap();
+        let decoded = SignedTransaction::decode(&mut raw.as_slice()).unwrap();
+        assert_eq!(decoded.hash(), tx.hash());
+        assert_eq!(decoded.transaction(), tx.transaction());
+
+        let uri = format!("/raw_block?hash={}", block.k_hash);
+        let (status, body) = get_json(state.clone(), &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        let raw = parse_hex_data(body.as_str().unwrap()).unwrap();
+        let decoded = Block::decode(&mut raw.as_slice()).unwrap();
+        assert_eq!(decoded.seal().k_hash, block.k_hash);
+
+        for endpoint in ["raw_tx", "raw_block"] {
+            let uri = format!("/{}?hash={}", endpoint, B256::repeat_byte(1));
+            let (status, _) = get_json(state.clone(), &uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+    }
 }
@@ -1,10 +1,33 @@
-use alloy_primitives::{Address, U256};
+use alloy_primitives::{Address, B256, U256, keccak256};
 use alloy_rlp::{Decodable, Encodable, RlpDecodable, RlpEncodable};
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
 };
 
+// Snapshot file layout: MAGIC | VERSION | entry count (u64) | entries.
+// Each entry is: key len (u32) | key | value len (u32) | value. Integers are big endian.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"PETH";
+const SNAPSHOT_VERSION: u8 = 1;
+
+// Streamed export layout: STREAM_MAGIC | entries | end marker.
+// Entries are framed as in snapshots, the end marker is a zero key length (keys are never empty).
+const STREAM_MAGIC: &[u8; 4] = b"PETS";
+// Compressed layout: COMPRESSED_MAGIC | lz4 block, prefixed with its uncompressed size
+const COMPRESSED_MAGIC: &[u8; 4] = b"PETZ";
+// Journal layout: JOURNAL_MAGIC | block (u64) | entries | end marker | keccak256 of all before.
+// Entries are framed as in streamed exports. A journal whose hash doesn't match was torn.
+const JOURNAL_MAGIC: &[u8; 4] = b"PWAL";
+// How many entries an export reads per lock acquisition
+const EXPORT_CHUNK: usize = 256;
+
+// How many accounts SharedStorage keeps decoded in memory by default
+const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
 /// Represents a single user's state.
 #[derive(Debug, Clone, Default, PartialEq, Eq, RlpEncodable, RlpDecodable)]
 pub struct Account {
@@ -12,33 +35,76 @@ pub struct Account {
     pub balance: U256,
 }
 
-/// This struct holds the information of all accounts
-#[derive(Debug, Default)]
-pub struct SimpleStorage {
-    pub accounts: HashMap<Vec<u8>, Vec<u8>>,
+/// Why a backend refused a write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    /// No room left for the entry (a bounded backend at capacity, a full disk...).
+    Full,
+    /// Any other failure of the backend.
+    Io(String),
 }
 
-impl SimpleStorage {
-    /// Creates a new, empty storage instance.
-    pub fn new() -> Self {
-        // Default::default() tells Rust how to create a default SimpleStorage.
-        Self::default()
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Full => write!(f, "storage is full"),
+            StorageError::Io(reason) => write!(f, "storage error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::StorageFull => StorageError::Full,
+            _ => StorageError::Io(e.to_string()),
+        }
+    }
+}
+
+// For the file operations, which report io errors
+impl From<StorageError> for io::Error {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Full => io::Error::from(io::ErrorKind::StorageFull),
+            StorageError::Io(reason) => io::Error::other(reason),
+        }
+    }
+}
+
+/// A raw key/value store the state lives in (in-memory, on disk, a test mock...).
+/// Accounts are stored RLP-encoded under their address.
+pub trait StorageBackend {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Fails if the backend can't take the entry, e.g. `StorageError::Full`.
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError>;
+
+    /// Returns the removed value, if there was one.
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// All entries, in no particular order: `state_root` and the proofs sort them by key
+    /// first (a `HashMap` backend iterates differently from run to run).
+    fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_>;
+
+    /// Makes the writes so far durable. Backends that don't persist have nothing to do.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 
     /// Helper to update an account
-    pub fn set_account(&mut self, addr: Address, account: Account) {
-        let key_bytes = addr.to_vec();
+    fn set_account(&mut self, addr: Address, account: Account) -> Result<(), StorageError> {
         // Account to RLP
         let mut value_bytes = Vec::new();
         account.encode(&mut value_bytes);
-        self.accounts.insert(key_bytes, value_bytes);
+        self.put(addr.to_vec(), value_bytes)
     }
 
     /// Helper to get an account info
-    pub fn get_account(&self, addr: &Address) -> Account {
-        let key_bytes = addr.as_slice();
-
-        match self.accounts.get(key_bytes) {
+    fn get_account(&self, addr: &Address) -> Account {
+        match self.get(addr.as_slice()) {
             Some(bytes) => {
                 // RLP to Account
                 Account::decode(&mut bytes.as_slice()).unwrap_or_default()
@@ -46,12 +112,516 @@ impl SimpleStorage {
             None => Account::default(),
         }
     }
+
+    /// Helper to read a slot of an account's storage (zero if never written)
+    fn get_storage(&self, addr: &Address, slot: U256) -> U256 {
+        match self.get(&storage_key(addr, slot)) {
+            Some(bytes) => U256::from_be_slice(&bytes),
+            None => U256::ZERO,
+        }
+    }
+
+    /// Helper to write a slot of an account's storage
+    fn set_storage(&mut self, addr: Address, slot: U256, value: U256) -> Result<(), StorageError> {
+        self.put(storage_key(&addr, slot), value.to_be_bytes::<32>().to_vec())
+    }
+
+    /// Commitment to the whole state: root of the binary Merkle tree whose leaves are the
+    /// entries sorted by key (see `entry_leaf`). keccak256 of nothing for an empty state.
+    /// Storage slots are entries like accounts, so they're in the same order. A backend that
+    /// overrides this (e.g. one keeping a tree) must hash the entries in that same key order,
+    /// or its roots won't match the other backends'.
+    fn state_root(&self) -> B256 {
+        let levels = entry_levels(&sorted_entries(self));
+        levels[levels.len() - 1]
+            .first()
+            .copied()
+            .unwrap_or(keccak256([]))
+    }
+}
+
+// Key of a storage slot: the account's address followed by the 32-byte slot.
+// Account entries are keyed by the bare address, so the two never collide.
+fn storage_key(addr: &Address, slot: U256) -> Vec<u8> {
+    let mut key = addr.to_vec();
+    key.extend_from_slice(&slot.to_be_bytes::<32>());
+    key
+}
+
+// The entries of `backend` sorted by key
+fn sorted_entries<B: StorageBackend + ?Sized>(backend: &B) -> Vec<(&[u8], &[u8])> {
+    let mut entries: Vec<_> = backend.iter().collect();
+    entries.sort();
+    entries
+}
+
+// The Merkle tree over sorted `entries`
+fn entry_levels(entries: &[(&[u8], &[u8])]) -> Vec<Vec<B256>> {
+    let leaves = entries
+        .iter()
+        .map(|(key, value)| entry_leaf(key, value))
+        .collect();
+    merkle_levels(leaves)
+}
+
+/// Leaf of a state entry: keccak over the length-prefixed key and value.
+pub fn entry_leaf(key: &[u8], value: &[u8]) -> B256 {
+    let mut data = Vec::with_capacity(8 + key.len() + value.len());
+    data.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    data.extend_from_slice(key);
+    data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    data.extend_from_slice(value);
+    keccak256(data)
+}
+
+/// Every level of the binary keccak Merkle tree over `leaves`, from the leaves up to the root.
+/// A node without a sibling moves up unchanged. The last level is empty for no leaves.
+pub fn merkle_levels(leaves: Vec<B256>) -> Vec<Vec<B256>> {
+    let mut levels = vec![leaves];
+    while levels.last().unwrap().len() > 1 {
+        let level = levels.last().unwrap();
+        let parents = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => merkle_parent(*left, *right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(parents);
+    }
+    levels
+}
+
+fn merkle_parent(left: B256, right: B256) -> B256 {
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(left.as_slice());
+    data[32..].copy_from_slice(right.as_slice());
+    keccak256(data)
+}
+
+/// Siblings of leaf `index` on its way up `levels`, bottom-up.
+/// Levels where the node has no sibling (odd one out) have no entry.
+pub fn merkle_branch(levels: &[Vec<B256>], mut index: usize) -> Vec<B256> {
+    let mut siblings = Vec::new();
+    for level in &levels[..levels.len() - 1] {
+        if let Some(sibling) = level.get(index ^ 1) {
+            siblings.push(*sibling);
+        }
+        index /= 2;
+    }
+    siblings
+}
+
+/// Whether `siblings` (from `merkle_branch`) lead from `leaf`, at `index` of `leaf_count`, to `root`.
+pub fn verify_merkle_branch(
+    leaf: B256,
+    index: usize,
+    leaf_count: usize,
+    siblings: &[B256],
+    root: B256,
+) -> bool {
+    if index >= leaf_count {
+        return false;
+    }
+    let mut siblings = siblings.iter();
+    let (mut hash, mut index, mut width) = (leaf, index, leaf_count);
+    while width > 1 {
+        if index % 2 == 1 {
+            let Some(left) = siblings.next() else {
+                return false;
+            };
+            hash = merkle_parent(*left, hash);
+        } else if index + 1 < width {
+            let Some(right) = siblings.next() else {
+                return false;
+            };
+            hash = merkle_parent(hash, *right);
+        }
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && hash == root
+}
+
+/// A state entry with its Merkle branch to the state root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvenEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    // Position among the entries sorted by key
+    pub index: usize,
+    pub siblings: Vec<B256>,
+}
+
+/// Proof of what a key holds under a state root.
+/// A present key comes with its own entry. Since leaves are sorted by key, an absent key comes with
+/// its neighbours instead: the entries right before and after it (one of them at the edges).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageProof {
+    pub leaf_count: usize,
+    pub entries: Vec<ProvenEntry>,
+}
+
+/// Whether `proof` shows that `key` holds `value` (`None`: nothing) under `state_root`.
+pub fn verify_storage_proof(
+    state_root: B256,
+    key: &[u8],
+    value: Option<&[u8]>,
+    proof: &StorageProof,
+) -> bool {
+    let proven = proof.entries.iter().all(|entry| {
+        let leaf = entry_leaf(&entry.key, &entry.value);
+        verify_merkle_branch(
+            leaf,
+            entry.index,
+            proof.leaf_count,
+            &entry.siblings,
+            state_root,
+        )
+    });
+    if !proven {
+        return false;
+    }
+
+    match (value, proof.entries.as_slice()) {
+        (Some(value), [entry]) => entry.key == key && entry.value == value,
+        (Some(_), _) => false,
+        (None, []) => proof.leaf_count == 0 && state_root == keccak256([]),
+        // Before the first entry or after the last one
+        (None, [edge]) => {
+            (edge.index == 0 && key < edge.key.as_slice())
+                || (edge.index + 1 == proof.leaf_count && edge.key.as_slice() < key)
+        }
+        (None, [previous, next]) => {
+            previous.index + 1 == next.index
+                && previous.key.as_slice() < key
+                && key < next.key.as_slice()
+        }
+        (None, _) => false,
+    }
+}
+
+/// The in-memory backend. This struct holds the information of all accounts
+/// Copy-on-write: a clone shares the entries until one of the two is written to, so cloning
+/// is O(1) and a clone is a point-in-time view (see `SharedStorage::snapshot`).
+#[derive(Debug, Clone, Default)]
+pub struct SimpleStorage {
+    pub accounts: Arc<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl SimpleStorage {
+    /// Creates a new, empty storage instance.
+    pub fn new() -> Self {
+        // Default::default() tells Rust how to create a default SimpleStorage.
+        Self::default()
+    }
+}
+
+// A borrowed backend is a backend, so code that takes one by value can also borrow it
+impl<T: StorageBackend + ?Sized> StorageBackend for &mut T {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        (**self).get(key)
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError> {
+        (**self).put(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        (**self).remove(key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+        (**self).iter()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+impl StorageBackend for SimpleStorage {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.accounts.get(key).cloned()
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError> {
+        Arc::make_mut(&mut self.accounts).insert(key, value);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        Arc::make_mut(&mut self.accounts).remove(key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+        Box::new(
+            self.accounts
+                .iter()
+                .map(|(key, value)| (key.as_slice(), value.as_slice())),
+        )
+    }
+}
+
+/// Read cache counters (for the metrics).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Recently read accounts. The least recently used one is evicted when full.
+#[derive(Debug)]
+struct AccountCache {
+    capacity: usize,
+    // Value: (account, tick of its last use)
+    entries: HashMap<Address, (Account, u64)>,
+    // Key: tick of the last use, first is the least recently used
+    order: BTreeMap<u64, Address>,
+    // Bumped on every use
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl AccountCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn get(&mut self, addr: &Address) -> Option<Account> {
+        match self.entries.get(addr) {
+            Some((account, _)) => {
+                self.stats.hits += 1;
+                let account = account.clone();
+                self.touch(addr);
+                Some(account)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, addr: Address, account: Account) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((cached, _)) = self.entries.get_mut(&addr) {
+            *cached = account;
+            self.touch(&addr);
+            return;
+        }
+        self.tick += 1;
+        self.entries.insert(addr, (account, self.tick));
+        self.order.insert(self.tick, addr);
+        if self.order.len() > self.capacity
+            && let Some((_, evicted)) = self.order.pop_first()
+        {
+            self.entries.remove(&evicted);
+        }
+    }
+
+    fn invalidate(&mut self, addr: &Address) {
+        if let Some((_, last_used)) = self.entries.remove(addr) {
+            self.order.remove(&last_used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    // Moves an entry to the most recently used end
+    fn touch(&mut self, addr: &Address) {
+        if let Some((_, last_used)) = self.entries.get_mut(addr) {
+            self.order.remove(last_used);
+            self.tick += 1;
+            *last_used = self.tick;
+            self.order.insert(self.tick, *addr);
+        }
+    }
+}
+
+// A value and the block it was written at. None: the key was removed at that block
+type Version = (u64, Option<Vec<u8>>);
+
+/// Past values of the entries, recorded block by block.
+#[derive(Debug, Default)]
+struct History {
+    // Key: raw key, Value: (block, value) by increasing block, only when the value changed
+    versions: HashMap<Vec<u8>, Vec<Version>>,
+    // Blocks before this one were pruned
+    pruned_before: u64,
+    // First and last blocks a version was committed for
+    committed: Option<(u64, u64)>,
+}
+
+impl History {
+    fn record(&mut self, block: u64, key: &[u8], value: &[u8]) {
+        let versions = self.versions.entry(key.to_vec()).or_default();
+        if versions
+            .last()
+            .is_none_or(|(_, last)| last.as_deref() != Some(value))
+        {
+            versions.push((block, Some(value.to_vec())));
+        }
+    }
+
+    // Marks the keys that were present and no longer `exist` as removed at `block`
+    fn record_removals(&mut self, block: u64, exists: impl Fn(&[u8]) -> bool) {
+        for (key, versions) in &mut self.versions {
+            if versions.last().is_some_and(|(_, last)| last.is_some()) && !exists(key) {
+                versions.push((block, None));
+            }
+        }
+    }
+
+    fn commit(&mut self, block: u64) {
+        let (first, last) = self.committed.get_or_insert((block, block));
+        *first = (*first).min(block);
+        *last = (*last).max(block);
+    }
+
+    // Every entry as it was after `block`, if that block's state is still known
+    fn state_at(&self, block: u64) -> Option<HashMap<Vec<u8>, Vec<u8>>> {
+        let (first, last) = self.committed?;
+        if block < first.max(self.pruned_before) || block > last {
+            return None;
+        }
+        let entries = self
+            .versions
+            .keys()
+            .filter_map(|key| Some((key.clone(), self.get_at(key, block)?.to_vec())))
+            .collect();
+        Some(entries)
+    }
+
+    fn get_at(&self, key: &[u8], block: u64) -> Option<&[u8]> {
+        if block < self.pruned_before {
+            return None;
+        }
+        let versions = self.versions.get(key)?;
+        // Last version written at or before `block`
+        let position = versions.partition_point(|(written_at, _)| *written_at <= block);
+        versions
+            .get(position.checked_sub(1)?)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    fn prune(&mut self, keep_from_block: u64) {
+        for versions in self.versions.values_mut() {
+            // The last version before the cut is still the value at `keep_from_block`
+            let first_kept =
+                versions.partition_point(|(written_at, _)| *written_at <= keep_from_block);
+            versions.drain(..first_kept.saturating_sub(1));
+        }
+        self.pruned_before = self.pruned_before.max(keep_from_block);
+    }
+}
+
+/// The writes of one block, as journaled before they're applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockWrites {
+    pub block: u64,
+    /// Entries to put, in key order.
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// What `SharedStorage::recover` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// No journal left: the last block was applied, or none was in flight.
+    Clean,
+    /// A complete journal was replayed, the state is the one after `block`.
+    Replayed { block: u64 },
+    /// The journal was torn while being written, so nothing of it was applied. Dropped.
+    RolledBack,
+    /// A complete journal, but not of the block expected next: it wasn't written over this
+    /// state, so it's dropped without being applied.
+    Stale { block: u64 },
+}
+
+fn encode_journal(writes: &BlockWrites) -> io::Result<Vec<u8>> {
+    let mut out = JOURNAL_MAGIC.to_vec();
+    out.extend_from_slice(&writes.block.to_be_bytes());
+    for (key, value) in &writes.entries {
+        write_bytes(&mut out, key)?;
+        write_bytes(&mut out, value)?;
+    }
+    out.extend_from_slice(&0u32.to_be_bytes());
+    let hash = keccak256(&out);
+    out.extend_from_slice(hash.as_slice());
+    Ok(out)
+}
+
+// `None` if the journal is torn or isn't one
+fn decode_journal(data: &[u8]) -> Option<BlockWrites> {
+    let (body, hash) = data.split_at_checked(data.len().checked_sub(32)?)?;
+    if keccak256(body).as_slice() != hash {
+        return None;
+    }
+    let mut input = body.strip_prefix(JOURNAL_MAGIC.as_slice())?;
+    let mut block = [0u8; 8];
+    input.read_exact(&mut block).ok()?;
+    let mut entries = Vec::new();
+    loop {
+        let key = read_bytes(&mut input).ok()?;
+        if key.is_empty() {
+            break;
+        }
+        entries.push((key, read_bytes(&mut input).ok()?));
+    }
+    Some(BlockWrites {
+        block: u64::from_be_bytes(block),
+        entries,
+    })
+}
+
+// Removes the journal at `path`, if there is one
+fn remove_journal(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
 }
 
-/// The Thread-Safe Public Interface.
-#[derive(Clone)]
-pub struct SharedStorage {
-    inner: Arc<Mutex<SimpleStorage>>,
+// Saves `writes` to `path` and waits for the disk
+fn write_journal(path: &Path, writes: &BlockWrites) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&encode_journal(writes)?)?;
+    file.sync_all()
+}
+
+/// The Thread-Safe Public Interface, over any backend (in-memory by default).
+/// Account reads go through an LRU cache that is invalidated by writes.
+/// Lock order is always `inner`, then `cache` or `history`.
+pub struct SharedStorage<B: StorageBackend = SimpleStorage> {
+    inner: Arc<Mutex<B>>,
+    cache: Arc<Mutex<AccountCache>>,
+    history: Arc<Mutex<History>>,
+    // Write-ahead journal of `update_block` (None: block writes are applied directly)
+    journal: Option<PathBuf>,
+}
+
+// Manual impl: cloning the handle must not require a cloneable backend
+impl<B: StorageBackend> Clone for SharedStorage<B> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            cache: Arc::clone(&self.cache),
+            history: Arc::clone(&self.history),
+            journal: self.journal.clone(),
+        }
+    }
 }
 
 impl Default for SharedStorage {
@@ -62,34 +632,437 @@ impl Default for SharedStorage {
 
 impl SharedStorage {
     pub fn new() -> Self {
+        Self::with_cache_capacity(DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Storage whose read cache holds up to `capacity` accounts (0 disables it).
+    pub fn with_cache_capacity(capacity: usize) -> Self {
+        Self::with_backend(SimpleStorage::new(), capacity)
+    }
+}
+
+impl SharedStorage {
+    /// A point-in-time copy of the state to read (or write) without holding the lock.
+    /// The lock is only taken to share the entries; the first write after that copies them once,
+    /// on whichever side writes.
+    pub fn snapshot(&self) -> SimpleStorage {
+        lock(&self.inner).clone()
+    }
+}
+
+impl<B: StorageBackend> SharedStorage<B> {
+    /// Storage on top of a custom backend, with a read cache of `capacity` accounts.
+    pub fn with_backend(backend: B, capacity: usize) -> Self {
         Self {
-            inner: Arc::new(Mutex::new(SimpleStorage::new())),
+            inner: Arc::new(Mutex::new(backend)),
+            cache: Arc::new(Mutex::new(AccountCache::new(capacity))),
+            history: Arc::new(Mutex::new(History::default())),
+            journal: None,
         }
     }
 
+    /// Journals the writes of `update_block` to `path` before applying them, so a crash
+    /// mid-block can't leave part of a block on a durable backend (see `recover`).
+    pub fn with_journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.journal = Some(path.into());
+        self
+    }
+
+    /// Finishes what a crash interrupted, once the state is the one after `next_block - 1`
+    /// (e.g. restored from a snapshot) and before it's used: a complete journal of `next_block`
+    /// is replayed (its writes are plain puts, so replaying twice is harmless) and flushed, a
+    /// torn one is dropped since nothing was applied before it was whole, and one of another
+    /// block is dropped too. Either way the state ends up at a block boundary and the journal
+    /// is gone.
+    pub fn recover(&self, next_block: u64) -> io::Result<Recovery> {
+        let Some(path) = &self.journal else {
+            return Ok(Recovery::Clean);
+        };
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Recovery::Clean),
+            Err(e) => return Err(e),
+        };
+        let recovery = match decode_journal(&data) {
+            Some(writes) if writes.block != next_block => Recovery::Stale {
+                block: writes.block,
+            },
+            Some(writes) => {
+                let mut db = lock(&self.inner);
+                lock(&self.cache).clear();
+                for (key, value) in writes.entries {
+                    db.put(key, value)?;
+                }
+                db.flush()?;
+                Recovery::Replayed {
+                    block: writes.block,
+                }
+            }
+            None => Recovery::RolledBack,
+        };
+        fs::remove_file(path)?;
+        Ok(recovery)
+    }
+
+    /// Like `update`, for the writes of a block: `f` reads the state and returns the writes
+    /// it wants. With a journal they're saved before being applied, then flushed. `commit`
+    /// runs next (e.g. to add the block to the chain), then the journal is cleared. The lock
+    /// is held throughout.
+    ///
+    /// If the backend refuses a write (e.g. it's full) or `commit` fails, the journal is dropped
+    /// and the writes applied so far are undone: the state is the one `f` saw. That error is
+    /// returned even if the undo fails too (then logged, the state is left part way).
+    pub fn update_block<F, C, R, T, E>(&self, f: F, commit: C) -> Result<T, E>
+    where
+        F: FnOnce(&B) -> Result<(R, BlockWrites), E>,
+        C: FnOnce(R) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let mut db = lock(&self.inner);
+        let (result, writes) = f(&db)?;
+        if let Some(path) = &self.journal
+            && let Err(e) = write_journal(path, &writes)
+        {
+            // A journal whose sync failed may still be whole on disk, `recover` would replay it
+            let _ = remove_journal(path);
+            return Err(StorageError::from(e).into());
+        }
+        lock(&self.cache).clear();
+        let block = writes.block;
+        // Value of every written key before the block (None: it didn't exist)
+        let mut previous = Vec::with_capacity(writes.entries.len());
+        let applied = writes.entries.into_iter().try_for_each(|(key, value)| {
+            previous.push((key.clone(), db.get(&key)));
+            db.put(key, value)
+        });
+        let flushed = applied.and_then(|()| match &self.journal {
+            Some(_) => db.flush().map_err(StorageError::from),
+            None => Ok(()),
+        });
+        let committed = match flushed {
+            Ok(()) => commit(result),
+            Err(e) => Err(e.into()),
+        };
+        if committed.is_ok() {
+            if let Some(path) = &self.journal {
+                remove_journal(path).map_err(StorageError::from)?;
+            }
+            return committed;
+        }
+        // Dropped before undoing, so a crash part way through can't replay the block
+        if let Some(path) = &self.journal
+            && let Err(e) = remove_journal(path)
+        {
+            eprintln!("Failed to drop the journal of block {}: {}", block, e);
+        }
+        // Newest first, so a key written twice ends up with its oldest value
+        let undone = previous
+            .into_iter()
+            .rev()
+            .try_for_each(|(key, value)| match value {
+                Some(value) => db.put(key, value),
+                None => {
+                    db.remove(&key);
+                    Ok(())
+                }
+            });
+        let rolled_back = undone.and_then(|()| match &self.journal {
+            Some(_) => db.flush().map_err(StorageError::from),
+            None => Ok(()),
+        });
+        if let Err(e) = rolled_back {
+            eprintln!("Failed to roll back block {}: {}", block, e);
+        }
+        committed
+    }
+
+    /// Records the current state as the state after `block` (for `get_at`).
+    /// Only the entries that changed since the last record take extra memory.
+    pub fn commit_version(&self, block: u64) {
+        let db = lock(&self.inner);
+        let mut history = lock(&self.history);
+        for (key, value) in db.iter() {
+            history.record(block, key, value);
+        }
+        history.record_removals(block, |key| db.get(key).is_some());
+        history.commit(block);
+    }
+
+    /// The whole state as it was after `block`, to re-execute on top of. `None` if no version
+    /// was committed for that block or it was pruned. Versions record removals too (e.g. by
+    /// `import_from_reader`), so they alone rebuild it.
+    pub fn state_at(&self, block: u64) -> Option<SimpleStorage> {
+        let accounts = lock(&self.history).state_at(block)?;
+        Some(SimpleStorage {
+            accounts: Arc::new(accounts),
+        })
+    }
+
+    /// An account as it was after `block`. `None` if it didn't exist yet or that
+    /// block was pruned.
+    pub fn get_at(&self, addr: Address, block: u64) -> Option<Account> {
+        let history = lock(&self.history);
+        let mut bytes = history.get_at(addr.as_slice(), block)?;
+        Account::decode(&mut bytes).ok()
+    }
+
+    /// Drops the versions older than `keep_from_block`, keeping per key the one that's
+    /// current at that block. `get_at` for earlier blocks returns `None` afterwards.
+    pub fn prune_history(&self, keep_from_block: u64) {
+        lock(&self.history).prune(keep_from_block);
+    }
+
     /// Update an account
-    pub fn set_account(&self, addr: Address, account: Account) {
-        let mut accounts = self.inner.lock().unwrap();
-        accounts.set_account(addr, account);
+    pub fn set_account(&self, addr: Address, account: Account) -> Result<(), StorageError> {
+        let mut accounts = lock(&self.inner);
+        lock(&self.cache).invalidate(&addr);
+        accounts.set_account(addr, account)
+    }
+
+    /// A slot of an account's storage (zero if never written).
+    pub fn get_storage(&self, addr: Address, slot: U256) -> U256 {
+        self.view(|db| db.get_storage(&addr, slot))
     }
 
     // RPC uses this to check balances.
     // Returns an account given and address
     pub fn get_account(&self, addr: Address) -> Account {
-        let accounts = self.inner.lock().unwrap();
-        accounts.get_account(&addr)
+        if let Some(account) = lock(&self.cache).get(&addr) {
+            return account;
+        }
+
+        // Populate the cache while holding the db lock, so a concurrent write can't be missed
+        let accounts = lock(&self.inner);
+        let account = accounts.get_account(&addr);
+        lock(&self.cache).insert(addr, account.clone());
+        account
+    }
+
+    /// Root of the current state (see `StorageBackend::state_root`).
+    pub fn state_root(&self) -> B256 {
+        lock(&self.inner).state_root()
+    }
+
+    /// Hit/miss counters of the account read cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        lock(&self.cache).stats
+    }
+
+    /// The value under `key`, with a proof of it (or of its absence) against `state_root`.
+    pub fn get_with_proof(&self, key: &[u8]) -> (Option<Vec<u8>>, StorageProof) {
+        let db = lock(&self.inner);
+        let entries = sorted_entries(&*db);
+        let levels = entry_levels(&entries);
+        let proven = |index: usize| {
+            let (key, value) = entries[index];
+            ProvenEntry {
+                key: key.to_vec(),
+                value: value.to_vec(),
+                index,
+                siblings: merkle_branch(&levels, index),
+            }
+        };
+
+        let (value, proven_entries) = match entries.binary_search_by(|(k, _)| (*k).cmp(key)) {
+            Ok(index) => (Some(entries[index].1.to_vec()), vec![proven(index)]),
+            // `index` is where the key would go: between its neighbours
+            Err(index) => {
+                let neighbours = index.checked_sub(1).into_iter().chain(Some(index));
+                let neighbours = neighbours.filter(|&i| i < entries.len());
+                (None, neighbours.map(proven).collect())
+            }
+        };
+        let proof = StorageProof {
+            leaf_count: entries.len(),
+            entries: proven_entries,
+        };
+        (value, proof)
+    }
+
+    // The read-only counterpart of `update` (e.g. for RPC checks against the state).
+    pub fn view<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&B) -> R,
+    {
+        let db = lock(&self.inner);
+        f(&db)
+    }
+
+    /// Writes the full key/value set to a single snapshot file.
+    pub fn dump(&self, path: &Path) -> io::Result<()> {
+        let db = lock(&self.inner);
+        let mut out = BufWriter::new(File::create(path)?);
+
+        out.write_all(SNAPSHOT_MAGIC)?;
+        out.write_all(&[SNAPSHOT_VERSION])?;
+        // Sorted so the same state always produces the same file
+        let mut entries: Vec<_> = db.iter().collect();
+        entries.sort();
+        out.write_all(&(entries.len() as u64).to_be_bytes())?;
+
+        for (key, value) in entries {
+            write_bytes(&mut out, key)?;
+            write_bytes(&mut out, value)?;
+        }
+
+        out.flush()
+    }
+
+    /// Replaces the current contents with the ones of a snapshot file written by `dump`.
+    pub fn restore(&self, path: &Path) -> io::Result<()> {
+        let mut input = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(invalid_data("not a pETHit snapshot"));
+        }
+
+        let mut version = [0u8; 1];
+        input.read_exact(&mut version)?;
+        if version[0] != SNAPSHOT_VERSION {
+            return Err(invalid_data("unsupported snapshot version"));
+        }
+
+        let mut count = [0u8; 8];
+        input.read_exact(&mut count)?;
+
+        // Read everything before touching the live state
+        let mut entries = Vec::new();
+        for _ in 0..u64::from_be_bytes(count) {
+            let key = read_bytes(&mut input)?;
+            let value = read_bytes(&mut input)?;
+            entries.push((key, value));
+        }
+
+        self.replace_all(entries)
+    }
+
+    /// Streams every entry to `w`, sorted by key.
+    /// The lock is only held while reading each chunk of entries, so writers aren't blocked for
+    /// the whole export. Entries written concurrently may or may not make it into the stream.
+    pub fn export_to_writer(&self, mut w: impl Write) -> io::Result<()> {
+        let mut keys: Vec<Vec<u8>> =
+            self.view(|db| db.iter().map(|(key, _)| key.to_vec()).collect());
+        keys.sort();
+
+        w.write_all(STREAM_MAGIC)?;
+        for chunk in keys.chunks(EXPORT_CHUNK) {
+            let entries: Vec<_> = self.view(|db| {
+                chunk
+                    .iter()
+                    // Removed since the keys were listed
+                    .filter_map(|key| Some((key, db.get(key)?)))
+                    .collect()
+            });
+            for (key, value) in entries {
+                write_bytes(&mut w, key)?;
+                write_bytes(&mut w, &value)?;
+            }
+        }
+        w.write_all(&0u32.to_be_bytes())?;
+        w.flush()
+    }
+
+    /// Replaces the current contents with a stream written by `export_to_writer`.
+    pub fn import_from_reader(&self, mut r: impl Read) -> io::Result<()> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != STREAM_MAGIC {
+            return Err(invalid_data("not a pETHit state stream"));
+        }
+
+        // Read everything before touching the live state
+        let mut entries = Vec::new();
+        loop {
+            let key = read_bytes(&mut r)?;
+            if key.is_empty() {
+                break;
+            }
+            let value = read_bytes(&mut r)?;
+            entries.push((key, value));
+        }
+
+        self.replace_all(entries)
+    }
+
+    fn replace_all(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> io::Result<()> {
+        let mut db = lock(&self.inner);
+        lock(&self.cache).clear();
+        let stale_keys: Vec<Vec<u8>> = db.iter().map(|(key, _)| key.to_vec()).collect();
+        for key in stale_keys {
+            db.remove(&key);
+        }
+        for (key, value) in entries {
+            db.put(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Returns once the backend has made every write durable.
+    pub fn flush(&self) -> io::Result<()> {
+        lock(&self.inner).flush()
     }
 
     // The "Guard" method the Miner uses to modify the db.
-    pub fn update<F>(&self, f: F)
+    pub fn update<F, R>(&self, f: F) -> R
     where
-        F: FnOnce(&mut SimpleStorage),
+        F: FnOnce(&mut B) -> R,
     {
-        let mut db = self.inner.lock().unwrap();
-        f(&mut db);
+        let mut db = lock(&self.inner);
+        let result = f(&mut db);
+        // Writes inside `f` bypass the cache, so drop it
+        lock(&self.cache).clear();
+        result
     }
 }
 
+// Writes a length-prefixed byte string
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    out.write_all(bytes)
+}
+
+// Reads a length-prefixed byte string
+fn read_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len)?;
+    let mut bytes = vec![0u8; u32::from_be_bytes(len) as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Compresses `data` (e.g. exported blocks) before it's written to disk.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = COMPRESSED_MAGIC.to_vec();
+    out.extend_from_slice(&lz4_flex::compress_prepend_size(data));
+    out
+}
+
+/// Whether `data` was written by `compress`.
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(COMPRESSED_MAGIC)
+}
+
+/// Reverses `compress`. Data it didn't write, or corrupted since, is an `InvalidData` error.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let compressed = data
+        .strip_prefix(COMPRESSED_MAGIC)
+        .ok_or_else(|| invalid_data("not pETHit compressed data"))?;
+    lz4_flex::decompress_size_prepended(compressed)
+        .map_err(|e| invalid_data(&format!("corrupt compressed data: {}", e)))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// `update` closures write straight to the backend and can panic part way, so a poisoned
+// guard may expose partial writes; it's taken over anyway, like state after a crash
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,7 +1079,7 @@ mod tests {
         };
 
         // Put
-        storage.set_account(addr, account.clone());
+        storage.set_account(addr, account.clone()).unwrap();
 
         // Get
         let retrieved = storage.get_account(addr);
@@ -127,4 +1100,653 @@ mod tests {
         assert_eq!(retrieved.nonce, 0);
         assert_eq!(retrieved.balance, U256::from(0));
     }
+
+    // A trivial ordered backend, to check SharedStorage doesn't rely on SimpleStorage
+    #[derive(Default)]
+    struct BTreeBackend(BTreeMap<Vec<u8>, Vec<u8>>);
+
+    impl StorageBackend for BTreeBackend {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.0.get(key).cloned()
+        }
+
+        fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError> {
+            self.0.insert(key, value);
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+            self.0.remove(key)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+            Box::new(self.0.iter().map(|(k, v)| (k.as_slice(), v.as_slice())))
+        }
+    }
+
+    #[test]
+    fn it_puts_and_gets_account_on_custom_backend() {
+        let storage = SharedStorage::with_backend(BTreeBackend::default(), 0);
+        let account = Account {
+            nonce: 5,
+            balance: U256::from(100),
+        };
+
+        storage.set_account(Address::ZERO, account.clone()).unwrap();
+
+        assert_eq!(storage.get_account(Address::ZERO), account);
+        // Missing keys still read as the default account
+        assert_eq!(
+            storage.get_account(Address::with_last_byte(1)),
+            Account::default()
+        );
+        // The writes landed in the custom backend
+        assert_eq!(storage.view(|db| db.0.len()), 1);
+    }
+
+    // Backend with a "disk" copy that only flushes update, and that outlives the store
+    #[derive(Default)]
+    struct DurableBackend {
+        live: BTreeMap<Vec<u8>, Vec<u8>>,
+        disk: Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    }
+
+    impl StorageBackend for DurableBackend {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.live.get(key).cloned()
+        }
+
+        fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError> {
+            self.live.insert(key, value);
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+            self.live.remove(key)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+            Box::new(self.live.iter().map(|(k, v)| (k.as_slice(), v.as_slice())))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            *self.disk.lock().unwrap() = self.live.clone();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_keeps_flushed_writes_across_a_crash() {
+        let disk = Arc::new(Mutex::new(BTreeMap::new()));
+        let backend = DurableBackend {
+            disk: disk.clone(),
+            ..Default::default()
+        };
+        let storage = SharedStorage::with_backend(backend, 0);
+        let flushed = Account {
+            nonce: 1,
+            balance: U256::from(10),
+        };
+        storage
+            .set_account(Address::with_last_byte(1), flushed.clone())
+            .unwrap();
+        storage.flush().unwrap();
+        storage
+            .set_account(Address::with_last_byte(2), flushed.clone())
+            .unwrap();
+
+        // Crash: everything not on disk is lost
+        drop(storage);
+        let live = disk.lock().unwrap().clone();
+        let recovered = SharedStorage::with_backend(DurableBackend { live, disk }, 0);
+
+        assert_eq!(recovered.get_account(Address::with_last_byte(1)), flushed);
+        assert_eq!(
+            recovered.get_account(Address::with_last_byte(2)),
+            Account::default()
+        );
+    }
+
+    // Backend that refuses to write `refused`, like a disk failing part way through a block.
+    // Shared so a test can move the failure while a block is in flight
+    struct RefusingBackend {
+        entries: BTreeMap<Vec<u8>, Vec<u8>>,
+        refused: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl StorageBackend for RefusingBackend {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.entries.get(key).cloned()
+        }
+
+        fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StorageError> {
+            if key == *self.refused.lock().unwrap() {
+                return Err(StorageError::Full);
+            }
+            self.entries.insert(key, value);
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+            self.entries.remove(key)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+            Box::new(
+                self.entries
+                    .iter()
+                    .map(|(k, v)| (k.as_slice(), v.as_slice())),
+            )
+        }
+    }
+
+    #[test]
+    fn it_undoes_a_block_that_fails_to_apply_or_commit() {
+        let journal =
+            std::env::temp_dir().join(format!("pethit-undo-journal-{}", std::process::id()));
+        let refused_key = Arc::new(Mutex::new(b"c".to_vec()));
+        let backend = RefusingBackend {
+            entries: BTreeMap::from([(b"a".to_vec(), b"1".to_vec())]),
+            refused: refused_key.clone(),
+        };
+        let storage = SharedStorage::with_backend(backend, 0).with_journal(&journal);
+        let root = storage.state_root();
+        let block = |keys: &[&[u8]]| BlockWrites {
+            block: 1,
+            entries: keys
+                .iter()
+                .map(|key| (key.to_vec(), b"2".to_vec()))
+                .collect(),
+        };
+
+        // `a` and `b` are applied before `c` is refused, and undone: `a` rewritten, `b` removed
+        let refused = storage.update_block(|_| Ok(((), block(&[b"a", b"b", b"c"]))), Ok);
+        assert_eq!(refused, Err(StorageError::Full));
+        assert_eq!(storage.view(|db| db.get(b"a")), Some(b"1".to_vec()));
+        assert_eq!(storage.view(|db| db.get(b"b")), None);
+        assert_eq!(storage.state_root(), root);
+        assert!(!journal.exists());
+
+        // All applied, then the commit fails
+        let failed = StorageError::Io("the chain refused the block".to_string());
+        let refused = storage.update_block(
+            |_| Ok(((), block(&[b"a", b"b"]))),
+            |()| Err::<(), _>(failed.clone()),
+        );
+        assert_eq!(refused, Err(failed.clone()));
+        assert_eq!(storage.state_root(), root);
+        assert!(!journal.exists());
+
+        // The disk fails during the commit, so the undo fails too: the commit error still
+        // comes back and the journal is gone, the block won't be replayed
+        let undo_refused = storage.update_block(
+            |_| Ok(((), block(&[b"a"]))),
+            |()| {
+                *refused_key.lock().unwrap() = b"a".to_vec();
+                Err::<(), _>(failed.clone())
+            },
+        );
+        assert_eq!(undo_refused, Err(failed));
+        assert!(!journal.exists());
+        assert_eq!(storage.view(|db| db.get(b"a")), Some(b"2".to_vec()));
+
+        let committed = storage.update_block(
+            |_| Ok::<_, StorageError>((7, block(&[b"b"]))),
+            |n| Ok(n + 1),
+        );
+        assert_eq!(committed, Ok(8));
+        assert_eq!(storage.view(|db| db.get(b"b")), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn it_recovers_from_a_crash_mid_block() {
+        let journal = std::env::temp_dir().join(format!("pethit-journal-{}", std::process::id()));
+        let disk = Arc::new(Mutex::new(BTreeMap::new()));
+        let open = |disk: &Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>| {
+            let live = disk.lock().unwrap().clone();
+            let backend = DurableBackend {
+                live,
+                disk: disk.clone(),
+            };
+            SharedStorage::with_backend(backend, 0).with_journal(&journal)
+        };
+        let with_balance = |balance: u64| {
+            let mut value = Vec::new();
+            Account {
+                nonce: 0,
+                balance: U256::from(balance),
+            }
+            .encode(&mut value);
+            value
+        };
+        let (a, b) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let block = |number: u64, balance: u64| BlockWrites {
+            block: number,
+            entries: vec![
+                (a.to_vec(), with_balance(balance)),
+                (b.to_vec(), with_balance(balance)),
+            ],
+        };
+
+        // Block 1 goes through entirely: flushed, journal cleared
+        let storage = open(&disk);
+        storage
+            .update_block(|_| Ok::<_, StorageError>(((), block(1, 10))), Ok)
+            .unwrap();
+        assert!(!journal.exists());
+        let root_after_1 = storage.state_root();
+
+        // Crash once block 2 is journaled, before any of it is applied
+        write_journal(&journal, &block(2, 20)).unwrap();
+        drop(storage);
+        let recovered = open(&disk);
+        assert_eq!(
+            recovered.recover(2).unwrap(),
+            Recovery::Replayed { block: 2 }
+        );
+        assert_eq!(recovered.get_account(a).balance, U256::from(20));
+        assert_eq!(recovered.get_account(b).balance, U256::from(20));
+        // The replay was flushed, and there's nothing left to do
+        assert_eq!(open(&disk).get_account(b).balance, U256::from(20));
+        assert_eq!(recovered.recover(3).unwrap(), Recovery::Clean);
+
+        // Crash while block 3 is being journaled: torn, rolled back to block 2
+        let mut torn = encode_journal(&block(3, 30)).unwrap();
+        torn.truncate(torn.len() - 40);
+        fs::write(&journal, torn).unwrap();
+        let recovered = open(&disk);
+        assert_eq!(recovered.recover(3).unwrap(), Recovery::RolledBack);
+        assert!(!journal.exists());
+        assert_eq!(recovered.get_account(a).balance, U256::from(20));
+        assert_ne!(recovered.state_root(), root_after_1);
+
+        // A journal of a block that doesn't follow the state (e.g. from an older snapshot)
+        write_journal(&journal, &block(5, 50)).unwrap();
+        let recovered = open(&disk);
+        assert_eq!(recovered.recover(3).unwrap(), Recovery::Stale { block: 5 });
+        assert!(!journal.exists());
+        assert_eq!(recovered.get_account(a).balance, U256::from(20));
+    }
+
+    #[test]
+    fn it_dumps_and_restores_snapshot() {
+        let path = std::env::temp_dir().join(format!("pethit-snapshot-{}", std::process::id()));
+        let storage = SharedStorage::new();
+
+        for i in 0..10u8 {
+            let account = Account {
+                nonce: i as u64,
+                balance: U256::from(1000 * i as u64),
+            };
+            storage
+                .set_account(Address::with_last_byte(i), account)
+                .unwrap();
+        }
+        storage.dump(&path).unwrap();
+
+        // Reload into a fresh store
+        let restored = SharedStorage::new();
+        restored.restore(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        for i in 0..10u8 {
+            let account = restored.get_account(Address::with_last_byte(i));
+            assert_eq!(account.nonce, i as u64);
+            assert_eq!(account.balance, U256::from(1000 * i as u64));
+        }
+    }
+
+    #[test]
+    fn it_streams_state_through_a_writer() {
+        let storage = SharedStorage::new();
+        // More than one chunk
+        for i in 0..600u16 {
+            let account = Account {
+                nonce: i as u64,
+                balance: U256::from(i),
+            };
+            storage
+                .set_account(Address::left_padding_from(&i.to_be_bytes()), account)
+                .unwrap();
+        }
+        let mut stream = Vec::new();
+        storage.export_to_writer(&mut stream).unwrap();
+
+        // Whatever the target held before is replaced
+        let restored = SharedStorage::new();
+        restored
+            .set_account(Address::repeat_byte(0xff), Account::default())
+            .unwrap();
+        restored.import_from_reader(stream.as_slice()).unwrap();
+
+        assert_eq!(restored.state_root(), storage.state_root());
+        assert_eq!(
+            restored
+                .get_account(Address::left_padding_from(&[2, 7]))
+                .nonce,
+            519
+        );
+        assert!(restored.view(|db| db.get(Address::repeat_byte(0xff).as_slice()).is_none()));
+
+        // Snapshots are a different format
+        assert!(restored.import_from_reader(&b"PETH\x01"[..]).is_err());
+    }
+
+    #[test]
+    fn it_rejects_unknown_snapshot_format() {
+        let path = std::env::temp_dir().join(format!("pethit-bad-snapshot-{}", std::process::id()));
+        std::fs::write(&path, b"NOPE\x01").unwrap();
+
+        let result = SharedStorage::new().restore(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn it_caches_reads() {
+        let storage = SharedStorage::new();
+        let addr = Address::ZERO;
+
+        storage.get_account(addr);
+        storage.get_account(addr);
+
+        assert_eq!(storage.cache_stats(), CacheStats { hits: 1, misses: 1 });
+    }
+
+    #[test]
+    fn it_never_serves_stale_cached_reads() {
+        let storage = SharedStorage::new();
+        let addr = Address::ZERO;
+
+        // Cache the default account, then overwrite it
+        storage.get_account(addr);
+        let account = Account {
+            nonce: 1,
+            balance: U256::from(50),
+        };
+        storage.set_account(addr, account.clone()).unwrap();
+        assert_eq!(storage.get_account(addr), account);
+
+        // Same through the raw update guard
+        storage
+            .update(|db| db.set_account(addr, Account::default()))
+            .unwrap();
+        assert_eq!(storage.get_account(addr), Account::default());
+    }
+
+    #[test]
+    fn it_evicts_least_recently_used() {
+        let storage = SharedStorage::with_cache_capacity(2);
+        let (a, b, c) = (
+            Address::with_last_byte(1),
+            Address::with_last_byte(2),
+            Address::with_last_byte(3),
+        );
+
+        storage.get_account(a);
+        storage.get_account(b);
+        // `a` becomes the most recent, so `c` evicts `b`
+        storage.get_account(a);
+        storage.get_account(c);
+        storage.get_account(a);
+        storage.get_account(b);
+
+        assert_eq!(storage.cache_stats(), CacheStats { hits: 2, misses: 4 });
+    }
+
+    #[test]
+    fn it_reads_and_prunes_history() {
+        let storage = SharedStorage::new();
+        let addr = Address::ZERO;
+        let with_balance = |balance: u64| Account {
+            nonce: 0,
+            balance: U256::from(balance),
+        };
+
+        // The balance after blocks 1, 2 and 3. Block 4 leaves it as is
+        for block in 1..=4u64 {
+            storage
+                .set_account(addr, with_balance(block.min(3) * 10))
+                .unwrap();
+            storage.commit_version(block);
+        }
+        assert_eq!(storage.get_at(addr, 0), None);
+        assert_eq!(storage.get_at(addr, 1), Some(with_balance(10)));
+        assert_eq!(storage.get_at(addr, 4), Some(with_balance(30)));
+
+        storage.prune_history(2);
+
+        assert_eq!(storage.get_at(addr, 1), None);
+        assert_eq!(storage.get_at(addr, 2), Some(with_balance(20)));
+        assert_eq!(storage.get_at(addr, 4), Some(with_balance(30)));
+        // The current state isn't affected
+        assert_eq!(storage.get_account(addr), with_balance(30));
+    }
+
+    #[test]
+    fn it_rebuilds_past_states() {
+        let storage = SharedStorage::new();
+        let (a, b) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let with_balance = |balance: u64| Account {
+            nonce: 0,
+            balance: U256::from(balance),
+        };
+
+        storage.set_account(a, with_balance(1)).unwrap();
+        storage.commit_version(1);
+        let root_after_1 = storage.state_root();
+        storage.set_account(a, with_balance(2)).unwrap();
+        storage.set_account(b, with_balance(3)).unwrap();
+        storage.commit_version(2);
+
+        let past = storage.state_at(1).unwrap();
+        assert_eq!(past.state_root(), root_after_1);
+        assert_eq!(past.get_account(&a), with_balance(1));
+        // `b` didn't exist yet
+        assert_eq!(past.get(b.as_slice()), None);
+        assert_eq!(
+            storage.state_at(2).unwrap().state_root(),
+            storage.state_root()
+        );
+
+        // `b` removed (as a restore from a snapshot does): gone from the next version only
+        storage.update(|db| db.remove(b.as_slice()));
+        storage.commit_version(3);
+        assert_eq!(storage.state_at(3).unwrap().get(b.as_slice()), None);
+        assert_eq!(storage.get_at(b, 3), None);
+        assert_eq!(storage.get_at(b, 2), Some(with_balance(3)));
+
+        // Never committed, or pruned
+        assert!(storage.state_at(0).is_none());
+        assert!(storage.state_at(4).is_none());
+        storage.prune_history(2);
+        assert!(storage.state_at(1).is_none());
+    }
+
+    // Storage with accounts at addresses 0x..02, 0x..04, 0x..06
+    fn proof_storage() -> SharedStorage {
+        let storage = SharedStorage::new();
+        for byte in [2u8, 4, 6] {
+            let account = Account {
+                nonce: byte as u64,
+                balance: U256::from(byte),
+            };
+            storage
+                .set_account(Address::with_last_byte(byte), account)
+                .unwrap();
+        }
+        storage
+    }
+
+    #[test]
+    fn test_storage_proof_of_present_key() {
+        let storage = proof_storage();
+        let root = storage.state_root();
+        let key = Address::with_last_byte(4);
+
+        let (value, proof) = storage.get_with_proof(key.as_slice());
+        let value = value.unwrap();
+        assert_eq!(
+            Account::decode(&mut value.as_slice()).unwrap().balance,
+            U256::from(4)
+        );
+        assert!(verify_storage_proof(
+            root,
+            key.as_slice(),
+            Some(&value),
+            &proof
+        ));
+
+        // A tampered value, or the key claimed absent, doesn't verify
+        let mut tampered = proof.clone();
+        tampered.entries[0].value[0] ^= 1;
+        assert!(!verify_storage_proof(
+            root,
+            key.as_slice(),
+            Some(&tampered.entries[0].value),
+            &tampered
+        ));
+        assert!(!verify_storage_proof(
+            root,
+            key.as_slice(),
+            Some(b"other"),
+            &proof
+        ));
+        assert!(!verify_storage_proof(root, key.as_slice(), None, &proof));
+        // Nor against another root
+        storage
+            .set_account(Address::with_last_byte(8), Account::default())
+            .unwrap();
+        assert!(!verify_storage_proof(
+            storage.state_root(),
+            key.as_slice(),
+            Some(&value),
+            &proof
+        ));
+    }
+
+    #[test]
+    fn it_roots_the_same_entries_the_same_whatever_the_order() {
+        let entries: Vec<(Address, Option<U256>)> = (1..=20u8)
+            .map(|byte| (Address::with_last_byte(byte), None))
+            .chain((1..=5u8).map(|slot| (Address::with_last_byte(3), Some(U256::from(slot)))))
+            .collect();
+        let root_of = |order: &mut dyn Iterator<Item = &(Address, Option<U256>)>| {
+            let mut hash_map = SimpleStorage::new();
+            let mut tree = BTreeBackend::default();
+            for (addr, slot) in order {
+                for backend in [&mut hash_map as &mut dyn StorageBackend, &mut tree] {
+                    match slot {
+                        Some(slot) => backend.set_storage(*addr, *slot, *slot + U256::from(1)),
+                        None => backend.set_account(*addr, Account::default()),
+                    }
+                    .unwrap();
+                }
+            }
+            assert_eq!(hash_map.state_root(), tree.state_root());
+            hash_map.state_root()
+        };
+
+        let forward = root_of(&mut entries.iter());
+        assert_eq!(root_of(&mut entries.iter().rev()), forward);
+        let (evens, odds): (Vec<_>, Vec<_>) =
+            entries.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+        let mut interleaved = odds.into_iter().chain(evens).map(|(_, entry)| entry);
+        assert_eq!(root_of(&mut interleaved), forward);
+    }
+
+    #[test]
+    fn test_storage_proof_of_absent_key() {
+        let storage = proof_storage();
+        let root = storage.state_root();
+
+        // Between two entries, before the first and after the last
+        for byte in [3u8, 1, 7] {
+            let key = Address::with_last_byte(byte);
+            let (value, proof) = storage.get_with_proof(key.as_slice());
+            assert_eq!(value, None);
+            assert!(verify_storage_proof(root, key.as_slice(), None, &proof));
+        }
+
+        // Neighbours that aren't adjacent don't prove anything
+        let key = Address::with_last_byte(3);
+        let (_, mut proof) = storage.get_with_proof(key.as_slice());
+        let (_, far) = storage.get_with_proof(Address::with_last_byte(6).as_slice());
+        proof.entries[1] = far.entries[0].clone();
+        assert!(!verify_storage_proof(root, key.as_slice(), None, &proof));
+
+        let empty = SharedStorage::new();
+        let (value, proof) = empty.get_with_proof(key.as_slice());
+        assert_eq!(value, None);
+        assert!(verify_storage_proof(
+            empty.state_root(),
+            key.as_slice(),
+            None,
+            &proof
+        ));
+    }
+
+    #[test]
+    fn test_compress_round_trip_and_corruption() {
+        let data: Vec<u8> = (0..1000u32).flat_map(|i| (i % 7).to_be_bytes()).collect();
+
+        let compressed = compress(&data);
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed).unwrap(), data);
+
+        let err = decompress(&data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "not pETHit compressed data");
+
+        let truncated = &compressed[..compressed.len() - 4];
+        let err = decompress(truncated).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("corrupt compressed data"));
+    }
+
+    #[test]
+    fn test_snapshot_is_point_in_time_and_does_not_block_writers() {
+        let storage = SharedStorage::new();
+        for byte in 0..100u8 {
+            let account = Account {
+                nonce: 0,
+                balance: U256::from(byte),
+            };
+            storage
+                .set_account(Address::with_last_byte(byte), account)
+                .unwrap();
+        }
+        let root = storage.state_root();
+
+        let snapshot = storage.snapshot();
+        let mut seen = 0;
+        for (key, _) in snapshot.iter() {
+            // Writing while iterating: would deadlock if the snapshot held the lock
+            let writer = storage.clone();
+            let key = key.to_vec();
+            std::thread::spawn(move || {
+                let address = Address::from_slice(&key);
+                let mut account = writer.get_account(address);
+                account.nonce += 1;
+                writer.set_account(address, account).unwrap();
+                writer
+                    .set_account(Address::repeat_byte(0xee), Account::default())
+                    .unwrap();
+            })
+            .join()
+            .unwrap();
+            seen += 1;
+        }
+
+        assert_eq!(seen, 100);
+        // None of the writes made it into the snapshot
+        assert_eq!(snapshot.state_root(), root);
+        assert_eq!(snapshot.get_account(&Address::with_last_byte(1)).nonce, 0);
+        assert_eq!(snapshot.get(Address::repeat_byte(0xee).as_slice()), None);
+        // They're all in the storage
+        assert_eq!(storage.get_account(Address::with_last_byte(1)).nonce, 1);
+        assert_ne!(storage.state_root(), root);
+    }
 }
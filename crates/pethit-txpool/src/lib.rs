@@ -1,37 +1,357 @@
-use alloy_primitives::B256;
+use alloy_primitives::{Address, B256, U256};
+use alloy_rlp::{Decodable, Encodable};
 use pethit_execution::SignedTransaction;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-// A simple error type
-pub type PoolError = String;
+/// Where the pool, the chain and the miner read the time from, so tests can set it.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Monotonic time, for ages and intervals.
+    fn now(&self) -> Instant;
+    /// Seconds since the Unix epoch, for block timestamps.
+    fn unix_now(&self) -> u64;
+}
+
+/// The system's clocks, used unless told otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// A clock that stands still until `advance`d (for tests). Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    unix_start: u64,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    /// Starts at `unix_start` seconds since the Unix epoch.
+    pub fn new(unix_start: u64) -> Self {
+        MockClock {
+            start: Instant::now(),
+            unix_start,
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *lock(&self.elapsed) += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + *lock(&self.elapsed)
+    }
+
+    fn unix_now(&self) -> u64 {
+        self.unix_start + lock(&self.elapsed).as_secs()
+    }
+}
+
+/// Why the pool refuses a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolError {
+    /// No sender can be recovered from the signature.
+    InvalidSignature(String),
+    /// The gas price is below `PoolConfig::min_gas_price`.
+    Underpriced { gas_price: U256, min: U256 },
+    /// The sender already has `PoolConfig::max_per_sender` transactions pooled.
+    SenderLimit { sender: Address, limit: usize },
+    /// Same sender and nonce as a pooled transaction, without the required price bump.
+    ReplacementUnderpriced {
+        gas_price: U256,
+        bump_percent: u64,
+        old_gas_price: U256,
+    },
+    /// The sender's pooled transactions plus this one cost more (value + fee) than its balance.
+    InsufficientBalance {
+        sender: Address,
+        cost: U256,
+        balance: U256,
+    },
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::InvalidSignature(reason) => write!(f, "{}", reason),
+            PoolError::Underpriced { gas_price, min } => {
+                write!(f, "Gas price {} is below the minimum of {}", gas_price, min)
+            }
+            PoolError::SenderLimit { sender, limit } => write!(
+                f,
+                "Sender {} reached the limit of {} pooled transactions",
+                sender, limit
+            ),
+            PoolError::ReplacementUnderpriced {
+                gas_price,
+                bump_percent,
+                old_gas_price,
+            } => write!(
+                f,
+                "Replacement gas price {} must be at least {}% above {}",
+                gas_price, bump_percent, old_gas_price
+            ),
+            PoolError::InsufficientBalance {
+                sender,
+                cost,
+                balance,
+            } => write!(
+                f,
+                "Sender {} can't cover its pending transactions: they cost {}, the balance is {}",
+                sender, cost, balance
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+/// Admission rules of the pool.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// How many transactions a single sender can have pooled at once.
+    pub max_per_sender: usize,
+    /// Transactions paying less per unit of gas are refused (0 accepts all).
+    pub min_gas_price: U256,
+    /// A transaction replaces the pooled one with the same sender and nonce only if its
+    /// gas price is higher by at least this many percent.
+    pub price_bump_percent: u64,
+    /// Transactions pooled this long are dropped by `prune_expired` (None keeps them).
+    pub ttl: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_per_sender: 16,
+            min_gas_price: U256::ZERO,
+            price_bump_percent: 10,
+            ttl: None,
+        }
+    }
+}
+
+/// A pooled transaction with what was learned at admission.
+/// The sender is recovered once, so readers don't have to run recovery again.
+#[derive(Debug, Clone)]
+pub struct PooledTx {
+    pub tx: SignedTransaction,
+    pub sender: Address,
+    pub received_at: Instant,
+    // RLP size in bytes
+    pub size: usize,
+    /// When it was last re-broadcast to peers (None: never).
+    pub rebroadcast_at: Option<Instant>,
+}
+
+impl PooledTx {
+    /// Accounts the transaction reads or writes: the sender (balance, nonce and the storage its
+    /// code runs against) and the recipient. The coinbase is left out, it's paid once after
+    /// the whole block. Transactions touching no common account can run in parallel.
+    pub fn touched_accounts(&self) -> Vec<Address> {
+        let to = self.tx.transaction().to;
+        if to == self.sender {
+            return vec![self.sender];
+        }
+        vec![self.sender, to]
+    }
+
+    /// Whether both transactions touch some same account, so their order matters.
+    pub fn conflicts_with(&self, other: &PooledTx) -> bool {
+        let theirs = other.touched_accounts();
+        self.touched_accounts()
+            .iter()
+            .any(|account| theirs.contains(account))
+    }
+}
+
+/// A summary of what is pending (for monitoring).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub count: usize,
+    /// Time spent in the pool by the oldest transaction (zero when empty).
+    pub oldest_age: Duration,
+    pub total_bytes: usize,
+    /// Lowest and highest fee among the pooled transactions (`None` when empty).
+    pub min_fee: Option<U256>,
+    pub max_fee: Option<U256>,
+}
+
+// What a transaction can take from its sender's balance
+fn cost(tx: &SignedTransaction) -> U256 {
+    tx.transaction()
+        .value
+        .saturating_add(tx.transaction().fee())
+}
 
 /// This doesn't know about threads, just data.
 struct TxPool {
-    // Key: signed tx hash,  Value: signed tx
-    transactions: HashMap<B256, SignedTransaction>,
+    config: PoolConfig,
+    // Key: signed tx hash,  Value: pooled tx
+    transactions: HashMap<B256, PooledTx>,
+    // Key: sender, Value: hashes of the sender's pooled txs
+    by_sender: HashMap<Address, HashSet<B256>>,
+    // How many transactions were ever admitted (never goes down)
+    received: u64,
+    clock: Arc<dyn Clock>,
 }
 
 impl TxPool {
-    fn new() -> Self {
+    fn new(config: PoolConfig) -> Self {
         Self {
+            config,
             transactions: HashMap::new(),
+            by_sender: HashMap::new(),
+            received: 0,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // `balance` is the sender's on-chain balance, when the caller knows it
+    fn add(
+        &mut self,
+        k_hash: B256,
+        tx: SignedTransaction,
+        balance: Option<U256>,
+    ) -> Result<(), PoolError> {
+        // Already pooled (deduplication)
+        if self.transactions.contains_key(&k_hash) {
+            return Ok(());
+        }
+
+        let gas_price = tx.transaction().gas_price;
+        if gas_price < self.config.min_gas_price {
+            return Err(PoolError::Underpriced {
+                gas_price,
+                min: self.config.min_gas_price,
+            });
+        }
+
+        let sender = tx.recover_sender().map_err(PoolError::InvalidSignature)?;
+        let replaced = self.find(sender, tx.transaction().nonce);
+        if let Some(old_hash) = replaced {
+            let old_price = self.transactions[&old_hash].tx.transaction().gas_price;
+            let bump = U256::from(100 + self.config.price_bump_percent);
+            if gas_price <= old_price
+                || gas_price.saturating_mul(U256::from(100)) < old_price.saturating_mul(bump)
+            {
+                return Err(PoolError::ReplacementUnderpriced {
+                    gas_price,
+                    bump_percent: self.config.price_bump_percent,
+                    old_gas_price: old_price,
+                });
+            }
+        }
+
+        if let Some(balance) = balance {
+            // Everything the sender has pending must be payable at once, the replaced one aside
+            let pending = self.by_sender.get(&sender).into_iter().flatten();
+            let cost = pending
+                .filter(|hash| Some(**hash) != replaced)
+                .map(|hash| cost(&self.transactions[hash].tx))
+                .fold(cost(&tx), U256::saturating_add);
+            if cost > balance {
+                return Err(PoolError::InsufficientBalance {
+                    sender,
+                    cost,
+                    balance,
+                });
+            }
+        }
+
+        if let Some(old_hash) = replaced {
+            // The replacement takes the slot of the old one
+            self.remove(&old_hash);
+        }
+
+        let sender_txs = self.by_sender.entry(sender).or_default();
+        if sender_txs.len() >= self.config.max_per_sender {
+            return Err(PoolError::SenderLimit {
+                sender,
+                limit: self.config.max_per_sender,
+            });
         }
+
+        sender_txs.insert(k_hash);
+        let pooled = PooledTx {
+            size: tx.length(),
+            tx,
+            sender,
+            received_at: self.clock.now(),
+            rebroadcast_at: None,
+        };
+        self.transactions.insert(k_hash, pooled);
+        self.received += 1;
+        Ok(())
     }
 
-    fn add(&mut self, k_hash: B256, tx: SignedTransaction) {
-        // HashMap::insert automatically overwrites if key exists (deduplication)
-        self.transactions.insert(k_hash, tx);
+    // Hash of the pooled transaction of `sender` with `nonce`
+    fn find(&self, sender: Address, nonce: u64) -> Option<B256> {
+        let hashes = self.by_sender.get(&sender)?;
+        hashes
+            .iter()
+            .find(|hash| self.transactions[*hash].tx.transaction().nonce == nonce)
+            .copied()
     }
 
     fn get_all(&self) -> Vec<SignedTransaction> {
         // Return a cloned list of all transactions
-        self.transactions.values().cloned().collect()
+        self.transactions
+            .values()
+            .map(|pooled| pooled.tx.clone())
+            .collect()
+    }
+
+    fn stats(&self) -> PoolStats {
+        let mut stats = PoolStats {
+            count: self.transactions.len(),
+            ..Default::default()
+        };
+        let now = self.clock.now();
+        for pooled in self.transactions.values() {
+            let fee = pooled.tx.transaction().fee();
+            let age = now.saturating_duration_since(pooled.received_at);
+            stats.oldest_age = stats.oldest_age.max(age);
+            stats.total_bytes += pooled.size;
+            stats.min_fee = Some(stats.min_fee.map_or(fee, |min| min.min(fee)));
+            stats.max_fee = Some(stats.max_fee.map_or(fee, |max| max.max(fee)));
+        }
+        stats
+    }
+
+    fn remove(&mut self, k_hash: &B256) {
+        let Some(pooled) = self.transactions.remove(k_hash) else {
+            return;
+        };
+        if let Some(sender_txs) = self.by_sender.get_mut(&pooled.sender) {
+            sender_txs.remove(k_hash);
+            if sender_txs.is_empty() {
+                self.by_sender.remove(&pooled.sender);
+            }
+        }
     }
 
     fn clear(&mut self) {
         // Clears the pool (called after a block is mined)
         self.transactions.clear();
+        self.by_sender.clear();
     }
 }
 
@@ -42,6 +362,8 @@ pub struct SharedTxPool {
     // Arc allows multiple owners.
     // Mutex allows exclusive access (mutability).
     inner: Arc<Mutex<TxPool>>,
+    // Signaled on every admitted transaction
+    admitted: Arc<Condvar>,
 }
 
 impl Default for SharedTxPool {
@@ -52,32 +374,200 @@ impl Default for SharedTxPool {
 
 impl SharedTxPool {
     pub fn new() -> Self {
+        Self::with_config(PoolConfig::default())
+    }
+
+    pub fn with_config(config: PoolConfig) -> Self {
         Self {
-            inner: Arc::new(Mutex::new(TxPool::new())),
+            inner: Arc::new(Mutex::new(TxPool::new(config))),
+            admitted: Arc::new(Condvar::new()),
         }
     }
 
+    /// Reads the time from `clock` (the system's by default).
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        lock(&self.inner).clock = Arc::new(clock);
+        self
+    }
+
     /// Adds a transaction to the pool in a thread-safe way.
     pub fn add(&self, k_hash: B256, tx: SignedTransaction) -> Result<(), PoolError> {
+        self.admit(k_hash, tx, None)
+    }
+
+    /// Like `add`, also refusing the transaction if the sender's `balance` can't pay for it
+    /// together with the sender's other pooled transactions.
+    pub fn add_with_balance(
+        &self,
+        k_hash: B256,
+        tx: SignedTransaction,
+        balance: U256,
+    ) -> Result<(), PoolError> {
+        self.admit(k_hash, tx, Some(balance))
+    }
+
+    fn admit(
+        &self,
+        k_hash: B256,
+        tx: SignedTransaction,
+        balance: Option<U256>,
+    ) -> Result<(), PoolError> {
         // Lock the Mutex
-        let mut pool = self.inner.lock().map_err(|_| "Lock poisoned".to_string())?;
+        let mut pool = lock(&self.inner);
         // Call the internal function
-        pool.add(k_hash, tx);
-
+        pool.add(k_hash, tx, balance)?;
+        self.admitted.notify_all();
         Ok(())
     }
 
+    /// Blocks until more than `seen` transactions were admitted since the pool was created, or
+    /// `timeout` passed, then returns the count. Pass the returned value back in to wait for
+    /// the next ones.
+    pub fn wait_for_transactions(&self, seen: u64, timeout: Duration) -> u64 {
+        let pool = lock(&self.inner);
+        let (pool, _) = self
+            .admitted
+            .wait_timeout_while(pool, timeout, |pool| pool.received <= seen)
+            .unwrap_or_else(PoisonError::into_inner);
+        pool.received
+    }
+
     /// Retrieves all transactions.
     pub fn get_all_transactions(&self) -> Vec<SignedTransaction> {
-        let pool = self.inner.lock().unwrap();
+        let pool = lock(&self.inner);
         pool.get_all()
     }
 
+    /// Retrieves all transactions along with their senders.
+    pub fn get_pooled_transactions(&self) -> Vec<PooledTx> {
+        let pool = lock(&self.inner);
+        pool.transactions.values().cloned().collect()
+    }
+
+    /// The pooled transactions of each sender, by nonce.
+    pub fn content_by_sender(&self) -> BTreeMap<Address, BTreeMap<u64, PooledTx>> {
+        let pool = lock(&self.inner);
+        pool.by_sender
+            .iter()
+            .map(|(sender, hashes)| {
+                let txs = hashes
+                    .iter()
+                    .filter_map(|hash| pool.transactions.get(hash))
+                    .map(|pooled| (pooled.tx.transaction().nonce, pooled.clone()))
+                    .collect();
+                (*sender, txs)
+            })
+            .collect()
+    }
+
+    /// The transactions pending for at least `interval` since they arrived or were last
+    /// re-broadcast, oldest first, marked as re-broadcast now. Recent ones are left to the
+    /// gossip they arrived with.
+    pub fn due_for_rebroadcast(&self, interval: Duration) -> Vec<SignedTransaction> {
+        let mut pool = lock(&self.inner);
+        let now = pool.clock.now();
+        let mut due: Vec<&mut PooledTx> = pool
+            .transactions
+            .values_mut()
+            .filter(|pooled| {
+                let last_sent = pooled.rebroadcast_at.unwrap_or(pooled.received_at);
+                now.saturating_duration_since(last_sent) >= interval
+            })
+            .collect();
+        due.sort_by_key(|pooled| pooled.received_at);
+        due.into_iter()
+            .map(|pooled| {
+                pooled.rebroadcast_at = Some(now);
+                pooled.tx.clone()
+            })
+            .collect()
+    }
+
+    /// Drops the transactions pooled for at least `PoolConfig::ttl`, and returns how many.
+    pub fn prune_expired(&self) -> usize {
+        let mut pool = lock(&self.inner);
+        let Some(ttl) = pool.config.ttl else {
+            return 0;
+        };
+        let now = pool.clock.now();
+        let expired: Vec<B256> = pool
+            .transactions
+            .iter()
+            .filter(|(_, pooled)| now.saturating_duration_since(pooled.received_at) >= ttl)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in &expired {
+            pool.remove(hash);
+        }
+        expired.len()
+    }
+
+    /// Count, age, size and fee range of the pooled transactions.
+    pub fn stats(&self) -> PoolStats {
+        let pool = lock(&self.inner);
+        pool.stats()
+    }
+
+    /// Lowest gas price the pool accepts.
+    pub fn min_gas_price(&self) -> U256 {
+        let pool = lock(&self.inner);
+        pool.config.min_gas_price
+    }
+
+    /// Number of pooled transactions.
+    pub fn len(&self) -> usize {
+        let pool = lock(&self.inner);
+        pool.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes the given transactions (e.g. the ones a block included). Unknown hashes are ignored.
+    pub fn remove(&self, k_hashes: &[B256]) {
+        let mut pool = lock(&self.inner);
+        for k_hash in k_hashes {
+            pool.remove(k_hash);
+        }
+    }
+
     /// Clears the pool
     pub fn clear(&self) {
-        let mut pool = self.inner.lock().unwrap();
+        let mut pool = lock(&self.inner);
         pool.clear();
     }
+
+    /// RLP of every pooled transaction, back to back (to keep them across restarts).
+    pub fn export(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for tx in self.get_all_transactions() {
+            tx.encode(&mut out);
+        }
+        out
+    }
+
+    /// Adds the transactions written by `export`, through the same checks as `add`.
+    /// Refused ones are skipped. Returns how many were pooled and why the others were refused.
+    pub fn import(&self, mut data: &[u8]) -> Result<(usize, Vec<(B256, PoolError)>), String> {
+        let mut imported = 0;
+        let mut skipped = Vec::new();
+        while !data.is_empty() {
+            let tx = SignedTransaction::decode(&mut data).map_err(|e| e.to_string())?;
+            let k_hash = tx.hash();
+            match self.add(k_hash, tx) {
+                Ok(()) => imported += 1,
+                Err(e) => skipped.push((k_hash, e)),
+            }
+        }
+        Ok((imported, skipped))
+    }
+}
+
+// Pool methods check a transaction before inserting it, so a panicking caller can't leave
+// half an entry behind; take over a poisoned lock rather than failing every later call
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 #[cfg(test)]
@@ -93,12 +583,23 @@ mod tests {
     fn mock_tx(nonce: u64) -> SignedTransaction {
         // Generate random key
         let signing_key = SigningKey::random(&mut OsRng);
+        mock_tx_from(&signing_key, nonce)
+    }
 
+    // Helper to generate a SignedTransaction from a given sender
+    fn mock_tx_from(signing_key: &SigningKey, nonce: u64) -> SignedTransaction {
+        mock_tx_priced(signing_key, nonce, 1)
+    }
+
+    // Helper to generate a SignedTransaction paying `gas_price`
+    fn mock_tx_priced(signing_key: &SigningKey, nonce: u64, gas_price: u64) -> SignedTransaction {
         // Create Tx
         let tx = Transaction {
             to: Address::ZERO,
             value: U256::from(100),
             nonce,
+            gas_price: U256::from(gas_price),
+            data: Vec::new(),
         };
 
         // Sign it
@@ -106,11 +607,7 @@ mod tests {
             .sign_prehash_recoverable(tx.hash().as_slice())
             .unwrap();
 
-        SignedTransaction {
-            transaction: tx,
-            signature,
-            recovery_id: recid,
-        }
+        SignedTransaction::new(tx, signature, recid)
     }
 
     #[test]
@@ -174,4 +671,295 @@ mod tests {
         let all_txs = pool.get_all_transactions();
         assert_eq!(all_txs.len(), 10);
     }
+
+    #[test]
+    fn test_max_per_sender() {
+        let pool = SharedTxPool::with_config(PoolConfig {
+            max_per_sender: 2,
+            ..Default::default()
+        });
+        let alice = SigningKey::random(&mut OsRng);
+        let bob = SigningKey::random(&mut OsRng);
+
+        // Alice fills her slots
+        for nonce in 0..2 {
+            let tx = mock_tx_from(&alice, nonce);
+            pool.add(tx.hash(), tx).unwrap();
+        }
+
+        // Her next one is rejected
+        let tx = mock_tx_from(&alice, 2);
+        assert!(pool.add(tx.hash(), tx).is_err());
+
+        // Bob is unaffected
+        let tx = mock_tx_from(&bob, 0);
+        pool.add(tx.hash(), tx).unwrap();
+
+        assert_eq!(pool.get_all_transactions().len(), 3);
+    }
+
+    #[test]
+    fn test_pooled_sender_matches_recovery() {
+        let pool = SharedTxPool::new();
+        let tx = mock_tx(0);
+        pool.add(tx.hash(), tx.clone()).unwrap();
+
+        let pooled = pool.get_pooled_transactions();
+
+        assert_eq!(pooled.len(), 1);
+        assert_eq!(pooled[0].tx, tx);
+        assert_eq!(pooled[0].sender, tx.recover_sender().unwrap());
+    }
+
+    #[test]
+    fn test_remove_frees_sender_slot() {
+        let pool = SharedTxPool::with_config(PoolConfig {
+            max_per_sender: 1,
+            ..Default::default()
+        });
+        let alice = SigningKey::random(&mut OsRng);
+        let first = mock_tx_from(&alice, 0);
+        let second = mock_tx_from(&alice, 1);
+        pool.add(first.hash(), first.clone()).unwrap();
+
+        pool.remove(&[first.hash()]);
+
+        assert!(pool.is_empty());
+        assert!(pool.add(second.hash(), second).is_ok());
+    }
+
+    #[test]
+    fn test_min_gas_price() {
+        let pool = SharedTxPool::with_config(PoolConfig {
+            min_gas_price: U256::from(10),
+            ..Default::default()
+        });
+        let alice = SigningKey::random(&mut OsRng);
+
+        let cheap = mock_tx_priced(&alice, 0, 9);
+        let err = pool.add(cheap.hash(), cheap).unwrap_err();
+        assert_eq!(err.to_string(), "Gas price 9 is below the minimum of 10");
+
+        // At and above the floor
+        let at_floor = mock_tx_priced(&alice, 0, 10);
+        assert!(pool.add(at_floor.hash(), at_floor).is_ok());
+        let above = mock_tx_priced(&alice, 1, 11);
+        assert!(pool.add(above.hash(), above).is_ok());
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_stats() {
+        let pool = SharedTxPool::new();
+        assert_eq!(pool.stats(), PoolStats::default());
+
+        let alice = SigningKey::random(&mut OsRng);
+        let cheap = mock_tx_priced(&alice, 0, 1);
+        let pricey = mock_tx_priced(&alice, 1, 3);
+        pool.add(cheap.hash(), cheap.clone()).unwrap();
+        pool.add(pricey.hash(), pricey.clone()).unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.total_bytes, cheap.length() + pricey.length());
+        assert_eq!(stats.min_fee, Some(cheap.transaction().fee()));
+        assert_eq!(stats.max_fee, Some(pricey.transaction().fee()));
+    }
+
+    #[test]
+    fn test_replacement_needs_price_bump() {
+        let pool = SharedTxPool::new();
+        let alice = SigningKey::random(&mut OsRng);
+        let original = mock_tx_priced(&alice, 0, 100);
+        pool.add(original.hash(), original.clone()).unwrap();
+
+        // Higher, but by less than 10%
+        let cheap = mock_tx_priced(&alice, 0, 109);
+        let err = pool.add(cheap.hash(), cheap).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Replacement gas price 109 must be at least 10% above 100"
+        );
+        assert_eq!(pool.get_all_transactions(), vec![original]);
+
+        // Exactly 10% more replaces it
+        let bumped = mock_tx_priced(&alice, 0, 110);
+        pool.add(bumped.hash(), bumped.clone()).unwrap();
+        assert_eq!(pool.get_all_transactions(), vec![bumped]);
+    }
+
+    #[test]
+    fn test_replacement_price_bump_is_configurable() {
+        let pool = SharedTxPool::with_config(PoolConfig {
+            max_per_sender: 1,
+            price_bump_percent: 50,
+            ..Default::default()
+        });
+        let alice = SigningKey::random(&mut OsRng);
+        let original = mock_tx_priced(&alice, 0, 10);
+        pool.add(original.hash(), original).unwrap();
+
+        let short = mock_tx_priced(&alice, 0, 14);
+        assert!(pool.add(short.hash(), short).is_err());
+
+        // Replacing doesn't count against the per-sender limit
+        let bumped = mock_tx_priced(&alice, 0, 15);
+        pool.add(bumped.hash(), bumped.clone()).unwrap();
+        assert_eq!(pool.get_all_transactions(), vec![bumped]);
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let pool = SharedTxPool::new();
+        let signing_key = SigningKey::random(&mut OsRng);
+        for nonce in 0..3 {
+            let tx = mock_tx_from(&signing_key, nonce);
+            pool.add(tx.hash(), tx).unwrap();
+        }
+
+        let restored = SharedTxPool::new();
+        assert_eq!(restored.import(&pool.export()), Ok((3, vec![])));
+
+        let mut expected = pool.get_all_transactions();
+        let mut got = restored.get_all_transactions();
+        expected.sort_by_key(|tx| tx.hash());
+        got.sort_by_key(|tx| tx.hash());
+        assert_eq!(got, expected);
+
+        // Refused ones come back with the reason instead of being pooled
+        let strict = SharedTxPool::with_config(PoolConfig {
+            min_gas_price: U256::from(2),
+            ..Default::default()
+        });
+        let (imported, skipped) = strict.import(&pool.export()).unwrap();
+        assert_eq!(imported, 0);
+        assert_eq!(skipped.len(), 3);
+        assert!(matches!(skipped[0].1, PoolError::Underpriced { .. }));
+        assert!(strict.get_all_transactions().is_empty());
+        assert!(restored.import(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn test_pending_spend_is_capped_by_balance() {
+        let pool = SharedTxPool::new();
+        let signing_key = SigningKey::random(&mut OsRng);
+        // Each costs 100 of value plus 21_000 of fee
+        let txs: Vec<_> = (0..3)
+            .map(|nonce| mock_tx_from(&signing_key, nonce))
+            .collect();
+        let balance = U256::from(2 * 21_100 + 50);
+
+        for tx in &txs[..2] {
+            pool.add_with_balance(tx.hash(), tx.clone(), balance)
+                .unwrap();
+        }
+        let err = pool
+            .add_with_balance(txs[2].hash(), txs[2].clone(), balance)
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            PoolError::InsufficientBalance {
+                sender: txs[2].recover_sender().unwrap(),
+                cost: U256::from(3 * 21_100),
+                balance,
+            }
+        );
+        assert_eq!(pool.len(), 2);
+
+        // A replacement only counts once
+        let replacement = mock_tx_priced(&signing_key, 1, 1);
+        let pricier = {
+            let tx = Transaction {
+                gas_price: U256::from(2),
+                ..replacement.transaction().clone()
+            };
+            let (signature, recid) = signing_key
+                .sign_prehash_recoverable(tx.hash().as_slice())
+                .unwrap();
+            SignedTransaction::new(tx, signature, recid)
+        };
+        let roomy = U256::from(21_100 + 42_100);
+        pool.add_with_balance(pricier.hash(), pricier, roomy)
+            .unwrap();
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_only_stale_transactions_are_rebroadcast() {
+        let clock = MockClock::new(0);
+        let pool = SharedTxPool::new().with_clock(clock.clone());
+        let interval = Duration::from_secs(30);
+        let stale = mock_tx(0);
+        pool.add(stale.hash(), stale.clone()).unwrap();
+        clock.advance(interval);
+        let fresh = mock_tx(0);
+        pool.add(fresh.hash(), fresh).unwrap();
+
+        assert_eq!(pool.due_for_rebroadcast(interval), vec![stale.clone()]);
+        // Just sent, it waits another interval
+        assert!(pool.due_for_rebroadcast(interval).is_empty());
+        clock.advance(interval);
+        assert_eq!(pool.due_for_rebroadcast(interval).len(), 2);
+    }
+
+    #[test]
+    fn test_transactions_sharing_an_account_conflict() {
+        let pooled = |sender: u8, to: u8| {
+            let signing_key = SigningKey::random(&mut OsRng);
+            let tx = Transaction {
+                to: Address::with_last_byte(to),
+                ..mock_tx_from(&signing_key, 0).transaction().clone()
+            };
+            let (signature, recid) = signing_key
+                .sign_prehash_recoverable(tx.hash().as_slice())
+                .unwrap();
+            PooledTx {
+                tx: SignedTransaction::new(tx, signature, recid),
+                sender: Address::with_last_byte(sender),
+                received_at: Instant::now(),
+                size: 0,
+                rebroadcast_at: None,
+            }
+        };
+        let (a_to_b, c_to_d) = (pooled(0xa, 0xb), pooled(0xc, 0xd));
+        assert_eq!(
+            a_to_b.touched_accounts(),
+            vec![Address::with_last_byte(0xa), Address::with_last_byte(0xb)]
+        );
+        assert!(!a_to_b.conflicts_with(&c_to_d));
+
+        // Same recipient, or one's recipient is the other's sender
+        assert!(a_to_b.conflicts_with(&pooled(0xc, 0xb)));
+        assert!(a_to_b.conflicts_with(&pooled(0xb, 0xd)));
+        assert_eq!(pooled(0xa, 0xa).touched_accounts().len(), 1);
+    }
+
+    #[test]
+    fn test_transactions_expire_after_the_ttl() {
+        let clock = MockClock::new(0);
+        let pool = SharedTxPool::with_config(PoolConfig {
+            ttl: Some(Duration::from_secs(60)),
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        let old = mock_tx(0);
+        pool.add(old.hash(), old.clone()).unwrap();
+        clock.advance(Duration::from_secs(30));
+        let young = mock_tx(0);
+        pool.add(young.hash(), young.clone()).unwrap();
+        assert_eq!(pool.stats().oldest_age, Duration::from_secs(30));
+
+        // One second before the old one expires, then right on time
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(pool.prune_expired(), 0);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(pool.prune_expired(), 1);
+        assert_eq!(pool.get_all_transactions().len(), 1);
+        assert_eq!(pool.get_all_transactions()[0].hash(), young.hash());
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(pool.prune_expired(), 1);
+        assert!(pool.get_all_transactions().is_empty());
+    }
 }
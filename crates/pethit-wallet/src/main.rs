@@ -1,156 +1,1089 @@
-use alloy_primitives::{Address, U256};
+use alloy_primitives::{Address, B256, U256};
 use alloy_rlp::Encodable;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use k256::{ecdsa::SigningKey, elliptic_curve::rand_core::OsRng};
-use pethit_execution::{SignedTransaction, Transaction};
+use pethit_client::{Client, ClientError};
+use pethit_execution::{SignedTransaction, Transaction, normalize_signature};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::str::FromStr;
 
 /// Pethit Wallet CLI
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// How results and errors are printed: prose, or one JSON object for scripts
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// Config file with defaults for the flags left out [default: ~/.pethit/wallet.toml]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
 
+// Used when neither a flag nor the config file sets them
+const DEFAULT_RPC: &str = "http://127.0.0.1:3000";
+const DEFAULT_KEYSTORE_DIR: &str = ".pethit-wallet";
+
+/// Defaults for the flags left out, read from a TOML file. Any other key is an error.
+/// Transactions don't carry a chain id, so `chain_id` is only checked against the node's head
+/// before sending, to catch a wallet pointed at the wrong network.
+///
+/// ```toml
+/// rpc = "http://127.0.0.1:3000"
+/// chain_id = 1
+/// keystore_dir = "/home/me/.pethit/keystore"
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    rpc: Option<String>,
+    chain_id: Option<u64>,
+    keystore_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// Reads `path`, or `~/.pethit/wallet.toml` when it's None. Only the latter may be missing
+    /// (the built-in defaults apply).
+    fn load(path: Option<&Path>) -> Result<Self, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match std::env::var_os("HOME") {
+                Some(home) => (PathBuf::from(home).join(".pethit/wallet.toml"), false),
+                None => return Ok(Config::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                Config::parse(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(e) => Err(format!("Cannot read {}: {}", path.display(), e)),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    // The flag, else the config, else the built-in default
+    fn rpc(&self, flag: Option<String>) -> String {
+        flag.or_else(|| self.rpc.clone())
+            .unwrap_or_else(|| DEFAULT_RPC.to_string())
+    }
+
+    // None: the node's chain isn't checked
+    fn chain_id(&self, flag: Option<u64>) -> Option<u64> {
+        flag.or(self.chain_id)
+    }
+
+    fn keystore_dir(&self, flag: Option<PathBuf>) -> PathBuf {
+        flag.or_else(|| self.keystore_dir.clone())
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_KEYSTORE_DIR))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// What a command has to say, in both formats.
+struct Report {
+    text: String,
+    json: Value,
+}
+
+impl Report {
+    fn render(&self, output: OutputFormat) -> String {
+        match output {
+            OutputFormat::Text => self.text.clone(),
+            OutputFormat::Json => format!("{}\n", self.json),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Generate a new random private key and address
     Generate,
+    /// Print the address of a private key
+    Address {
+        /// Private key in hex format (0x prefix optional)
+        #[arg(
+            long,
+            required_unless_present = "keystore",
+            conflicts_with = "keystore"
+        )]
+        private_key: Option<String>,
+        /// File holding the private key in hex format (0x prefix optional)
+        #[arg(long)]
+        keystore: Option<PathBuf>,
+    },
     /// Send a transaction
     Send {
-        /// Private key in hex format without 0x prefix
+        /// Private key in hex format (0x prefix optional)
         #[arg(long)]
         private_key: String,
-        /// Receiver address (0x)
+        /// Receiver address (20 bytes hex, 0x prefix optional)
         #[arg(long)]
         to: String,
         /// Amount to send
         #[arg(long)]
         value: u64,
-        /// RPC URL
-        #[arg(long, default_value = "http://127.0.0.1:3000")]
-        rpc: String,
+        /// Price per unit of gas, paid to the miner
+        #[arg(long, default_value_t = 1)]
+        gas_price: u64,
+        /// RPC URL [default: from the config, else http://127.0.0.1:3000]
+        #[arg(long)]
+        rpc: Option<String>,
+        /// Chain the node must be on, checked before sending [default: from the config, else
+        /// not checked]
+        #[arg(long)]
+        chain_id: Option<u64>,
+        /// Directory where the wallet keeps its local state (next nonce per address)
+        /// [default: from the config, else .pethit-wallet]
+        #[arg(long)]
+        keystore_dir: Option<PathBuf>,
+        /// Forget the locally tracked nonce and use the one the node reports
+        #[arg(long)]
+        reset_nonce: bool,
+    },
+    /// Send one transfer per `to,value` row of a CSV file, with consecutive nonces
+    BatchSend {
+        /// CSV file of `to,value` rows (a `to,value` header line is allowed)
+        #[arg(long)]
+        file: PathBuf,
+        /// Private key in hex format (0x prefix optional)
+        #[arg(long)]
+        private_key: String,
+        /// Price per unit of gas of every transaction, paid to the miner
+        #[arg(long, default_value_t = 1)]
+        gas_price: u64,
+        /// RPC URL [default: from the config, else http://127.0.0.1:3000]
+        #[arg(long)]
+        rpc: Option<String>,
+        /// Chain the node must be on, checked before sending [default: from the config, else
+        /// not checked]
+        #[arg(long)]
+        chain_id: Option<u64>,
+        /// Directory where the wallet keeps its local state (next nonce per address)
+        /// [default: from the config, else .pethit-wallet]
+        #[arg(long)]
+        keystore_dir: Option<PathBuf>,
+        /// Keep going after a failed row instead of stopping
+        #[arg(long)]
+        continue_on_error: bool,
+    },
+    /// Fund an address from the node's faucet (dev mode only)
+    Faucet {
+        /// Address to credit (20 bytes hex, 0x prefix optional)
+        #[arg(long)]
+        to: String,
+        /// Amount to credit, in wei
+        #[arg(long)]
+        amount: u64,
+        /// API key of the node, if it requires one
+        #[arg(long)]
+        api_key: Option<String>,
+        /// RPC URL [default: from the config, else http://127.0.0.1:3000]
+        #[arg(long)]
+        rpc: Option<String>,
+    },
+    /// Fetch a block and print it
+    Block {
+        /// Block hash (32 bytes hex, 0x prefix optional)
+        #[arg(long)]
+        hash: String,
+        /// RPC URL [default: from the config, else http://127.0.0.1:3000]
+        #[arg(long)]
+        rpc: Option<String>,
     },
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    let output = cli.output;
+    exit_code(run(cli).await, output)
+}
+
+// Failures (node errors included) go to stderr with a non-zero exit, so scripts can tell
+fn exit_code(result: Result<(), Box<dyn std::error::Error>>, output: OutputFormat) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match output {
+                OutputFormat::Text => eprintln!("Error: {}", e),
+                OutputFormat::Json => eprintln!("{}", error_json(e.as_ref())),
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// `{"error": ...}`, with the HTTP status and the error code when the node refused the request
+fn error_json(error: &(dyn std::error::Error + 'static)) -> Value {
+    let mut json = json!({ "error": error.to_string() });
+    if let Some(ClientError::Rpc { status, code, .. }) = error.downcast_ref::<ClientError>() {
+        json["status"] = json!(status);
+        json["code"] = json!(code);
+    }
+    json
+}
 
-    match cli.command {
-        Commands::Generate => {
-            generate_wallet();
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load(cli.config.as_deref())?;
+    let report = match cli.command {
+        Commands::Generate => generate_wallet(),
+        Commands::Address {
+            private_key,
+            keystore,
+        } => {
+            let private_key = match (private_key, keystore) {
+                (Some(private_key), _) => private_key,
+                (None, Some(keystore)) => read_keystore(&keystore)?,
+                // clap requires one of them
+                (None, None) => unreachable!(),
+            };
+            let signer = parse_private_key(&private_key)?;
+            let address = address_of(&signer).to_checksum(None);
+            Report {
+                text: format!("{}\n", address),
+                json: json!({ "address": address }),
+            }
         }
         Commands::Send {
             private_key,
             to,
             value,
+            gas_price,
             rpc,
+            chain_id,
+            keystore_dir,
+            reset_nonce,
         } => {
-            send_transaction(private_key, to, value, rpc).await?;
+            let mut nonces = NonceTracker::load(&config.keystore_dir(keystore_dir))?;
+            let client = Client::new(config.rpc(rpc));
+            check_chain_id(&client, config.chain_id(chain_id)).await?;
+            let sent = send_transaction(
+                &client,
+                &mut nonces,
+                &private_key,
+                &to,
+                value,
+                gas_price,
+                reset_nonce,
+            )
+            .await?;
+            let from = sent.from.to_checksum(None);
+            Report {
+                text: format!(
+                    "Sending from: {}\n  Nonce: {}\nTransaction sent! Hash: {}\n",
+                    from, sent.nonce, sent.tx_hash
+                ),
+                json: json!({
+                    "from": from,
+                    "nonce": sent.nonce,
+                    "tx_hash": sent.tx_hash.to_string(),
+                }),
+            }
         }
-    }
+        Commands::BatchSend {
+            file,
+            private_key,
+            gas_price,
+            rpc,
+            chain_id,
+            keystore_dir,
+            continue_on_error,
+        } => {
+            let csv = fs::read_to_string(&file)
+                .map_err(|e| format!("Cannot read {}: {}", file.display(), e))?;
+            let mut nonces = NonceTracker::load(&config.keystore_dir(keystore_dir))?;
+            let client = Client::new(config.rpc(rpc));
+            check_chain_id(&client, config.chain_id(chain_id)).await?;
+            let rows = batch_send(
+                &client,
+                &mut nonces,
+                &private_key,
+                &csv,
+                gas_price,
+                continue_on_error,
+            )
+            .await;
+
+            let mut text = String::new();
+            let mut json_rows = Vec::new();
+            for row in &rows {
+                match &row.result {
+                    Ok(sent) => {
+                        text.push_str(&format!("Line {}: sent {}\n", row.line, sent.tx_hash));
+                        json_rows.push(json!({
+                            "line": row.line,
+                            "nonce": sent.nonce,
+                            "tx_hash": sent.tx_hash.to_string(),
+                        }));
+                    }
+                    Err(e) => {
+                        text.push_str(&format!("Line {}: failed: {}\n", row.line, e));
+                        json_rows.push(json!({ "line": row.line, "error": e }));
+                    }
+                }
+            }
+            let report = Report {
+                text,
+                json: json!({ "rows": json_rows }),
+            };
+            // The rows are printed either way, the failure comes on top
+            let failed = rows.iter().filter(|row| row.result.is_err()).count();
+            if failed > 0 {
+                print!("{}", report.render(cli.output));
+                return Err(format!("{} of {} transactions failed", failed, rows.len()).into());
+            }
+            report
+        }
+        Commands::Faucet {
+            to,
+            amount,
+            api_key,
+            rpc,
+        } => {
+            let (to, _) = parse_address(&to)?;
+            let account = Client::new(config.rpc(rpc))
+                .faucet(to, U256::from(amount), api_key.as_deref())
+                .await?;
+            let address = account.address.to_checksum(None);
+            Report {
+                text: format!("Funded {}. New balance: {} Wei\n", address, account.balance),
+                json: json!({ "address": address, "balance": account.balance.to_string() }),
+            }
+        }
+        Commands::Block { hash, rpc } => {
+            let hash = B256::from_str(strip_0x(&hash))
+                .map_err(|_| format!("Invalid block hash '{}'", hash))?;
+            describe_block(&Client::new(config.rpc(rpc)), hash).await?
+        }
+    };
+    print!("{}", report.render(cli.output));
     Ok(())
 }
 
-fn generate_wallet() {
+fn generate_wallet() -> Report {
     // Generate a private random key
     let signing_key = SigningKey::random(&mut OsRng);
-    let secret_bytes = signing_key.to_bytes();
+    let private_key = hex::encode(signing_key.to_bytes());
+
+    let address = address_of(&signing_key).to_checksum(None);
 
-    // Derive address
-    let verifying_key = signing_key.verifying_key();
-    let public_key_bytes = verifying_key.to_encoded_point(false); // uncompressed
+    Report {
+        text: format!(
+            "New Wallet Generated:\n\
+             Private Key: {}\n\
+             Address:     {}\n\
+             SAVE THIS PRIVATE KEY! IT WILL NOT BE SHOWN AGAIN.\n",
+            private_key, address
+        ),
+        json: json!({ "address": address, "private_key": private_key }),
+    }
+}
+
+// Derives the address of a key: the last 20 bytes of the keccak of the public key
+fn address_of(signing_key: &SigningKey) -> Address {
+    let public_key_bytes = signing_key.verifying_key().to_encoded_point(false); // uncompressed
     let public_key_slice = &public_key_bytes.as_bytes()[1..]; // Remove first byte (0x04)
     let hash = alloy_primitives::keccak256(public_key_slice);
-    let address = Address::from_slice(&hash[12..]); // Last 20 bytes
+    Address::from_slice(&hash[12..])
+}
 
-    println!("New Wallet Generated:");
-    println!("Private Key: {}", hex::encode(secret_bytes));
-    println!("Address:     {}", address);
-    println!("SAVE THIS PRIVATE KEY! IT WILL NOT BE SHOWN AGAIN.");
+// Reads a private key saved in a file, surrounding whitespace ignored
+fn read_keystore(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path)
+        .map(|contents| contents.trim().to_string())
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))
 }
 
-async fn send_transaction(
-    private_key_hex: String,
-    to_str: String,
-    value: u64,
-    rpc_url: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let priv_key_bytes = hex::decode(private_key_hex)?;
+/// Next nonce per address, for the transactions this wallet sent that the node may not reflect yet.
+/// Stored as `<address> <nonce>` lines in `<dir>/nonces`.
+struct NonceTracker {
+    path: PathBuf,
+    next: BTreeMap<Address, u64>,
+}
 
-    // Load signer from raw bytes
-    let signer = SigningKey::from_slice(&priv_key_bytes)?;
-    let verifying_key = signer.verifying_key();
+impl NonceTracker {
+    /// A missing file is an empty tracker.
+    fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join("nonces");
+        let mut next = BTreeMap::new();
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    let entry = line.split_once(' ').and_then(|(address, nonce)| {
+                        Some((Address::from_str(address).ok()?, nonce.parse().ok()?))
+                    });
+                    let (address, nonce) = entry.ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Corrupt nonce file {}: '{}'", path.display(), line),
+                        )
+                    })?;
+                    next.insert(address, nonce);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Self { path, next })
+    }
 
+    /// The tracked nonce wins only when it's ahead of the node's.
+    fn next_nonce(&self, address: Address, node_nonce: u64) -> u64 {
+        self.next
+            .get(&address)
+            .map_or(node_nonce, |&tracked| tracked.max(node_nonce))
+    }
+
+    fn record(&mut self, address: Address, next_nonce: u64) -> io::Result<()> {
+        self.next.insert(address, next_nonce);
+        self.save()
+    }
+
+    fn reset(&mut self, address: Address) -> io::Result<()> {
+        if self.next.remove(&address).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents: String = self
+            .next
+            .iter()
+            .map(|(address, nonce)| format!("{} {}\n", address, nonce))
+            .collect();
+        fs::write(&self.path, contents)
+    }
+}
+
+// A transaction the node accepted
+#[derive(Debug)]
+struct SentTx {
+    from: Address,
+    nonce: u64,
+    tx_hash: B256,
+}
+
+// Signs and submits a transfer. The nonce is the node's, unless `nonces` knows of
+// transactions sent after it (still in the pool).
+// Refuses to go on when the node's head isn't on `expected` (None: anything goes)
+async fn check_chain_id(
+    client: &Client,
+    expected: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let head = client.head().await?;
+    if head.chain_id != expected {
+        let message = format!(
+            "The node is on chain {}, expected chain {}",
+            head.chain_id, expected
+        );
+        return Err(message.into());
+    }
+    Ok(())
+}
+
+async fn send_transaction(
+    client: &Client,
+    nonces: &mut NonceTracker,
+    private_key_hex: &str,
+    to_str: &str,
+    value: u64,
+    gas_price: u64,
+    reset_nonce: bool,
+) -> Result<SentTx, Box<dyn std::error::Error>> {
+    let signer = parse_private_key(private_key_hex)?;
     // Derive the address to check nonce
-    let public_key_bytes = verifying_key.to_encoded_point(false);
-    let hash = alloy_primitives::keccak256(&public_key_bytes.as_bytes()[1..]);
-    let from_address = Address::from_slice(&hash[12..]);
+    let from_address = address_of(&signer);
 
-    println!("Sending from: {}", from_address);
+    // Get nonce from RPC, then account for our own in-flight transactions
+    if reset_nonce {
+        nonces.reset(from_address)?;
+    }
+    let node_nonce = client.get_nonce(from_address).await?;
+    let nonce = nonces.next_nonce(from_address, node_nonce);
 
-    // Get nonce from RPC
-    let nonce = fetch_nonce(&rpc_url, from_address).await.unwrap_or(0);
-    println!("  Nonce: {}", nonce);
+    let (to, checksum_ok) = parse_address(to_str)?;
+    if !checksum_ok {
+        eprintln!(
+            "Warning: --to does not match its EIP-55 checksum (expected {})",
+            to.to_checksum(None)
+        );
+    }
 
     // Create transaction
     let tx = Transaction {
-        to: Address::from_str(&to_str)?,
+        to,
         value: U256::from(value),
         nonce,
+        gas_price: U256::from(gas_price),
+        data: Vec::new(),
     };
 
     // Sign transaction
     let tx_hash = tx.hash();
     let (signature, recid) = signer.sign_prehash_recoverable(tx_hash.as_slice())?;
-    let signed_tx = SignedTransaction {
-        transaction: tx,
-        signature,
-        recovery_id: recid,
-    };
+    // Nodes only accept low-S signatures
+    let (signature, recid) = normalize_signature(signature, recid);
+    let signed_tx = SignedTransaction::new(tx, signature, recid);
 
     // Encode to RLP
     let mut rlp_bytes = Vec::new();
     signed_tx.encode(&mut rlp_bytes);
-    let tx_hex = hex::encode(rlp_bytes);
 
     // Send tx to RPC
-    let send_tx_url = format!("{}/send_tx", rpc_url);
-    let client = reqwest::Client::new();
-    let response = client
-        .post(&send_tx_url)
-        .header("content-type", "application/json")
-        .json(&serde_json::json!({
-            "raw_tx": tx_hex
-        }))
-        .send()
-        .await?;
-
-    let response_text = response.text().await?;
-    println!("Response: {}", response_text);
+    let tx_hash = client.send_raw_tx(&rlp_bytes).await?;
+    nonces.record(from_address, nonce + 1)?;
 
-    Ok(())
+    Ok(SentTx {
+        from: from_address,
+        nonce,
+        tx_hash,
+    })
+}
+
+// Outcome of one row of a batch file
+struct BatchRow {
+    // 1-based line in the file
+    line: usize,
+    result: Result<SentTx, String>,
+}
+
+// Sends the `to,value` rows of `csv` in order. Nonces follow each other since every successful
+// send is recorded in `nonces`, and a failed row doesn't use one.
+// Stops after the first failure unless `continue_on_error`.
+async fn batch_send(
+    client: &Client,
+    nonces: &mut NonceTracker,
+    private_key_hex: &str,
+    csv: &str,
+    gas_price: u64,
+    continue_on_error: bool,
+) -> Vec<BatchRow> {
+    let mut rows = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || (line_number == 1 && line.eq_ignore_ascii_case("to,value")) {
+            continue;
+        }
+
+        let result = match line.split_once(',') {
+            Some((to, value)) => match value.trim().parse() {
+                Ok(value) => send_transaction(
+                    client,
+                    nonces,
+                    private_key_hex,
+                    to.trim(),
+                    value,
+                    gas_price,
+                    false,
+                )
+                .await
+                .map_err(|e| e.to_string()),
+                Err(_) => Err(format!("Invalid value '{}'", value.trim())),
+            },
+            None => Err(format!("Expected 'to,value', got '{}'", line)),
+        };
+        let failed = result.is_err();
+        rows.push(BatchRow {
+            line: line_number,
+            result,
+        });
+        if failed && !continue_on_error {
+            break;
+        }
+    }
+    rows
+}
+
+// Fetches a block and renders it (or says it doesn't exist, `{"block": null}` in JSON)
+async fn describe_block(client: &Client, hash: B256) -> Result<Report, Box<dyn std::error::Error>> {
+    let Some(block) = client.get_block_by_hash(hash).await? else {
+        return Ok(Report {
+            text: format!("Block {} not found\n", hash),
+            json: json!({ "block": null }),
+        });
+    };
+
+    let mut out = String::new();
+    out.push_str(&format!("Block #{}\n", block.number));
+    out.push_str(&format!("  Hash:         {}\n", block.hash));
+    out.push_str(&format!("  Parent:       {}\n", block.parent_hash));
+    out.push_str(&format!("  Timestamp:    {}\n", block.timestamp));
+    out.push_str(&format!("  Transactions: {}\n", block.transactions.len()));
+    for tx_hash in &block.transactions {
+        out.push_str(&format!("    {}\n", tx_hash));
+    }
+    let json = json!({
+        "block": {
+            "number": block.number,
+            "hash": block.hash.to_string(),
+            "parent_hash": block.parent_hash.to_string(),
+            "timestamp": block.timestamp,
+            "coinbase": block.coinbase.to_checksum(None),
+            "transactions": block.transactions.iter().map(B256::to_string).collect::<Vec<_>>(),
+        }
+    });
+    Ok(Report { text: out, json })
+}
+
+// Every hex input of the CLI takes an optional 0x prefix
+fn strip_0x(input: &str) -> &str {
+    input.strip_prefix("0x").unwrap_or(input)
+}
+
+// Parses a 32-byte hex private key, with or without 0x.
+fn parse_private_key(input: &str) -> Result<SigningKey, String> {
+    let bytes =
+        hex::decode(strip_0x(input)).map_err(|_| "Invalid private key: not a hex string")?;
+    SigningKey::from_slice(&bytes).map_err(|_| "Invalid private key: expected 32 bytes".to_string())
+}
+
+// Parses a 20-byte hex address, with or without 0x.
+// Returns whether the EIP-55 checksum holds (only mixed-case input carries one).
+fn parse_address(input: &str) -> Result<(Address, bool), String> {
+    let hex_part = strip_0x(input);
+
+    if hex_part.len() != 40 {
+        return Err(format!(
+            "Invalid address '{}': expected 40 hex characters, got {}",
+            input,
+            hex_part.len()
+        ));
+    }
+    if !hex_part.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid address '{}': not a hex string", input));
+    }
+
+    let address = Address::from_str(hex_part).map_err(|e| e.to_string())?;
+
+    let has_lower = hex_part.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = hex_part.chars().any(|c| c.is_ascii_uppercase());
+    let checksum_ok = !(has_lower && has_upper) || address.to_checksum(None)[2..] == *hex_part;
+
+    Ok((address, checksum_ok))
 }
 
-// Helper to fetch nonce
-// Limitation: The same account can only send one tx per slot since nonce is fetched at DB
-async fn fetch_nonce(rpc_url: &str, address: Address) -> Result<u64, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/get_account", rpc_url);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_rlp::Decodable;
+    use axum::{
+        Json, Router,
+        http::StatusCode,
+        routing::{get, post},
+    };
+    use std::sync::{Arc, Mutex};
+
+    const HASH: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+    const PARENT: &str = "0x2222222222222222222222222222222222222222222222222222222222222222";
+
+    // Serves `app` on a random local port and returns its base url
+    async fn serve(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    // Serves `app` on a random local port and returns a client for it
+    async fn mock_node(app: Router) -> Client {
+        Client::new(serve(app).await)
+    }
+
+    // The only block of the mock nodes, on chain 1
+    fn block_json() -> Value {
+        json!({
+            "number": 7,
+            "hash": HASH,
+            "parent_hash": PARENT,
+            "timestamp": 1700000000,
+            "coinbase": "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "chain_id": 1,
+            "transactions": [PARENT]
+        })
+    }
+
+    // Serves a node that only knows the block HASH, also its head
+    async fn block_node() -> Client {
+        let app = Router::new()
+            .route(
+                "/get_block",
+                post(|Json(body): Json<Value>| async move {
+                    if body["hash"] == HASH {
+                        (StatusCode::OK, Json(block_json()))
+                    } else {
+                        (StatusCode::NOT_FOUND, Json(json!({ "error": "not found" })))
+                    }
+                }),
+            )
+            .route("/head", get(|| async { Json(block_json()) }));
+        mock_node(app).await
+    }
+
+    #[tokio::test]
+    async fn test_describe_block() {
+        let client = block_node().await;
+
+        let report = describe_block(&client, HASH.parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(report.json["block"]["number"], 7);
+        let out = report.text;
+
+        assert!(out.starts_with("Block #7\n"));
+        assert!(out.contains(&format!("Hash:         {}", HASH)));
+        assert!(out.contains(&format!("Parent:       {}", PARENT)));
+        assert!(out.contains("Timestamp:    1700000000"));
+        assert!(out.contains(&format!("Transactions: 1\n    {}", PARENT)));
+    }
+
+    #[tokio::test]
+    async fn test_describe_missing_block() {
+        let client = block_node().await;
+
+        let report = describe_block(&client, B256::ZERO).await.unwrap();
+
+        assert_eq!(report.text, format!("Block {} not found\n", B256::ZERO));
+        assert_eq!(report.json, json!({ "block": null }));
+    }
+
+    #[test]
+    fn test_parse_private_key_with_or_without_prefix() {
+        let bare = "0101010101010101010101010101010101010101010101010101010101010101";
 
-    let response = client
-        .post(&url)
-        .header("content-type", "application/json")
-        .json(&serde_json::json!({
-            "address": address.to_string()
-        }))
-        .send()
-        .await?;
+        let from_bare = parse_private_key(bare).unwrap();
+        let from_prefixed = parse_private_key(&format!("0x{}", bare)).unwrap();
 
-    let account_info: serde_json::Value = response.json().await?;
-    let nonce = account_info["nonce"].as_u64().unwrap_or(0);
+        assert_eq!(from_bare.to_bytes(), from_prefixed.to_bytes());
+        assert!(parse_private_key("0x0101").is_err());
+    }
+
+    #[test]
+    fn test_address_of_known_key() {
+        let key = "0x0000000000000000000000000000000000000000000000000000000000000001";
+        let expected = "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf";
+
+        let address = address_of(&parse_private_key(key).unwrap());
+        assert_eq!(address.to_checksum(None), expected);
+
+        // Same key read back from a file, trailing newline included
+        let dir =
+            std::env::temp_dir().join(format!("pethit-wallet-keystore-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("key");
+        fs::write(&path, format!("{}\n", key)).unwrap();
+        let from_file = parse_private_key(&read_keystore(&path).unwrap()).unwrap();
+        assert_eq!(address_of(&from_file).to_checksum(None), expected);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(Cli::try_parse_from(["pethit-wallet", "address"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_valid_address() {
+        let (address, checksum_ok) =
+            parse_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").unwrap();
+        assert!(checksum_ok);
+
+        // Same address, lowercase and without prefix
+        let (lowercase, checksum_ok) =
+            parse_address("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+        assert!(checksum_ok);
+        assert_eq!(address, lowercase);
+    }
+
+    #[test]
+    fn test_parse_too_short_address() {
+        let err = parse_address("0x5aAeb6053F3E94C9").unwrap_err();
+        assert!(err.contains("expected 40 hex characters, got 16"));
+    }
+
+    #[test]
+    fn test_parse_bad_checksum_address() {
+        // Last letter casing flipped
+        let (_, checksum_ok) = parse_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").unwrap();
+        assert!(!checksum_ok);
+    }
+
+    // Serves a node that always reports nonce 3 and keeps the raw transactions it's sent
+    async fn mock_tx_node(sent: Arc<Mutex<Vec<String>>>) -> Client {
+        let app = Router::new()
+            .route(
+                "/get_account",
+                post(|Json(body): Json<Value>| async move {
+                    Json(json!({ "address": body["address"], "nonce": 3, "balance": "1000000" }))
+                }),
+            )
+            .route(
+                "/send_tx",
+                post(move |Json(body): Json<Value>| async move {
+                    sent.lock()
+                        .unwrap()
+                        .push(body["raw_tx"].as_str().unwrap().to_string());
+                    Json(json!({ "tx_hash": HASH }))
+                }),
+            );
+        mock_node(app).await
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_is_checked_against_the_head() {
+        let client = block_node().await;
+
+        let error = check_chain_id(&client, Some(2)).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The node is on chain 1, expected chain 2"
+        );
+        assert!(check_chain_id(&client, Some(1)).await.is_ok());
+        // Unset: the node isn't even asked
+        assert!(
+            check_chain_id(&Client::new("http://127.0.0.1:1"), None)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sequential_sends_use_consecutive_nonces() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let client = mock_tx_node(sent.clone()).await;
+        let dir = std::env::temp_dir().join(format!("pethit-wallet-nonces-{}", std::process::id()));
+        let mut nonces = NonceTracker::load(&dir).unwrap();
+        let key = "0101010101010101010101010101010101010101010101010101010101010101";
+        let to = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+        for reset in [false, false, true] {
+            send_transaction(&client, &mut nonces, key, to, 1, 1, reset)
+                .await
+                .unwrap();
+        }
+        // The tracker survives a reload
+        let reloaded = NonceTracker::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The node still says 3: the second send relies on the tracker, the reset drops it
+        assert_eq!(sent_nonces(&sent), vec![3, 4, 3]);
+        assert_eq!(reloaded.next.values().collect::<Vec<_>>(), vec![&4]);
+    }
+
+    #[tokio::test]
+    async fn test_send_surfaces_node_error() {
+        let app = Router::new()
+            .route(
+                "/get_account",
+                post(|Json(body): Json<Value>| async move {
+                    Json(json!({ "address": body["address"], "nonce": 0, "balance": "0" }))
+                }),
+            )
+            .route(
+                "/send_tx",
+                post(|| async {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({ "error": "Insufficient funds" })),
+                    )
+                }),
+            );
+        let url = serve(app).await;
+        let dir = std::env::temp_dir().join(format!("pethit-wallet-error-{}", std::process::id()));
+
+        let cli = Cli::parse_from([
+            "pethit-wallet",
+            "send",
+            "--private-key",
+            "0101010101010101010101010101010101010101010101010101010101010101",
+            "--to",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "--value",
+            "1",
+            "--rpc",
+            &url,
+            "--keystore-dir",
+            dir.to_str().unwrap(),
+        ]);
+        let result = run(cli).await;
 
-    Ok(nonce)
+        let error = result.as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "node error (400): Insufficient funds");
+        assert_eq!(
+            error_json(error.as_ref()),
+            json!({ "error": "node error (400): Insufficient funds", "status": 400, "code": null })
+        );
+        assert_eq!(exit_code(result, OutputFormat::Text), ExitCode::FAILURE);
+        // A failed send doesn't move the local nonce
+        assert!(NonceTracker::load(&dir).unwrap().next.is_empty());
+    }
+
+    // Nonces of the raw transactions a mock node received
+    fn sent_nonces(sent: &Mutex<Vec<String>>) -> Vec<u64> {
+        sent.lock()
+            .unwrap()
+            .iter()
+            .map(|raw| {
+                let bytes = hex::decode(strip_0x(raw)).unwrap();
+                let tx = SignedTransaction::decode(&mut bytes.as_slice()).unwrap();
+                tx.transaction().nonce
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_batch_send() {
+        let key = "0101010101010101010101010101010101010101010101010101010101010101";
+        let csv = "to,value\n\
+                   0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed,10\n\
+                   0x000000000000000000000000000000000000dEaD,20\n\
+                   \n\
+                   0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed,30\n";
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let client = mock_tx_node(sent.clone()).await;
+        let dir = std::env::temp_dir().join(format!("pethit-wallet-batch-{}", std::process::id()));
+        let mut nonces = NonceTracker::load(&dir).unwrap();
+
+        let rows = batch_send(&client, &mut nonces, key, csv, 1, false).await;
+        fs::remove_dir_all(&dir).unwrap();
+
+        let lines: Vec<_> = rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+        assert!(rows.iter().all(|row| row.result.is_ok()));
+        // The node says nonce 3 throughout
+        assert_eq!(sent_nonces(&sent), vec![3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_send_stops_or_continues_on_error() {
+        let key = "0101010101010101010101010101010101010101010101010101010101010101";
+        let csv = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed,10\n\
+                   0x5aAeb6053F3E94C9,20\n\
+                   0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed,30\n";
+
+        for continue_on_error in [false, true] {
+            let sent = Arc::new(Mutex::new(Vec::new()));
+            let client = mock_tx_node(sent.clone()).await;
+            let dir = std::env::temp_dir().join(format!(
+                "pethit-wallet-batch-{}-{}",
+                continue_on_error,
+                std::process::id()
+            ));
+            let mut nonces = NonceTracker::load(&dir).unwrap();
+
+            let rows = batch_send(&client, &mut nonces, key, csv, 1, continue_on_error).await;
+            let _ = fs::remove_dir_all(&dir);
+
+            assert!(rows[0].result.is_ok());
+            let error = rows[1].result.as_ref().unwrap_err();
+            assert!(error.contains("expected 40 hex characters"));
+            if continue_on_error {
+                assert_eq!(rows.len(), 3);
+                assert!(rows[2].result.is_ok());
+                // The failed row didn't use up a nonce
+                assert_eq!(sent_nonces(&sent), vec![3, 4]);
+            } else {
+                assert_eq!(rows.len(), 2);
+                assert_eq!(sent_nonces(&sent), vec![3]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_json_output() {
+        let cli = Cli::parse_from(["pethit-wallet", "generate", "--output", "json"]);
+        assert_eq!(cli.output, OutputFormat::Json);
+
+        let rendered = generate_wallet().render(cli.output);
+        let json: Value = serde_json::from_str(&rendered).unwrap();
+
+        let private_key = parse_private_key(json["private_key"].as_str().unwrap()).unwrap();
+        let address = address_of(&private_key).to_checksum(None);
+        assert_eq!(json["address"], address);
+        // Text stays the default
+        let cli = Cli::parse_from(["pethit-wallet", "generate"]);
+        assert!(
+            generate_wallet()
+                .render(cli.output)
+                .starts_with("New Wallet Generated:\n")
+        );
+    }
+
+    #[test]
+    fn test_config_defaults_and_flags_override_them() {
+        let config = Config::parse(
+            "# node on the LAN\n\
+             rpc = \"http://10.0.0.2:3000\"  # not the local one\n\
+             chain_id = 7\n\
+             \n\
+             keystore_dir = '/tmp/keys'\n",
+        )
+        .unwrap();
+        let block = ["pethit-wallet", "block", "--hash", "0x01"];
+
+        let Commands::Block { rpc, .. } = Cli::parse_from(block).command else {
+            unreachable!()
+        };
+        assert_eq!(config.rpc(rpc), "http://10.0.0.2:3000");
+        let with_flag = [&block[..], &["--rpc", "http://127.0.0.1:4000"]].concat();
+        let Commands::Block { rpc, .. } = Cli::parse_from(with_flag).command else {
+            unreachable!()
+        };
+        assert_eq!(config.rpc(rpc), "http://127.0.0.1:4000");
+        assert_eq!(config.keystore_dir(None), PathBuf::from("/tmp/keys"));
+        assert_eq!(config.chain_id(None), Some(7));
+        assert_eq!(config.chain_id(Some(8)), Some(8));
+
+        // Nothing set: the built-in defaults, and no chain check
+        assert_eq!(Config::default().rpc(None), DEFAULT_RPC);
+        assert_eq!(Config::default().chain_id(None), None);
+        assert_eq!(
+            Config::default().keystore_dir(None),
+            PathBuf::from(DEFAULT_KEYSTORE_DIR)
+        );
+    }
+
+    #[test]
+    fn test_config_file_errors() {
+        // A `#` inside a string isn't a comment
+        let config = Config::parse("rpc = \"http://x/#a\\\"b\" # the node").unwrap();
+        assert_eq!(config.rpc.as_deref(), Some("http://x/#a\"b"));
+
+        assert!(Config::parse("rpc = http://x").is_err());
+        assert!(
+            Config::parse("\nchain = 1")
+                .unwrap_err()
+                .contains("unknown field `chain`")
+        );
+        assert!(Config::parse("chain_id = \"one\"").is_err());
+        assert!(Config::parse("[node]\nrpc = \"http://x\"").is_err());
+        // An explicit --config must exist
+        let missing = std::env::temp_dir().join(format!("pethit-no-config-{}", std::process::id()));
+        assert!(
+            Config::load(Some(&missing))
+                .unwrap_err()
+                .starts_with("Cannot read")
+        );
+    }
 }